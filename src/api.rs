@@ -1,31 +1,538 @@
 use anyhow::{anyhow, Result};
-use log::{debug, info, warn};
+use log::info;
+use regex::Regex;
 use reqwest::{
-    header::{HeaderMap, HeaderValue, ACCEPT},
-    Client, StatusCode,
+    header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE, LOCATION},
+    redirect::Policy,
+    Client, StatusCode, Url,
 };
 
-use crate::model::{Channel, Rss};
+use crate::{
+    atom::parse_atom,
+    model::{Channel, Rss},
+};
 use serde::Deserialize;
-///Get the contents of an rss feed
-pub async fn fetch_rss_feed(url: &str) -> Result<Option<Channel>> {
-    let client = Client::new();
+use std::{
+    fmt::{self, Display},
+    sync::OnceLock,
+};
+
+///Most redirects we follow before giving up on a url
+const MAX_REDIRECTS: usize = 10;
+///Where sites tend to keep their feed when the page does not say
+const WELL_KNOWN_FEEDS: [&str; 3] = ["/feed", "/rss.xml", "/atom.xml"];
+
+///A feed a web page pointed us at
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedCandidate {
+    pub url: String,
+    ///What the page called it, if anything
+    pub title: Option<String>,
+}
+
+impl FeedCandidate {
+    pub fn label(&self) -> String {
+        match &self.title {
+            Some(title) => format!("{title}  {}", self.url),
+            None => self.url.clone(),
+        }
+    }
+}
+
+///What we found at a url someone wants to follow
+#[derive(Debug)]
+pub enum Discovered {
+    Feed(Channel),
+    ///A page with more than one feed, for them to choose from
+    Choices(Vec<FeedCandidate>),
+}
+
+///A feed answering with something other than 200, kept as is so we can tell when it is gone
+#[derive(Debug)]
+pub struct StatusError(pub StatusCode);
+
+impl Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+///What we got back from a url
+struct Page {
+    text: String,
+    is_html: bool,
+    ///Where the url has permanently moved to, if it told us so
+    moved_to: Option<String>,
+}
+
+///Fetch the url, following redirects ourselves so we know which of them were permanent
+async fn fetch_page(url: &str) -> Result<Page> {
+    let client = Client::builder().redirect(Policy::none()).build()?;
     let mut header_map = HeaderMap::new();
     header_map.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/png,image/svg+xml,*/*;q=0.8"));
-    let result = client.get(url).headers(header_map).send().await?;
+    let mut current = Url::parse(url)?;
+    let mut visited = vec![current.clone()];
+    let mut moved_to = None;
+    //a temporary redirect along the way means what follows is not for keeping
+    let mut all_permanent = true;
+    let result = loop {
+        let result = client
+            .get(current.clone())
+            .headers(header_map.clone())
+            .send()
+            .await?;
+        let status = result.status();
+        let permanent = match status {
+            StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT => true,
+            StatusCode::FOUND | StatusCode::SEE_OTHER | StatusCode::TEMPORARY_REDIRECT => false,
+            _ => break result,
+        };
+        let location = result
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or(anyhow!(
+                "{current} redirected with {status} but no location"
+            ))?;
+        let next = current.join(location)?;
+        if visited.contains(&next) {
+            return Err(anyhow!("{url} redirects in a loop"));
+        }
+        if visited.len() > MAX_REDIRECTS {
+            return Err(anyhow!("{url} redirects more than {MAX_REDIRECTS} times"));
+        }
+        info!("{current} redirects with {status} to {next}");
+        all_permanent &= permanent;
+        if all_permanent {
+            moved_to = Some(next.to_string());
+        }
+        visited.push(next.clone());
+        current = next;
+    };
 
     //let result = reqwest::get(url).await?;
     let response_status = result.status();
     if response_status != StatusCode::OK {
-        return Err(anyhow!(response_status.to_string()));
+        return Err(StatusError(response_status).into());
     }
     info!("We got back this status: {}", response_status.to_string());
-    let txt = result.text().await?;
-    info!("Text returned from the url: {}", txt);
+    let html_type = result
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.contains("html"));
+    let text = result.text().await?;
+    info!("Text returned from the url: {}", text);
+    let start = text
+        .trim_start()
+        .get(..15)
+        .unwrap_or_default()
+        .to_lowercase();
+    let is_html = html_type || start.starts_with("<!doctype html") || start.starts_with("<html");
+    Ok(Page {
+        text,
+        is_html,
+        moved_to,
+    })
+}
+
+///Get the contents of an rss or atom feed
+///The channel's link is where we got it from, or where it has permanently moved to
+pub async fn fetch_rss_feed(url: &str) -> Result<Option<Channel>> {
+    let page = fetch_page(url).await?;
+    match parse_feed(&page.text) {
+        Ok(mut channel) => {
+            //remember where we got it from, the feed's own links point all over the place
+            channel.set_link(page.moved_to.as_deref().unwrap_or(url));
+            Ok(Some(channel))
+        }
+        Err(_) if page.is_html => Err(anyhow!("{url} is a web page, not a feed")),
+        Err(e) => Err(anyhow!("Could not read the feed at {url}: {e}")),
+    }
+}
+
+///Fetch the url as a feed, or when it is a web page, find the feeds it links to.
+///A page with only the one feed gets that feed fetched
+pub async fn discover_feed(url: &str) -> Result<Discovered> {
+    let page = fetch_page(url).await?;
+    let url = page.moved_to.as_deref().unwrap_or(url);
+    if let Ok(mut channel) = parse_feed(&page.text) {
+        channel.set_link(url);
+        return Ok(Discovered::Feed(channel));
+    }
+    if !page.is_html {
+        return Err(anyhow!("{url} is neither a feed nor a web page"));
+    }
+    let mut candidates = feed_links(url, &page.text);
+    if candidates.is_empty() {
+        candidates = probe_well_known(url).await;
+    }
+    match candidates.len() {
+        0 => Err(anyhow!("No feed found at {url}")),
+        1 => fetch_rss_feed(&candidates[0].url)
+            .await?
+            .map(Discovered::Feed)
+            .ok_or(anyhow!("No channel found at {}", candidates[0].url)),
+        _ => Ok(Discovered::Choices(candidates)),
+    }
+}
+
+///The feeds a page announces with `<link rel="alternate">`, rss before atom
+pub fn feed_links(page_url: &str, html: &str) -> Vec<FeedCandidate> {
+    static LINK_TAG_REGEX: OnceLock<Regex> = OnceLock::new();
+    static ATTRIBUTE_REGEX: OnceLock<Regex> = OnceLock::new();
+    let link_tag = LINK_TAG_REGEX
+        .get_or_init(|| Regex::new(r"(?is)<link\b[^>]*>").expect("Link regex should compile"));
+    let attribute = ATTRIBUTE_REGEX.get_or_init(|| {
+        Regex::new(r#"(?is)([a-z][a-z-]*)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+            .expect("Attribute regex should compile")
+    });
+    let Ok(base) = Url::parse(page_url) else {
+        return Vec::new();
+    };
+    let mut rss = Vec::new();
+    let mut atom = Vec::new();
+    for tag in link_tag.find_iter(html) {
+        let attribute = |name: &str| {
+            attribute.captures_iter(tag.as_str()).find_map(|captures| {
+                if !captures[1].eq_ignore_ascii_case(name) {
+                    return None;
+                }
+                let value = captures
+                    .get(2)
+                    .or(captures.get(3))
+                    .or(captures.get(4))?
+                    .as_str();
+                Some(html_escape::decode_html_entities(value).trim().to_string())
+            })
+        };
+        let is_alternate = attribute("rel").is_some_and(|rel| {
+            rel.split_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+        });
+        let feed_type = attribute("type").unwrap_or_default().to_lowercase();
+        let Some(href) = attribute("href") else {
+            continue;
+        };
+        let Ok(url) = base.join(&href) else {
+            continue;
+        };
+        let candidate = FeedCandidate {
+            url: url.to_string(),
+            title: attribute("title").filter(|title| !title.is_empty()),
+        };
+        let list = match feed_type.as_str() {
+            "application/rss+xml" if is_alternate => &mut rss,
+            "application/atom+xml" if is_alternate => &mut atom,
+            _ => continue,
+        };
+        if !list.contains(&candidate) {
+            list.push(candidate);
+        }
+    }
+    rss.extend(atom);
+    rss
+}
+
+///Try the usual feed paths on the page's site, keeping those that answer with a feed
+async fn probe_well_known(page_url: &str) -> Vec<FeedCandidate> {
+    let Ok(base) = Url::parse(page_url) else {
+        return Vec::new();
+    };
+    let mut found = Vec::new();
+    for path in WELL_KNOWN_FEEDS {
+        let Ok(url) = base.join(path) else {
+            continue;
+        };
+        let Ok(page) = fetch_page(url.as_str()).await else {
+            continue;
+        };
+        if parse_feed(&page.text).is_ok() {
+            found.push(FeedCandidate {
+                url: page.moved_to.unwrap_or(url.to_string()),
+                title: None,
+            });
+        }
+    }
+    found
+}
+
+///Get whatever text is at the url, for the things feeds link to like chapters and transcripts
+pub async fn fetch_text(url: &str) -> Result<String> {
+    let result = reqwest::get(url).await?;
+    let response_status = result.status();
+    if !response_status.is_success() {
+        return Err(anyhow!(response_status.to_string()));
+    }
+    Ok(result.text().await?)
+}
+
+///Turn the xml of an rss or atom feed into our model
+pub fn parse_feed(txt: &str) -> Result<Channel> {
+    if let Some(channel) = parse_atom(txt)? {
+        return Ok(channel);
+    }
+    Ok(parse_rss(txt)?.channel)
+}
+
+///Turn the xml of an rss feed into our model
+pub fn parse_rss(txt: &str) -> Result<Rss, serde_xml_rs::Error> {
     let mut de = serde_xml_rs::Deserializer::new_from_reader(txt.as_bytes())
         .non_contiguous_seq_elements(true);
-    match Rss::deserialize(&mut de) {
-        Ok(rss) => Ok(Some(rss.channel)),
-        Err(e) => panic!("Failed to deserialize! {}", e),
+    Rss::deserialize(&mut de)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEST_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:content="http://purl.org/rss/1.0/modules/content/" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:slash="http://purl.org/rss/1.0/modules/slash/" xmlns:podcast="https://podcastindex.org/namespace/1.0">
+<channel>
+    <atom:link href="https://feeds.buzzsprout.com/2042709.rss" rel="self" type="application/rss+xml" />
+    <title>Between Two Cairns</title>
+    <link>https://www.buzzsprout.com/2042709</link>
+    <description>An OSR podcast</description>
+    <item>
+        <title>Episode 42: Goblin Markets</title>
+        <itunes:title>Goblin Markets</itunes:title>
+        <dc:creator>Yochai Gal</dc:creator>
+        <itunes:author>Between Two Cairns</itunes:author>
+        <category domain="https://example.com/tags">osr</category>
+        <category>reviews</category>
+        <itunes:duration>1:02:03</itunes:duration>
+        <enclosure url="https://www.buzzsprout.com/2042709/42.mp3" length="52300000" type="audio/mpeg" />
+        <pubDate>Thu, 30 May 2024 14:30:13 -0400</pubDate>
+        <description><![CDATA[<p>We visit the goblin market</p>]]></description>
+        <content:encoded><![CDATA[<p>We visit the goblin market, and buy a lot of things</p>]]></content:encoded>
+        <guid isPermaLink="false">Buzzsprout-15123456</guid>
+        <comments>https://www.buzzsprout.com/2042709/42#comments</comments>
+        <slash:comments>4</slash:comments>
+        <itunes:episode>42</itunes:episode>
+        <itunes:season>2</itunes:season>
+        <podcast:season>2</podcast:season>
+        <itunes:explicit>false</itunes:explicit>
+        <itunes:image href="https://www.buzzsprout.com/2042709/42.jpg" />
+        <podcast:chapters url="https://www.buzzsprout.com/2042709/42/chapters.json" type="application/json+chapters" />
+        <podcast:transcript url="https://www.buzzsprout.com/2042709/42/transcript.srt" type="application/srt" />
+        <podcast:transcript url="https://www.buzzsprout.com/2042709/42/transcript.html" type="text/html" />
+        <podcast:person role="host" href="https://example.com/yochai">Yochai Gal</podcast:person>
+        <podcast:person role="guest">Ben Milton</podcast:person>
+    </item>
+    <item>
+        <title>Episode 41</title>
+        <link>https://www.buzzsprout.com/2042709/41</link>
+    </item>
+</channel>
+</rss>"#;
+
+    #[test]
+    pub fn test_parse_rss() {
+        let channel = parse_rss(TEST_FEED).unwrap().channel;
+        assert_eq!("Between Two Cairns", channel.title);
+        assert_eq!(2, channel.items.len());
+        let item = &channel.items[0];
+        assert_eq!("Episode 42: Goblin Markets", item.get_title());
+        assert_eq!(Some("Yochai Gal".to_string()), item.get_author());
+        assert_eq!(vec!["osr", "reviews"], item.get_categories());
+        assert_eq!(Some(3723), item.duration_secs());
+        assert_eq!(
+            Some(52_300_000),
+            item.enclosure.as_ref().and_then(|e| e.size_bytes())
+        );
+        assert!(item.published().is_some());
+        assert_eq!("Buzzsprout-15123456", item.id());
+        assert_eq!(None, item.get_link());
+        assert_eq!(
+            Some("https://www.buzzsprout.com/2042709/42#comments".to_string()),
+            item.get_comments_link()
+        );
+        assert!(item.get_content().unwrap().contains("buy a lot of things"));
+
+        assert_eq!(Some("S2E42".to_string()), item.episode_label());
+        assert!(!item.is_explicit());
+        assert_eq!(
+            Some("https://www.buzzsprout.com/2042709/42.jpg".to_string()),
+            item.get_image()
+        );
+        assert_eq!(
+            "https://www.buzzsprout.com/2042709/42/chapters.json",
+            item.get_chapters().unwrap().url
+        );
+        assert_eq!(2, item.transcript.as_ref().unwrap().len());
+        assert_eq!(
+            vec!["Yochai Gal (host)", "Ben Milton (guest)"],
+            item.get_people()
+        );
+
+        let other_item = &channel.items[1];
+        assert_eq!(None, other_item.get_author());
+        assert_eq!("https://www.buzzsprout.com/2042709/41", other_item.id());
+        assert_eq!(None, other_item.episode_label());
+    }
+
+    #[test]
+    pub fn test_parse_repeated_tags() {
+        //a repeated element must not cost us the whole feed
+        let feed = r#"<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/"
+    xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:googleplay="http://www.google.com/schemas/play-podcasts/1.0"
+    xmlns:podcast="https://podcastindex.org/namespace/1.0">
+<channel><title>Repeats</title><link>https://testing.test</link><description>d</description>
+<item>
+<title>Twice over</title>
+<guid isPermaLink="false">guid-1</guid>
+<content:encoded><![CDATA[<p>The whole article</p>]]></content:encoded>
+<category>osr</category>
+<guid isPermaLink="false">guid-2</guid>
+<content:encoded><![CDATA[<p>Again</p>]]></content:encoded>
+<itunes:explicit>yes</itunes:explicit>
+<googleplay:explicit>no</googleplay:explicit>
+<itunes:duration>1:02:03</itunes:duration>
+<podcast:chapters url="https://testing.test/chapters.json" type="application/json+chapters"/>
+<itunes:duration></itunes:duration>
+<podcast:chapters url="https://testing.test/chapters.psc" type="application/psc+xml"/>
+</item>
+</channel></rss>"#;
+        let channel = parse_rss(feed).unwrap().channel;
+        let item = &channel.items[0];
+        assert_eq!("guid-1", item.id());
+        assert_eq!(
+            Some("<p>The whole article</p>".to_string()),
+            item.get_content()
+        );
+        assert!(item.is_explicit());
+        assert_eq!(Some(3723), item.duration_secs());
+        assert_eq!(
+            "https://testing.test/chapters.json",
+            item.get_chapters().unwrap().url
+        );
+    }
+
+    #[test]
+    pub fn test_feed_links() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="/style.css">
+            <link rel="alternate" type="application/atom+xml" title="Atom" href="/atom.xml" />
+            <LINK REL='alternate' TYPE='application/rss+xml' TITLE='Posts &amp; news' HREF='feed/?format=rss&amp;all=1'>
+            <link rel="alternate" type="application/rss+xml" href="https://feeds.example.org/comments.rss">
+            <link rel="alternate" hreflang="fr" href="/fr/">
+        </head></html>"#;
+        let found = feed_links("https://blog.example.org/posts/", html);
+        assert_eq!(
+            vec![
+                FeedCandidate {
+                    url: "https://blog.example.org/posts/feed/?format=rss&all=1".to_string(),
+                    title: Some("Posts & news".to_string()),
+                },
+                FeedCandidate {
+                    url: "https://feeds.example.org/comments.rss".to_string(),
+                    title: None,
+                },
+                FeedCandidate {
+                    url: "https://blog.example.org/atom.xml".to_string(),
+                    title: Some("Atom".to_string()),
+                },
+            ],
+            found
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_discover_feed() {
+        let (addr, _) = crate::testing::mock_server(|request| {
+            let target = request.split_whitespace().nth(1).unwrap_or_default();
+            match target {
+                "/rss.xml" => TEST_FEED.to_string(),
+                "/choices" => r#"<!DOCTYPE html><html><head>
+                    <link rel="alternate" type="application/rss+xml" href="/rss.xml">
+                    <link rel="alternate" type="application/atom+xml" href="/atom.xml">
+                    </head></html>"#
+                    .to_string(),
+                "/notes" => r#"<!DOCTYPE html><html><head>
+                    <link rel="alternate" type="application/atom+xml" title="Notes" href="/notes.atom">
+                    </head></html>"#
+                    .to_string(),
+                "/notes.atom" => r#"<?xml version="1.0" encoding="utf-8"?>
+                    <feed xmlns="http://www.w3.org/2005/Atom">
+                    <title>Notes</title>
+                    <entry><title>A note</title><id>urn:note:1</id></entry>
+                    </feed>"#
+                    .to_string(),
+                _ => "<!DOCTYPE html><html><body>Nothing here</body></html>".to_string(),
+            }
+        })
+        .await;
+
+        //no links on the page, but the site has a feed where they usually are
+        let Discovered::Feed(channel) = discover_feed(&format!("http://{addr}/about"))
+            .await
+            .unwrap()
+        else {
+            panic!("Expected the feed");
+        };
+        assert_eq!("Between Two Cairns", channel.title);
+        assert_eq!(format!("http://{addr}/rss.xml"), channel.get_link());
+
+        let Discovered::Choices(choices) = discover_feed(&format!("http://{addr}/choices"))
+            .await
+            .unwrap()
+        else {
+            panic!("Expected a choice of feeds");
+        };
+        assert_eq!(2, choices.len());
+
+        //a page with only an atom feed gets that feed
+        let Discovered::Feed(channel) = discover_feed(&format!("http://{addr}/notes"))
+            .await
+            .unwrap()
+        else {
+            panic!("Expected the atom feed");
+        };
+        assert_eq!("Notes", channel.title);
+        assert_eq!(format!("http://{addr}/notes.atom"), channel.get_link());
+        assert_eq!("A note", channel.items[0].get_title());
+
+        let not_a_feed = fetch_rss_feed(&format!("http://{addr}/about")).await;
+        assert!(not_a_feed.unwrap_err().to_string().contains("web page"));
+    }
+
+    fn redirect(status: &str, location: &str) -> String {
+        format!("HTTP/1.1 {status}\r\nLocation: {location}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+    }
+
+    #[tokio::test]
+    pub async fn test_redirects() {
+        let (addr, _) = crate::testing::mock_server(|request| {
+            let target = request.split_whitespace().nth(1).unwrap_or_default();
+            match target {
+                "/old" => redirect("301 Moved Permanently", "/middle"),
+                "/middle" => redirect("302 Found", "/rss.xml"),
+                "/elsewhere" => redirect("307 Temporary Redirect", "/old"),
+                "/loop-a" => redirect("301 Moved Permanently", "/loop-b"),
+                "/loop-b" => redirect("308 Permanent Redirect", "/loop-a"),
+                _ => TEST_FEED.to_string(),
+            }
+        })
+        .await;
+
+        //moved for good to /middle, which only points at the feed for now
+        let channel = fetch_rss_feed(&format!("http://{addr}/old"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("Between Two Cairns", channel.title);
+        assert_eq!(format!("http://{addr}/middle"), channel.get_link());
+
+        //a permanent move after a temporary one is not ours to keep
+        let channel = fetch_rss_feed(&format!("http://{addr}/elsewhere"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(format!("http://{addr}/elsewhere"), channel.get_link());
+
+        let looped = fetch_rss_feed(&format!("http://{addr}/loop-a")).await;
+        assert!(looped.unwrap_err().to_string().contains("loop"));
     }
 }
@@ -0,0 +1,249 @@
+use anyhow::{anyhow, Result};
+use html_escape::decode_html_entities;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use crate::model::{Channel, Enclosure, Guid, Item};
+
+///An attribute of the tag, unescaped
+fn attribute(tag: &BytesStart, name: &str) -> Option<String> {
+    tag.attributes()
+        .flatten()
+        .find(|attribute| attribute.key.local_name().as_ref() == name)
+        .map(|attribute| decode_html_entities(&attribute.value).to_string())
+}
+
+fn local_name(tag: &BytesStart) -> String {
+    tag.local_name().as_ref().to_string()
+}
+
+///The text of an element: cdata as it is, the rest unescaped.
+///Xhtml content is markup already, so it is kept as it is
+fn element_text(reader: &mut Reader<&[u8]>, tag: &BytesStart) -> Result<String> {
+    let raw = reader.read_text(tag.name())?;
+    if attribute(tag, "type").as_deref() == Some("xhtml") {
+        return Ok(raw.trim().to_string());
+    }
+    let mut text = String::new();
+    let mut rest: &str = &raw;
+    while let Some(start) = rest.find("<![CDATA[") {
+        text.push_str(&decode_html_entities(&rest[..start]));
+        let cdata = &rest[start + "<![CDATA[".len()..];
+        let end = cdata.find("]]>").unwrap_or(cdata.len());
+        text.push_str(&cdata[..end]);
+        rest = cdata.get(end + "]]>".len()..).unwrap_or_default();
+    }
+    text.push_str(&decode_html_entities(rest));
+    Ok(text.trim().to_string())
+}
+
+///The names of an author or contributor
+fn person_name(reader: &mut Reader<&[u8]>) -> Result<Option<String>> {
+    let mut name = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(tag) if local_name(&tag) == "name" => {
+                name = Some(element_text(reader, &tag)?).filter(|name| !name.is_empty());
+            }
+            Event::Start(tag) => {
+                reader.read_to_end(tag.name())?;
+            }
+            Event::End(_) => return Ok(name),
+            Event::Eof => return Err(anyhow!("The feed ends inside an author")),
+            _ => {}
+        }
+    }
+}
+
+///What an entry's link is for, the page itself unless it says otherwise
+fn entry_link(item: &mut Item, tag: &BytesStart) {
+    let Some(href) = attribute(tag, "href") else {
+        return;
+    };
+    match attribute(tag, "rel").as_deref().unwrap_or("alternate") {
+        "alternate" if item.link.is_none() => item.link = Some(href),
+        "enclosure" if item.enclosure.is_none() => {
+            item.enclosure = Some(Enclosure {
+                url: href,
+                length: attribute(tag, "length").unwrap_or("0".to_string()),
+                enclosure_type: attribute(tag, "type").unwrap_or_default(),
+            })
+        }
+        _ => {}
+    }
+}
+
+fn push(field: &mut Option<Vec<String>>, value: String) {
+    if !value.is_empty() {
+        field.get_or_insert_with(Vec::new).push(value);
+    }
+}
+
+fn entry(reader: &mut Reader<&[u8]>) -> Result<Item> {
+    let mut item = Item::default();
+    let mut updated = None;
+    loop {
+        match reader.read_event()? {
+            Event::Empty(tag) => match local_name(&tag).as_str() {
+                "link" => entry_link(&mut item, &tag),
+                "category" => push(
+                    &mut item.category,
+                    attribute(&tag, "term").unwrap_or_default(),
+                ),
+                _ => {}
+            },
+            Event::Start(tag) => match local_name(&tag).as_str() {
+                "title" => push(&mut item.title, element_text(reader, &tag)?),
+                "id" => {
+                    //atom ids are only names, not somewhere to go
+                    item.guid = Some(vec![Guid {
+                        is_perma_link: Some("false".to_string()),
+                        value: Some(element_text(reader, &tag)?),
+                    }])
+                }
+                "published" => item.pub_date = Some(element_text(reader, &tag)?),
+                "updated" => updated = Some(element_text(reader, &tag)?),
+                "summary" => item.description = Some(element_text(reader, &tag)?),
+                "content" => push(&mut item.encoded, element_text(reader, &tag)?),
+                "author" => {
+                    if let Some(name) = person_name(reader)? {
+                        push(&mut item.author, name);
+                    }
+                }
+                "link" => {
+                    entry_link(&mut item, &tag);
+                    reader.read_to_end(tag.name())?;
+                }
+                "category" => {
+                    push(
+                        &mut item.category,
+                        attribute(&tag, "term").unwrap_or_default(),
+                    );
+                    reader.read_to_end(tag.name())?;
+                }
+                _ => {
+                    reader.read_to_end(tag.name())?;
+                }
+            },
+            Event::End(_) => break,
+            Event::Eof => return Err(anyhow!("The feed ends inside an entry")),
+            _ => {}
+        }
+    }
+    //updated is all some feeds give
+    item.pub_date = item.pub_date.or(updated);
+    Ok(item)
+}
+
+///Read an Atom feed into the same channel and items as rss.
+///None when the xml is not an Atom feed at all
+pub fn parse_atom(txt: &str) -> Result<Option<Channel>> {
+    let mut reader = Reader::from_str(txt);
+    //anything that trips us up before the root is no feed of ours
+    let root = loop {
+        match reader.read_event() {
+            Ok(Event::Start(tag)) => break tag,
+            Ok(Event::Eof) | Ok(Event::Empty(_)) | Err(_) => return Ok(None),
+            Ok(_) => {}
+        }
+    };
+    if local_name(&root) != "feed" {
+        return Ok(None);
+    }
+
+    let mut channel = Channel::default();
+    loop {
+        match reader.read_event()? {
+            Event::Start(tag) => match local_name(&tag).as_str() {
+                "title" => channel.title = element_text(&mut reader, &tag)?,
+                "subtitle" => channel.description = element_text(&mut reader, &tag)?,
+                "updated" => channel.pub_date = Some(element_text(&mut reader, &tag)?),
+                "entry" => channel.items.push(entry(&mut reader)?),
+                _ => {
+                    reader.read_to_end(tag.name())?;
+                }
+            },
+            Event::End(_) | Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(Some(channel))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_atom() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title type="text">Notes &amp; Queries</title>
+  <subtitle>Things written down</subtitle>
+  <updated>2024-06-01T12:00:00Z</updated>
+  <link rel="self" href="https://blog.example.org/atom.xml"/>
+  <author><name>Alice</name></author>
+  <entry>
+    <title type="html">Older &lt;em&gt;post&lt;/em&gt;</title>
+    <id>tag:blog.example.org,2024:1</id>
+    <link href="https://blog.example.org/1"/>
+    <updated>2024-05-30T09:00:00+02:00</updated>
+    <summary><![CDATA[A <b>short</b> one]]></summary>
+  </entry>
+  <entry>
+    <title>Newer post</title>
+    <id>tag:blog.example.org,2024:2</id>
+    <link rel="alternate" type="text/html" href="https://blog.example.org/2"/>
+    <link rel="enclosure" type="audio/mpeg" length="1234" href="https://blog.example.org/2.mp3"/>
+    <published>2024-06-01T10:00:00Z</published>
+    <updated>2024-06-01T11:00:00Z</updated>
+    <author><name>Bob</name><email>bob@example.org</email></author>
+    <category term="podcasts"/>
+    <content type="xhtml"><div xmlns="http://www.w3.org/1999/xhtml"><p>The whole thing</p></div></content>
+  </entry>
+</feed>"#;
+        let channel = parse_atom(xml).unwrap().unwrap();
+        assert_eq!("Notes & Queries", channel.title);
+        assert_eq!("Things written down", channel.description);
+        assert_eq!(2, channel.items.len());
+
+        let newer = &channel.items[1];
+        assert_eq!("Newer post", newer.get_title());
+        assert_eq!("tag:blog.example.org,2024:2", newer.id());
+        assert_eq!(
+            Some("https://blog.example.org/2".to_string()),
+            newer.get_link()
+        );
+        assert_eq!(Some("2024-06-01T10:00:00Z".to_string()), newer.pub_date);
+        assert_eq!(Some(vec!["Bob".to_string()]), newer.author);
+        assert_eq!(vec!["podcasts".to_string()], newer.get_categories());
+        let enclosure = newer.enclosure.as_ref().unwrap();
+        assert_eq!("https://blog.example.org/2.mp3", enclosure.url);
+        assert_eq!(Some(1234), enclosure.size_bytes());
+        assert!(newer
+            .get_content()
+            .unwrap()
+            .contains("<p>The whole thing</p>"));
+
+        let older = &channel.items[0];
+        assert_eq!("Older <em>post</em>", older.get_title());
+        assert_eq!(Some("A <b>short</b> one".to_string()), older.description);
+        assert_eq!(
+            Some("2024-05-30T09:00:00+02:00".to_string()),
+            older.pub_date
+        );
+    }
+
+    #[test]
+    pub fn test_not_atom() {
+        assert!(parse_atom("<rss version=\"2.0\"><channel></channel></rss>")
+            .unwrap()
+            .is_none());
+        assert!(parse_atom("<!DOCTYPE html><html><head></head></html>")
+            .unwrap()
+            .is_none());
+        assert!(parse_atom("not xml at all").unwrap().is_none());
+    }
+}
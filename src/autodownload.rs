@@ -0,0 +1,306 @@
+use log::{error, info, warn};
+use regex::Regex;
+use std::{cmp::Reverse, path::PathBuf};
+
+use crate::{
+    dates::parse_date,
+    download::{DownloadManager, DownloadRequest},
+    model::{Channel, Item},
+    paths::{ChannelDownloadConfig, DownloadConfig},
+    state::ItemStates,
+};
+
+///Items with something to download
+fn episodes_newest_first(items: &[Item]) -> Vec<&Item> {
+    let mut episodes: Vec<&Item> = items
+        .iter()
+        .filter(|item| item.enclosure.is_some())
+        .collect();
+    episodes.sort_by_key(|item| Reverse(item.published()));
+    episodes
+}
+
+///The items of the channel the rules say to download, newest first.
+///Items without an enclosure are never picked
+pub fn pick_items<'i>(rules: &ChannelDownloadConfig, items: &'i [Item]) -> Vec<&'i Item> {
+    if !rules.auto_downloads() {
+        return Vec::new();
+    }
+    let since = match &rules.auto_since {
+        Some(since) => match parse_date(since) {
+            Some(since) => Some(since),
+            None => {
+                warn!("Could not make sense of auto_since date '{since}', not downloading");
+                return Vec::new();
+            }
+        },
+        None => None,
+    };
+    let title_regex = match &rules.auto_title_regex {
+        Some(title_regex) => match Regex::new(title_regex) {
+            Ok(title_regex) => Some(title_regex),
+            Err(e) => {
+                error!("Bad auto_title_regex '{title_regex}': {e}");
+                return Vec::new();
+            }
+        },
+        None => None,
+    };
+    episodes_newest_first(items)
+        .into_iter()
+        .filter(|item| match since {
+            Some(since) => item.published().is_some_and(|published| published > since),
+            None => true,
+        })
+        .filter(|item| match &title_regex {
+            Some(title_regex) => title_regex.is_match(&item.get_title()),
+            None => true,
+        })
+        .take(rules.auto_latest.unwrap_or(usize::MAX))
+        .collect()
+}
+
+///Downloaded files of the channel beyond the newest `keep_last`, which can go.
+///Only downloads we recorded count, anything else in the folder is not ours to delete
+pub fn files_to_prune(
+    download_config: &DownloadConfig,
+    channel: &Channel,
+    item_states: &ItemStates,
+) -> Vec<PathBuf> {
+    let Some(keep_last) = download_config
+        .channel_config(channel)
+        .and_then(|rules| rules.keep_last)
+    else {
+        return Vec::new();
+    };
+    episodes_newest_first(&channel.items)
+        .into_iter()
+        .filter_map(|item| item_states.downloaded_path(&item.id()))
+        .map(|path| path.to_path_buf())
+        .skip(keep_last)
+        .collect()
+}
+
+///Downloads the channel's rules want that we have not got, are not getting and have not pruned
+pub fn auto_download_requests(
+    download_config: &DownloadConfig,
+    channel: &Channel,
+    item_states: &ItemStates,
+    downloads: &DownloadManager,
+) -> Vec<DownloadRequest> {
+    let Some(rules) = download_config.channel_config(channel) else {
+        return Vec::new();
+    };
+    pick_items(rules, &channel.items)
+        .into_iter()
+        .filter_map(|item| download_config.download_request(channel, item))
+        .filter(|request| {
+            !request.path.exists()
+                && !downloads.has_item(&request.item_id)
+                && item_states.downloaded_path(&request.item_id).is_none()
+                && !item_states.is_pruned(&request.item_id)
+        })
+        .collect()
+}
+
+///Delete downloads the channels' retention rules no longer want kept.
+///Returns true if any item states changed, and need saving
+pub fn prune_downloads(
+    download_config: &DownloadConfig,
+    channels: &[Channel],
+    item_states: &mut ItemStates,
+) -> bool {
+    let mut pruned = Vec::new();
+    for channel in channels {
+        for path in files_to_prune(download_config, channel, item_states) {
+            info!("Pruning old download {}", path.display());
+            match std::fs::remove_file(&path) {
+                Ok(()) => pruned.push(path),
+                Err(e) => error!("Unable to prune {}: {e}", path.display()),
+            }
+        }
+    }
+    if pruned.is_empty() {
+        return false;
+    }
+    let pruned_ids: Vec<String> = item_states
+        .items
+        .iter()
+        .filter(|(_, state)| {
+            state
+                .downloaded
+                .as_ref()
+                .is_some_and(|path| pruned.contains(path))
+        })
+        .map(|(id, _)| id.clone())
+        .collect();
+    for id in pruned_ids {
+        let state = item_states.state_mut(&id);
+        state.downloaded = None;
+        state.pruned = true;
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::Enclosure;
+
+    fn episode(title: &str, pub_date: &str) -> Item {
+        Item {
+            title: Some(vec![title.to_string()]),
+            pub_date: Some(pub_date.to_string()),
+            enclosure: Some(Enclosure {
+                url: format!("https://example.com/{title}.mp3"),
+                length: "0".to_string(),
+                enclosure_type: "audio/mpeg".to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn titles(items: Vec<&Item>) -> Vec<String> {
+        items.iter().map(|item| item.get_title()).collect()
+    }
+
+    #[test]
+    pub fn test_pick_items() {
+        let items = vec![
+            episode("Old", "2024-01-01"),
+            episode("Bonus: goblins", "2024-03-01"),
+            episode("Newest", "2024-04-01"),
+            Item {
+                title: Some(vec!["Blog post".to_string()]),
+                pub_date: Some("2024-05-01".to_string()),
+                ..Default::default()
+            },
+        ];
+        let rules = |rules: ChannelDownloadConfig| titles(pick_items(&rules, &items));
+
+        assert!(rules(ChannelDownloadConfig::default()).is_empty());
+        assert_eq!(
+            vec!["Newest", "Bonus: goblins"],
+            rules(ChannelDownloadConfig {
+                auto_latest: Some(2),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            vec!["Newest"],
+            rules(ChannelDownloadConfig {
+                auto_since: Some("2024-03-15".to_string()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            vec!["Bonus: goblins"],
+            rules(ChannelDownloadConfig {
+                auto_title_regex: Some("^Bonus".to_string()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            vec!["Bonus: goblins"],
+            rules(ChannelDownloadConfig {
+                auto_latest: Some(1),
+                auto_title_regex: Some("^Bonus".to_string()),
+                ..Default::default()
+            }),
+            "The latest of the matching episodes, not the latest episode if it matches"
+        );
+        assert!(rules(ChannelDownloadConfig {
+            auto_title_regex: Some("(".to_string()),
+            ..Default::default()
+        })
+        .is_empty());
+    }
+
+    #[test]
+    pub fn test_files_to_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel = Channel {
+            title: "Pod".to_string(),
+            items: vec![
+                episode("Old", "2024-01-01"),
+                episode("Middle", "2024-02-01"),
+                episode("Newest", "2024-04-01"),
+            ],
+            ..Default::default()
+        };
+        let mut download_config = DownloadConfig {
+            root: dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let mut item_states = ItemStates::default();
+        for item in channel.items.iter() {
+            let path = download_config.path_for(&channel, item);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, b"episode").unwrap();
+            //the oldest one was put there by someone else
+            if item.get_title() != "Old" {
+                item_states.set_downloaded(&item.id(), Some(path));
+            }
+        }
+        assert!(files_to_prune(&download_config, &channel, &item_states).is_empty());
+
+        download_config.channels.insert(
+            "Pod".to_string(),
+            ChannelDownloadConfig {
+                keep_last: Some(1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            vec![download_config.path_for(&channel, &channel.items[1])],
+            files_to_prune(&download_config, &channel, &item_states)
+        );
+    }
+
+    #[test]
+    pub fn test_pruned_not_downloaded_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel = Channel {
+            title: "Pod".to_string(),
+            items: vec![
+                episode("Old", "2024-01-01"),
+                episode("Newest", "2024-04-01"),
+            ],
+            ..Default::default()
+        };
+        let mut download_config = DownloadConfig {
+            root: dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        download_config.channels.insert(
+            "Pod".to_string(),
+            ChannelDownloadConfig {
+                auto_latest: Some(2),
+                keep_last: Some(1),
+                ..Default::default()
+            },
+        );
+        let mut item_states = ItemStates::default();
+        for item in channel.items.iter() {
+            let path = download_config.path_for(&channel, item);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, b"episode").unwrap();
+            item_states.set_downloaded(&item.id(), Some(path));
+        }
+        let downloads = DownloadManager::default();
+        assert!(prune_downloads(
+            &download_config,
+            std::slice::from_ref(&channel),
+            &mut item_states
+        ));
+        assert!(item_states.is_pruned(&channel.items[0].id()));
+        assert!(
+            auto_download_requests(&download_config, &channel, &item_states, &downloads).is_empty()
+        );
+
+        //downloading it by hand brings it back under the retention rules
+        let path = download_config.path_for(&channel, &channel.items[0]);
+        item_states.set_downloaded(&channel.items[0].id(), Some(path));
+        assert!(!item_states.is_pruned(&channel.items[0].id()));
+    }
+}
@@ -0,0 +1,865 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use clap::Subcommand;
+use log::{error, info};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::net::TcpListener;
+
+use crate::{
+    api::{discover_feed, fetch_rss_feed, Discovered},
+    config::{load_config, save_config, RssConfig, StoreFiles},
+    daemon::{run_daemon, send, DaemonCommand},
+    digest::{digest_start, is_new, Digest, DigestChannel, DigestFormat, DigestState},
+    download::{DownloadManager, DownloadStatus},
+    export::export_html,
+    feed::{select_entries, write_feed, FeedFormat, FeedInfo},
+    health::Health,
+    history::History,
+    model::{Channel, Item},
+    output::{
+        print_records, ChannelRecord, DownloadRecord, ErrorRecord, FetchRecord, Format,
+        HealthRecord, ItemRecord, OutputArgs,
+    },
+    server::serve,
+    state::ItemStates,
+    sync::{fetch_from_server, sync_files},
+};
+
+///How often we look in on a download from the command line
+const DOWNLOAD_POLL: Duration = Duration::from_millis(200);
+
+///Things to do without the tui, for scripts and cron jobs
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    ///Fetch every channel in the config and store the new items, through the sync server if there is one
+    Fetch {
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    ///List the channels in the config
+    ListChannels {
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    ///List the stored items of a channel, newest first
+    ListItems {
+        ///Channel name or url
+        channel: String,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    ///Add a channel to the config
+    Add {
+        url: String,
+        ///Name to give the channel, defaults to the feed's title
+        #[arg(short, long)]
+        name: Option<String>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    ///Remove a channel from the config
+    Remove {
+        ///Channel name or url
+        name: String,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    ///Fetch every channel and print the items that are new since the last digest, by channel
+    Digest {
+        ///How far back to go, like 30m, 24h, 7d or 2w, or a date. Defaults to the last digest
+        #[arg(long)]
+        since: Option<String>,
+        #[arg(long, value_enum, default_value_t)]
+        format: DigestFormat,
+    },
+    ///Download the enclosure of an item
+    Download {
+        ///Item id, as shown by list-items
+        item: String,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    ///Print the stored items of several channels as one rss or atom feed
+    Feed {
+        ///Only the channels in this folder, from the folders table in the config
+        #[arg(long)]
+        folder: Option<String>,
+        ///Only the starred items
+        #[arg(long)]
+        starred: bool,
+        ///Title of the feed, defaults to the folder name
+        #[arg(long)]
+        title: Option<String>,
+        ///Where the feed will be published
+        #[arg(long)]
+        link: Option<String>,
+        ///How many items at most, newest first
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        #[arg(long, value_enum, default_value_t)]
+        format: FeedFormat,
+    },
+    ///Serve the channels, items and read state over http, for other tools and browsers
+    Serve {
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        ///Address to listen on, only this machine unless told otherwise
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        ///Secret to send as a bearer token to mark items read, without one the server is read only
+        #[arg(long)]
+        token: Option<String>,
+    },
+    ///Stay running in the background: refresh on a schedule, run downloads,
+    ///and take commands from rrss ctl and the tui over a unix socket
+    Daemon {
+        ///Defaults to .rrss.sock next to the config
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        ///Minutes between refreshes
+        #[arg(long, default_value_t = 30)]
+        refresh_minutes: u64,
+    },
+    ///Send a command to a running daemon
+    Ctl {
+        ///Defaults to .rrss.sock next to the config
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        #[command(subcommand)]
+        command: DaemonCommand,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    ///Sync channels, read and starred state with the Google Reader, Nextcloud News or Fever server in the config
+    Sync {
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    ///List the channels that are failing or gone, with their latest failures
+    Health {
+        ///Every channel, the healthy ones too
+        #[arg(long)]
+        all: bool,
+        ///Forget the failures of this channel, by name or url, so it is fetched again
+        #[arg(long)]
+        enable: Option<String>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    ///Write the stored items out as a static html site, with a river of news as index.html
+    ExportHtml {
+        ///Where to put the site, created if need be
+        dir: PathBuf,
+    },
+}
+
+impl Command {
+    fn format(&self) -> Format {
+        match self {
+            Command::Fetch { output }
+            | Command::ListChannels { output }
+            | Command::ListItems { output, .. }
+            | Command::Add { output, .. }
+            | Command::Remove { output, .. }
+            | Command::Download { output, .. }
+            | Command::Ctl { output, .. }
+            | Command::Sync { output }
+            | Command::Health { output, .. } => output.format,
+            //the digest is for people, not for jq
+            Command::Digest { .. }
+            | Command::Feed { .. }
+            | Command::Serve { .. }
+            | Command::Daemon { .. }
+            | Command::ExportHtml { .. } => Format::Tsv,
+        }
+    }
+}
+
+///Run the subcommand against the config file, or './.rrss.toml' if none is given.
+///When the output is json, failures are printed as an error record as well
+pub async fn run(command: Command, config_file: Option<String>) -> Result<()> {
+    let format = command.format();
+    let result = run_command(command, config_file).await;
+    if let Err(e) = &result {
+        if format != Format::Tsv {
+            print_records(
+                format,
+                &[ErrorRecord {
+                    error: e.to_string(),
+                }],
+            )?;
+        }
+    }
+    result
+}
+
+async fn run_command(command: Command, config_file: Option<String>) -> Result<()> {
+    let files = StoreFiles::for_config(config_file);
+    let cfg = load_config(files.config.clone())?.unwrap_or_default();
+    match command {
+        Command::Fetch { output } => fetch(&cfg, &files, output.format).await,
+        Command::ListChannels { output } => list_channels(&cfg, &files, output.format),
+        Command::ListItems { channel, output } => list_items(&cfg, &files, &channel, output.format),
+        Command::Add { url, name, output } => add(cfg, &files, &url, name, output.format).await,
+        Command::Remove { name, output } => remove(cfg, &files, &name, output.format),
+        Command::Download { item, output } => download(&cfg, &files, &item, output.format).await,
+        Command::Digest { since, format } => digest(&cfg, &files, since.as_deref(), format).await,
+        Command::Feed {
+            folder,
+            starred,
+            title,
+            link,
+            limit,
+            format,
+        } => {
+            let channels = match &folder {
+                Some(folder) => folder_channels(&cfg, folder)?,
+                None => channels(&cfg),
+            };
+            let title = title.or(folder).unwrap_or("rrss".to_string());
+            let info = FeedInfo {
+                description: format!("{title}, from rrss"),
+                title,
+                link,
+            };
+            feed(&channels, &files, &info, starred, limit, format)
+        }
+        Command::Serve { port, host, token } => {
+            let listener = TcpListener::bind((host.as_str(), port)).await?;
+            eprintln!("Serving on http://{}", listener.local_addr()?);
+            serve(listener, files, token).await
+        }
+        Command::Daemon {
+            socket,
+            refresh_minutes,
+        } => {
+            let socket = socket.unwrap_or(files.socket_path());
+            run_daemon(
+                &socket,
+                files,
+                Duration::from_secs(refresh_minutes.max(1) * 60),
+            )
+            .await
+        }
+        Command::Ctl {
+            socket,
+            command,
+            output,
+        } => {
+            let socket = socket.unwrap_or(files.socket_path());
+            ctl(&socket, &command, output.format).await
+        }
+        Command::Sync { output } => print_records(output.format, &[sync_files(&files).await?]),
+        Command::Health {
+            all,
+            enable,
+            output,
+        } => health(&cfg, &files, all, enable.as_deref(), output.format),
+        Command::ExportHtml { dir } => export(&cfg, &files, &dir),
+    }
+}
+
+///The config's channels, sorted by name so the output is stable
+pub fn channels(cfg: &RssConfig) -> Vec<Channel> {
+    let mut channels: Vec<Channel> = cfg
+        .channels
+        .iter()
+        .map(|(name, url)| {
+            let mut channel = Channel {
+                title: name.clone(),
+                ..Default::default()
+            };
+            channel.set_link(url);
+            channel
+        })
+        .collect();
+    channels.sort_by(|a, b| a.title.cmp(&b.title));
+    channels
+}
+
+///Find the channel by its name, its url, or failing that its name in any case
+pub fn find_channel(cfg: &RssConfig, name_or_url: &str) -> Result<Channel> {
+    let channels = channels(cfg);
+    channels
+        .iter()
+        .find(|channel| channel.title == name_or_url || channel.get_link() == name_or_url)
+        .or(channels
+            .iter()
+            .find(|channel| channel.title.eq_ignore_ascii_case(name_or_url)))
+        .cloned()
+        .ok_or(anyhow!("No channel called {name_or_url}"))
+}
+
+pub fn channel_record(
+    channel: &Channel,
+    history: &History,
+    item_states: &ItemStates,
+) -> ChannelRecord {
+    let items = history.items_for(&channel.get_link());
+    ChannelRecord {
+        name: channel.title.clone(),
+        url: channel.get_link(),
+        items: items.len(),
+        unread: items
+            .iter()
+            .filter(|item| !item_states.is_read(&item.id()))
+            .count(),
+    }
+}
+
+///What fetching a channel brought in
+pub struct Fetched {
+    pub channel: Channel,
+    ///Everything we now have for the channel, newest first
+    pub items: Vec<Item>,
+    ///Ids of the items we had not seen before
+    pub unseen: HashSet<String>,
+    pub error: Option<String>,
+    ///The url the channel had before it told us it moved for good
+    pub moved_from: Option<String>,
+    ///Left alone as it is gone or backing off, the error says which
+    pub skipped: bool,
+}
+
+///Fetch every channel in the config and fold the items into the history.
+///Channels that are gone or backing off after failures are left alone,
+///unless they were asked for by hand
+pub async fn fetch_channels(
+    cfg: &RssConfig,
+    history: &mut History,
+    health: &mut Health,
+    by_hand: bool,
+) -> Vec<Fetched> {
+    let mut fetched = Vec::new();
+    for mut channel in channels(cfg) {
+        let url = channel.get_link();
+        let known: HashSet<String> = history
+            .items_for(&url)
+            .iter()
+            .map(|item| item.id())
+            .collect();
+        let mut moved_from = None;
+        let now = Utc::now().fixed_offset();
+        let skip_reason = health.skip_reason(&url, now).filter(|_| !by_hand);
+        let skipped = skip_reason.is_some();
+        let result = match skip_reason {
+            Some(reason) => {
+                info!("Skipping {url}: {reason}");
+                Err(reason)
+            }
+            None => match fetch_rss_feed(&url).await {
+                Ok(Some(fetched)) => Ok(fetched),
+                Ok(None) => Err(anyhow!("No channel found")),
+                Err(e) => Err(e),
+            }
+            .map_err(|e| {
+                error!("Unable to fetch {url}: {e}");
+                health.record_failure(&url, &e, now);
+                e.to_string()
+            }),
+        };
+        let (items, error) = match result {
+            Ok(fetched) => {
+                let fetched_url = fetched.get_link();
+                if fetched_url != url {
+                    info!("{url} has moved to {fetched_url}");
+                    history.move_channel(&url, &fetched_url);
+                    health.move_feed(&url, &fetched_url);
+                    channel.set_link(&fetched_url);
+                    moved_from = Some(url);
+                }
+                health.record_success(&fetched_url, now);
+                (history.merge(&fetched), None)
+            }
+            Err(e) => (history.items_for(&channel.get_link()), Some(e)),
+        };
+        let unseen = items
+            .iter()
+            .map(|item| item.id())
+            .filter(|id| !known.contains(id))
+            .collect();
+        fetched.push(Fetched {
+            channel,
+            items,
+            unseen,
+            error,
+            moved_from,
+            skipped,
+        });
+    }
+    fetched
+}
+
+///Fetch the channels and save what came in. With a sync server in the config
+///the server does the fetching, otherwise we do.
+///Returns what came in, and what the user should hear about
+pub async fn refresh_channels(
+    cfg: &RssConfig,
+    files: &StoreFiles,
+    by_hand: bool,
+) -> Result<(Vec<Fetched>, Vec<String>)> {
+    if cfg.sync.is_some() {
+        let (fetched, record) = fetch_from_server(cfg, files).await?;
+        let notice = format!(
+            "Synced {} items with the server",
+            record.items_pulled + record.items_pushed
+        );
+        return Ok((fetched, vec![notice]));
+    }
+    let mut history = History::load(files.history.clone())?;
+    let mut health = Health::load(files.health.clone())?;
+    let fetched = fetch_channels(cfg, &mut history, &mut health, by_hand).await;
+    history.save_changes(files.history.clone())?;
+    health.save_changes(files.health.clone())?;
+    let notices = save_moved_channels(files.config.clone(), &fetched)?;
+    Ok((fetched, notices))
+}
+
+///Point the config at the new url of every channel that has moved for good,
+///and say which those were
+pub fn save_moved_channels(
+    config_file: Option<String>,
+    fetched: &[Fetched],
+) -> Result<Vec<String>> {
+    let moved: Vec<(&str, String)> = fetched
+        .iter()
+        .filter_map(|fetched| Some((fetched.moved_from.as_deref()?, fetched.channel.get_link())))
+        .collect();
+    if moved.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut cfg = load_config(config_file.clone())?.unwrap_or_default();
+    let mut notices = Vec::new();
+    for (old_url, new_url) in moved {
+        for (name, url) in cfg.channels.iter_mut() {
+            if url == old_url {
+                *url = new_url.clone();
+                notices.push(format!("{name} has moved to {new_url}"));
+            }
+        }
+        //folders can hold urls as well as names
+        for member in cfg.folders.values_mut().flatten() {
+            if member == old_url {
+                *member = new_url.clone();
+            }
+        }
+    }
+    save_config(config_file, cfg)?;
+    Ok(notices)
+}
+
+async fn fetch(cfg: &RssConfig, files: &StoreFiles, format: Format) -> Result<()> {
+    let (fetched, notices) = refresh_channels(cfg, files, false).await?;
+    for notice in notices {
+        eprintln!("{notice}");
+    }
+    //leaving a dead channel alone is not a failure of this fetch
+    let failed = fetched
+        .iter()
+        .filter(|fetched| fetched.error.is_some() && !fetched.skipped)
+        .count();
+    let records: Vec<FetchRecord> = fetched
+        .into_iter()
+        .map(|fetched| FetchRecord {
+            name: fetched.channel.title.clone(),
+            url: fetched.channel.get_link(),
+            new_items: fetched.unseen.len(),
+            error: fetched.error,
+            moved_from: fetched.moved_from,
+        })
+        .collect();
+    print_records(format, &records)?;
+    if failed > 0 {
+        return Err(anyhow!("{failed} channels failed to fetch"));
+    }
+    Ok(())
+}
+
+fn list_channels(cfg: &RssConfig, files: &StoreFiles, format: Format) -> Result<()> {
+    let history = History::load(files.history.clone())?;
+    let item_states = ItemStates::load(files.state.clone())?;
+    let records: Vec<ChannelRecord> = channels(cfg)
+        .iter()
+        .map(|channel| channel_record(channel, &history, &item_states))
+        .collect();
+    print_records(format, &records)
+}
+
+fn health(
+    cfg: &RssConfig,
+    files: &StoreFiles,
+    all: bool,
+    enable: Option<&str>,
+    format: Format,
+) -> Result<()> {
+    let mut health = Health::load(files.health.clone())?;
+    if let Some(name_or_url) = enable {
+        let channel = find_channel(cfg, name_or_url)?;
+        health.reset(&channel.get_link());
+        health.save_changes(files.health.clone())?;
+        eprintln!("{} will be fetched again", channel.title);
+    }
+    let now = Utc::now().fixed_offset();
+    let records: Vec<HealthRecord> = channels(cfg)
+        .iter()
+        .filter_map(|channel| {
+            let feed_health = health.get(&channel.get_link()).cloned().unwrap_or_default();
+            (all || feed_health.is_broken()).then(|| HealthRecord::new(channel, &feed_health, now))
+        })
+        .collect();
+    print_records(format, &records)
+}
+
+fn list_items(
+    cfg: &RssConfig,
+    files: &StoreFiles,
+    name_or_url: &str,
+    format: Format,
+) -> Result<()> {
+    let channel = find_channel(cfg, name_or_url)?;
+    let items = History::load(files.history.clone())?.items_for(&channel.get_link());
+    if items.is_empty() {
+        eprintln!("No items stored for {}, try rrss fetch", channel.title);
+    }
+    let item_states = ItemStates::load(files.state.clone())?;
+    let records: Vec<ItemRecord> = items
+        .iter()
+        .map(|item| ItemRecord::new(&channel, item, &item_states))
+        .collect();
+    print_records(format, &records)
+}
+
+async fn add(
+    cfg: RssConfig,
+    files: &StoreFiles,
+    url: &str,
+    name: Option<String>,
+    format: Format,
+) -> Result<()> {
+    let (channel, history) = add_channel(cfg, files, url, name).await?;
+    let record = channel_record(&channel, &history, &ItemStates::load(files.state.clone())?);
+    print_records(format, &[record])
+}
+
+///Follow the feed at the url, storing what it has so far.
+///Returns the channel under the name it was given, and the history with its items
+pub async fn add_channel(
+    mut cfg: RssConfig,
+    files: &StoreFiles,
+    url: &str,
+    name: Option<String>,
+) -> Result<(Channel, History)> {
+    if cfg.channels.values().any(|known| known == url) {
+        return Err(anyhow!("Already following {url}"));
+    }
+    //fetching makes sure it is a feed, and gives us a name for it
+    let fetched = match discover_feed(url).await? {
+        Discovered::Feed(channel) => channel,
+        Discovered::Choices(choices) => {
+            let urls: Vec<String> = choices.into_iter().map(|choice| choice.url).collect();
+            return Err(anyhow!(
+                "{url} has more than one feed, add one of: {}",
+                urls.join(" ")
+            ));
+        }
+    };
+    //a web page gives us the feed it links to, and that is what we follow
+    let url = fetched.get_link();
+    if cfg.channels.values().any(|known| *known == url) {
+        return Err(anyhow!("Already following {url}"));
+    }
+    let name = name.unwrap_or(fetched.title.clone());
+    if cfg.channels.contains_key(&name) {
+        return Err(anyhow!("There is already a channel called {name}"));
+    }
+    let mut history = History::load(files.history.clone())?;
+    history.merge(&fetched);
+    history.save_changes(files.history.clone())?;
+    info!("Adding channel {name} at {url}");
+    cfg.channels.insert(name.clone(), url);
+    save_config(files.config.clone(), cfg)?;
+
+    let channel = Channel {
+        title: name,
+        ..fetched
+    };
+    Ok((channel, history))
+}
+
+fn remove(mut cfg: RssConfig, files: &StoreFiles, name_or_url: &str, format: Format) -> Result<()> {
+    let channel = find_channel(&cfg, name_or_url)?;
+    let record = channel_record(
+        &channel,
+        &History::load(files.history.clone())?,
+        &ItemStates::load(files.state.clone())?,
+    );
+    cfg.channels.remove(&channel.title);
+    save_config(files.config.clone(), cfg)?;
+    print_records(format, &[record])
+}
+
+///Fetch everything and print what came out since the window or the last run
+async fn digest(
+    cfg: &RssConfig,
+    files: &StoreFiles,
+    since: Option<&str>,
+    format: DigestFormat,
+) -> Result<()> {
+    let mut digest_state = DigestState::load(files.digest.clone())?;
+    let now = Utc::now();
+    let start = digest_start(since, digest_state.last_run, now)?;
+    let (fetched, notices) = refresh_channels(cfg, files, false).await?;
+    for notice in notices {
+        eprintln!("{notice}");
+    }
+
+    let channels = fetched
+        .into_iter()
+        .map(|fetched| DigestChannel {
+            name: fetched.channel.title.clone(),
+            items: fetched
+                .items
+                .into_iter()
+                .filter(|item| is_new(item, &start, fetched.unseen.contains(&item.id())))
+                .collect(),
+            error: fetched.error,
+        })
+        .collect();
+    let digest = Digest {
+        since: start,
+        channels,
+    };
+    println!("{}", digest.render(format, &cfg.display));
+    digest_state.last_run = Some(now.fixed_offset());
+    digest_state.save(files.digest.clone())
+}
+
+///The channels in a folder from the config, by name or url
+pub fn folder_channels(cfg: &RssConfig, folder: &str) -> Result<Vec<Channel>> {
+    cfg.folders
+        .get(folder)
+        .ok_or(anyhow!("No folder called {folder} in the config"))?
+        .iter()
+        .map(|name_or_url| find_channel(cfg, name_or_url))
+        .collect()
+}
+
+///Channels with what we have stored for them
+pub fn with_stored_items(channels: Vec<Channel>, history: &History) -> Vec<Channel> {
+    channels
+        .into_iter()
+        .map(|mut channel| {
+            channel.items = history.items_for(&channel.get_link());
+            channel
+        })
+        .collect()
+}
+
+fn feed(
+    channels: &[Channel],
+    files: &StoreFiles,
+    info: &FeedInfo,
+    starred: bool,
+    limit: usize,
+    format: FeedFormat,
+) -> Result<()> {
+    let channels = with_stored_items(channels.to_vec(), &History::load(files.history.clone())?);
+    let item_states = ItemStates::load(files.state.clone())?;
+    let entries = select_entries(&channels, &item_states, starred, limit);
+    println!("{}", write_feed(format, info, &entries, Utc::now()));
+    Ok(())
+}
+
+async fn ctl(socket: &Path, command: &DaemonCommand, format: Format) -> Result<()> {
+    let reply = send(socket, command).await?;
+    if !reply.ok {
+        return Err(anyhow!(reply.message));
+    }
+    match format {
+        Format::Tsv => {
+            println!("{}", reply.message);
+            print_records(format, &reply.downloads)?;
+        }
+        Format::Json => println!("{}", serde_json::to_string_pretty(&reply)?),
+        Format::Ndjson => println!("{}", serde_json::to_string(&reply)?),
+    }
+    Ok(())
+}
+
+///Export what we have stored, run fetch first for the latest
+fn export(cfg: &RssConfig, files: &StoreFiles, dir: &Path) -> Result<()> {
+    let channels = with_stored_items(channels(cfg), &History::load(files.history.clone())?);
+    let item_states = ItemStates::load(files.state.clone())?;
+    let summary = export_html(dir, &channels, &item_states, &cfg.display)?;
+    eprintln!(
+        "Exported {} items from {} channels",
+        summary.items, summary.channels
+    );
+    println!("{}", summary.index.display());
+    Ok(())
+}
+
+///Find the item with this id among all the stored items, along with its channel
+pub fn find_item(cfg: &RssConfig, history: &History, item_id: &str) -> Result<(Channel, Item)> {
+    channels(cfg)
+        .into_iter()
+        .find_map(|channel| {
+            let item = history
+                .items_for(&channel.get_link())
+                .into_iter()
+                .find(|item| item.id() == item_id)?;
+            Some((channel, item))
+        })
+        .ok_or(anyhow!("No stored item with id {item_id}, try rrss fetch"))
+}
+
+async fn download(
+    cfg: &RssConfig,
+    files: &StoreFiles,
+    item_id: &str,
+    format: Format,
+) -> Result<()> {
+    let history = History::load(files.history.clone())?;
+    let (channel, item) = find_item(cfg, &history, item_id)?;
+    let request = cfg
+        .downloads
+        .download_request(&channel, &item)
+        .ok_or(anyhow!("{} has nothing to download", item.get_title()))?;
+
+    let mut downloads = DownloadManager::default();
+    let id = downloads.enqueue(request);
+    let mut finished = Vec::new();
+    while !downloads.is_idle() {
+        tokio::time::sleep(DOWNLOAD_POLL).await;
+        finished.extend(downloads.poll());
+    }
+    let download = downloads
+        .downloads
+        .iter()
+        .find(|download| download.id == id)
+        .ok_or(anyhow!("Lost track of the download"))?;
+    match &download.status {
+        DownloadStatus::Finished => {
+            ItemStates::update(files.state.clone(), |item_states| {
+                for download in finished {
+                    item_states
+                        .set_downloaded(&download.request.item_id, Some(download.request.path));
+                }
+            })?;
+            let record = DownloadRecord {
+                item_id: download.request.item_id.clone(),
+                path: download.request.path.clone(),
+                bytes: download.downloaded,
+            };
+            print_records(format, &[record])
+        }
+        DownloadStatus::Failed(reason) => Err(anyhow!("Download failed: {reason}")),
+        status => Err(anyhow!("Download stopped: {status:?}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::mock_server;
+    use std::collections::HashMap;
+
+    #[test]
+    pub fn test_find_channel() {
+        let cfg = RssConfig {
+            channels: HashMap::from([
+                (
+                    "Between Two Cairns".to_string(),
+                    "https://feeds.buzzsprout.com/2042709.rss".to_string(),
+                ),
+                (
+                    "Fear of a Black Dragon".to_string(),
+                    "http://feeds.libsyn.com/103241/rss".to_string(),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let found = |name: &str| find_channel(&cfg, name).map(|channel| channel.title);
+        assert_eq!("Between Two Cairns", found("Between Two Cairns").unwrap());
+        assert_eq!(
+            "Fear of a Black Dragon",
+            found("http://feeds.libsyn.com/103241/rss").unwrap()
+        );
+        assert_eq!("Between Two Cairns", found("between two cairns").unwrap());
+        assert!(found("Nope").is_err());
+    }
+
+    ///Stores for a config in a temporary directory, with a feed of one episode to follow
+    async fn test_setup(dir: &Path) -> (StoreFiles, String) {
+        let (episode_addr, _) = mock_server(|_: &str| "episode").await;
+        let feed = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+<title>Test Pod</title>
+<link>https://example.com</link>
+<description>A pod for testing</description>
+<item>
+<title>Episode 1</title>
+<guid>ep-1</guid>
+<pubDate>Mon, 03 Jun 2024 12:00:00 +0000</pubDate>
+<enclosure url="http://{episode_addr}/episode.mp3" length="7" type="audio/mpeg"/>
+</item>
+</channel>
+</rss>"#
+        );
+        let (feed_addr, _) = mock_server(move |_: &str| feed.clone()).await;
+        let config = dir.join("rrss.toml").to_string_lossy().to_string();
+        (
+            StoreFiles::for_config(Some(config)),
+            format!("http://{feed_addr}/feed.rss"),
+        )
+    }
+
+    #[tokio::test]
+    pub async fn test_add_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let (files, url) = test_setup(dir.path()).await;
+
+        add(RssConfig::default(), &files, &url, None, Format::Json)
+            .await
+            .unwrap();
+        let cfg = load_config(files.config.clone()).unwrap().unwrap();
+        assert_eq!(Some(&url), cfg.channels.get("Test Pod"));
+        //the stores go next to the config, not in the working directory
+        assert!(dir.path().join(".rrss_history.toml").exists());
+        let history = History::load(files.history.clone()).unwrap();
+        assert_eq!(1, history.items_for(&url).len());
+        assert!(add(cfg, &files, &url, None, Format::Json).await.is_err());
+
+        let cfg = load_config(files.config.clone()).unwrap().unwrap();
+        remove(cfg, &files, "test pod", Format::Json).unwrap();
+        let cfg = load_config(files.config.clone()).unwrap().unwrap();
+        assert!(cfg.channels.is_empty());
+        assert!(remove(cfg, &files, "Test Pod", Format::Json).is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let (files, url) = test_setup(dir.path()).await;
+        add(RssConfig::default(), &files, &url, None, Format::Json)
+            .await
+            .unwrap();
+        let mut cfg = load_config(files.config.clone()).unwrap().unwrap();
+        cfg.downloads.root = dir.path().join("pods").to_string_lossy().to_string();
+
+        download(&cfg, &files, "ep-1", Format::Json).await.unwrap();
+        let item_states = ItemStates::load(files.state.clone()).unwrap();
+        let path = item_states.downloaded_path("ep-1").unwrap();
+        assert!(path.starts_with(dir.path().join("pods")));
+        assert_eq!("episode", std::fs::read_to_string(path).unwrap());
+        assert!(dir.path().join(".rrss_state.toml").exists());
+
+        assert!(download(&cfg, &files, "ep-2", Format::Json).await.is_err());
+    }
+}
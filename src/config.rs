@@ -1,13 +1,94 @@
 use anyhow::Result;
 use log::info;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::read_to_string, path::Path};
+use std::{
+    collections::HashMap,
+    fs::{read_to_string, File},
+    path::{Path, PathBuf},
+};
 
-#[derive(Deserialize, Serialize)]
+use crate::{
+    daemon::DEFAULT_SOCKET, dates::DisplayConfig, paths::DownloadConfig, player::PlayerConfig,
+    sync::SyncConfig,
+};
+
+#[derive(Deserialize, Serialize, Default)]
 pub struct RssConfig {
     ///channel name to url map
     pub channels: HashMap<String, String>,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    #[serde(default)]
+    pub downloads: DownloadConfig,
+    #[serde(default)]
+    pub player: PlayerConfig,
+    ///folder name to the names of the channels in it
+    #[serde(default)]
+    pub folders: HashMap<String, Vec<String>>,
+    ///A Google Reader api server to sync with, none keeps everything local
+    #[serde(default)]
+    pub sync: Option<SyncConfig>,
+}
+///Where to find the config and the stores, None for the usual files in the working directory
+#[derive(Debug, Clone, Default)]
+pub struct StoreFiles {
+    pub config: Option<String>,
+    pub history: Option<String>,
+    pub state: Option<String>,
+    pub sync: Option<String>,
+    pub health: Option<String>,
+    pub digest: Option<String>,
+    ///Where the daemon for these stores listens
+    pub socket: Option<String>,
+}
+
+impl StoreFiles {
+    ///The stores kept next to the config file, so each config gets its own.
+    ///A config in the working directory, or none at all, gets the usual files there
+    pub fn for_config(config: Option<String>) -> StoreFiles {
+        let dir = config
+            .as_deref()
+            .and_then(|config| Path::new(config).parent())
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf);
+        let beside = |name: &str| {
+            dir.as_ref()
+                .map(|dir| dir.join(name).to_string_lossy().to_string())
+        };
+        StoreFiles {
+            history: beside(".rrss_history.toml"),
+            state: beside(".rrss_state.toml"),
+            sync: beside(".rrss_sync.toml"),
+            health: beside(".rrss_health.toml"),
+            digest: beside(".rrss_digest.toml"),
+            socket: beside(DEFAULT_SOCKET),
+            config,
+        }
+    }
+
+    ///The daemon's socket, so the tui and rrss ctl find the daemon using the same stores
+    pub fn socket_path(&self) -> PathBuf {
+        PathBuf::from(self.socket.as_deref().unwrap_or(DEFAULT_SOCKET))
+    }
+}
+
+///Run the change with the store's file locked.
+///The tui, daemon, server and command line all write the stores, so they take turns.
+///The lock file stays behind, as removing it would let a waiting writer lock a file nobody else sees
+pub fn with_store_lock<T>(store_file: &str, change: impl FnOnce() -> Result<T>) -> Result<T> {
+    let lock = File::create(format!("{store_file}.lock"))?;
+    lock.lock()?;
+    change()
+}
+
+///Write a store over its file in one go, so a reader never sees half of it
+pub fn write_store(store_file: &str, contents: &str) -> Result<()> {
+    let temp_file = format!("{store_file}.tmp");
+    std::fs::write(&temp_file, contents)?;
+    std::fs::rename(temp_file, store_file)?;
+    Ok(())
 }
+
 ///Load config from file, if file exists
 ///If no file passed, will default to checking for './.rrss.toml'
 pub fn load_config(path: Option<String>) -> Result<Option<RssConfig>> {
@@ -23,7 +104,8 @@ pub fn load_config(path: Option<String>) -> Result<Option<RssConfig>> {
             info!("Found config file, parsing");
             //load the string from the file
             let toml_str = read_to_string(config_file)?;
-            let cfg: RssConfig = toml::from_str(&toml_str)?;
+            let mut cfg: RssConfig = toml::from_str(&toml_str)?;
+            cfg.display.validate();
             info!("Channels found: {:?}", cfg.channels);
             Some(cfg)
         }
@@ -49,9 +131,11 @@ pub fn save_config(path: Option<String>, cfg: RssConfig) -> Result<()> {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_return)]
 mod test {
 
     use super::*;
+    use crate::dates::DateStyle;
 
     #[test]
     pub fn test_load_config() {
@@ -80,7 +164,14 @@ mod test {
     pub fn test_save_config() {
         let mut cfg_map = HashMap::new();
         cfg_map.insert("First test".to_string(), "https://testing.test".to_string());
-        let cfg = RssConfig { channels: cfg_map };
+        let cfg = RssConfig {
+            channels: cfg_map,
+            display: DisplayConfig::default(),
+            downloads: DownloadConfig::default(),
+            player: PlayerConfig::default(),
+            folders: HashMap::new(),
+            sync: None,
+        };
         let test_path = "test_file_save.toml".to_string();
         let res = save_config(Some(test_path.clone()), cfg);
         assert!(res.is_ok());
@@ -93,4 +184,51 @@ mod test {
         std::fs::remove_file(test_path.clone()).unwrap();
         assert!(cfg_found, "Did not find cfg");
     }
+
+    #[test]
+    pub fn test_load_display_config() {
+        let toml_str = r"[channels]
+'Between Two Cairns'='https://feeds.buzzsprout.com/2042709.rss'
+[display]
+date_style='absolute'
+timezone='utc'";
+        let test_file = "test_file_display.toml";
+        std::fs::write(test_file, toml_str).unwrap();
+        let cfg = load_config(Some(test_file.to_string())).unwrap().unwrap();
+        std::fs::remove_file(test_file).unwrap();
+        assert_eq!(DateStyle::Absolute, cfg.display.date_style);
+        assert_eq!("utc", cfg.display.timezone);
+        assert_eq!(
+            DisplayConfig::default().timestamp_format,
+            cfg.display.timestamp_format
+        );
+    }
+
+    #[test]
+    pub fn test_store_files_for_config() {
+        let files = StoreFiles::for_config(Some("/home/me/pods/rrss.toml".to_string()));
+        assert_eq!(
+            Some("/home/me/pods/.rrss_history.toml".to_string()),
+            files.history
+        );
+        assert_eq!(
+            Some("/home/me/pods/.rrss_state.toml".to_string()),
+            files.state
+        );
+        assert_eq!(Some("/home/me/pods/rrss.toml".to_string()), files.config);
+        assert_eq!(
+            PathBuf::from("/home/me/pods/.rrss.sock"),
+            files.socket_path()
+        );
+        //in the working directory we keep to the usual files
+        assert_eq!(
+            None,
+            StoreFiles::for_config(Some("rrss.toml".to_string())).history
+        );
+        assert_eq!(None, StoreFiles::for_config(None).state);
+        assert_eq!(
+            PathBuf::from(DEFAULT_SOCKET),
+            StoreFiles::for_config(None).socket_path()
+        );
+    }
 }
@@ -0,0 +1,591 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use clap::Subcommand;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    autodownload::{auto_download_requests, prune_downloads},
+    cli::{
+        add_channel, channels, find_channel, find_item, refresh_channels, with_stored_items,
+        Fetched,
+    },
+    config::{load_config, RssConfig, StoreFiles},
+    download::{Download, DownloadManager, DownloadStatus},
+    history::History,
+    model::Channel,
+    output::TsvRecord,
+    state::ItemStates,
+};
+
+///Where the daemon listens unless told otherwise, next to the config like the other stores
+pub const DEFAULT_SOCKET: &str = ".rrss.sock";
+///How often the daemon looks in on its downloads
+const DOWNLOAD_POLL: Duration = Duration::from_millis(500);
+
+///What the daemon can be asked to do, sent over its socket as one json object to a line
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum DaemonCommand {
+    ///Follow a new channel
+    Add {
+        url: String,
+        ///Name to give the channel, defaults to the feed's title
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    ///Fetch every channel now rather than waiting for the schedule
+    Refresh {
+        ///Name or url of the one channel to fetch, even if it is backing off after failures
+        #[serde(default)]
+        channel: Option<String>,
+    },
+    ///Mark items read
+    MarkRead {
+        ///Item ids, as shown by list-items
+        #[arg(required = true)]
+        ids: Vec<String>,
+        ///Mark them unread instead
+        #[arg(long)]
+        #[serde(default)]
+        unread: bool,
+    },
+    ///Download the enclosure of an item in the background
+    Download {
+        ///Item id, as shown by list-items
+        item: String,
+    },
+    ///What the daemon is up to, and its downloads
+    Status,
+    ///Stop the daemon, and any downloads it has running
+    Stop,
+}
+
+///How a download in the daemon is going
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DaemonDownload {
+    pub item_id: String,
+    pub title: String,
+    pub path: PathBuf,
+    ///queued, running, finished, failed or cancelled
+    pub status: String,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+}
+
+impl From<&Download> for DaemonDownload {
+    fn from(download: &Download) -> Self {
+        let status = match &download.status {
+            DownloadStatus::Queued => "queued",
+            DownloadStatus::Running => "running",
+            DownloadStatus::Finished => "finished",
+            DownloadStatus::Failed(_) => "failed",
+            DownloadStatus::Cancelled => "cancelled",
+        };
+        Self {
+            item_id: download.request.item_id.clone(),
+            title: download.request.title.clone(),
+            path: download.request.path.clone(),
+            status: status.to_string(),
+            downloaded: download.downloaded,
+            total: download.total,
+        }
+    }
+}
+
+impl TsvRecord for DaemonDownload {
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.item_id.clone(),
+            self.status.clone(),
+            self.downloaded.to_string(),
+            self.total
+                .map(|total| total.to_string())
+                .unwrap_or_default(),
+            self.path.display().to_string(),
+        ]
+    }
+}
+
+///What the daemon says back, one json object to a line
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DaemonReply {
+    pub ok: bool,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub downloads: Vec<DaemonDownload>,
+}
+
+impl DaemonReply {
+    fn ok(message: String) -> Self {
+        Self {
+            ok: true,
+            message,
+            ..Default::default()
+        }
+    }
+
+    fn failed(e: anyhow::Error) -> Self {
+        Self {
+            ok: false,
+            message: e.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+///What a refresh brought in, for the daemon to act on
+struct Refreshed {
+    cfg: RssConfig,
+    fetched: Vec<Fetched>,
+    ///Channels that moved, and how the sync went when the server fetches for us
+    notices: Vec<String>,
+}
+
+///Fetch the channel, or every channel if none, and save what came in.
+///Runs as its own task, so the daemon goes on answering while it fetches
+async fn refresh_stores(files: StoreFiles, channel: Option<String>) -> Result<Refreshed> {
+    let mut cfg = load_config(files.config.clone())?.unwrap_or_default();
+    if let Some(channel) = &channel {
+        let url = find_channel(&cfg, channel)?.get_link();
+        cfg.channels.retain(|_, channel_url| *channel_url == url);
+    }
+    let (fetched, notices) = refresh_channels(&cfg, &files, channel.is_some()).await?;
+    Ok(Refreshed {
+        cfg,
+        fetched,
+        notices,
+    })
+}
+
+///What the daemon owns: the downloads, and when it last fetched
+struct Daemon {
+    files: StoreFiles,
+    downloads: DownloadManager,
+    last_refresh: Option<DateTime<Local>>,
+    ///Refreshes still running in their tasks
+    refreshing: usize,
+}
+
+impl Daemon {
+    ///Everything but refresh, which the command loop runs as a task
+    async fn handle(&mut self, command: DaemonCommand) -> DaemonReply {
+        let result = match command {
+            DaemonCommand::Add { url, name } => self.add(&url, name).await,
+            DaemonCommand::Refresh { .. } => unreachable!("refreshes run as tasks"),
+            DaemonCommand::MarkRead { ids, unread } => self.mark_read(&ids, !unread),
+            DaemonCommand::Download { item } => self.download(&item),
+            DaemonCommand::Status => Ok(self.status()),
+            DaemonCommand::Stop => Ok("Stopping".to_string()),
+        };
+        self.reply(result)
+    }
+
+    fn reply(&self, result: Result<String>) -> DaemonReply {
+        match result {
+            Ok(message) => DaemonReply {
+                downloads: self.downloads.downloads.iter().map(Into::into).collect(),
+                ..DaemonReply::ok(message)
+            },
+            Err(e) => DaemonReply::failed(e),
+        }
+    }
+
+    async fn add(&mut self, url: &str, name: Option<String>) -> Result<String> {
+        let cfg = load_config(self.files.config.clone())?.unwrap_or_default();
+        let (channel, _) = add_channel(cfg, &self.files, url, name).await?;
+        Ok(format!("Added {}", channel.title))
+    }
+
+    ///Start whatever the download rules want from a refresh, and say how it went
+    fn finish_refresh(&mut self, refreshed: Result<Refreshed>) -> Result<String> {
+        let Refreshed {
+            cfg,
+            fetched,
+            notices,
+        } = refreshed?;
+        self.last_refresh = Some(Local::now());
+
+        let item_states = ItemStates::load(self.files.state.clone())?;
+        let mut queued = 0;
+        for fetched in fetched.iter().filter(|fetched| fetched.error.is_none()) {
+            let channel = Channel {
+                items: fetched.items.clone(),
+                ..fetched.channel.clone()
+            };
+            for request in
+                auto_download_requests(&cfg.downloads, &channel, &item_states, &self.downloads)
+            {
+                info!("Automatically downloading {}", request.title);
+                self.downloads.enqueue(request);
+                queued += 1;
+            }
+        }
+        let new_items: usize = fetched.iter().map(|fetched| fetched.unseen.len()).sum();
+        let failed = fetched
+            .iter()
+            .filter(|fetched| fetched.error.is_some() && !fetched.skipped)
+            .count();
+        let skipped = fetched.iter().filter(|fetched| fetched.skipped).count();
+        let mut message = format!(
+            "Fetched {} channels, {new_items} new items, {failed} failed, {skipped} left alone, {queued} downloads queued",
+            fetched.len() - skipped
+        );
+        for notice in notices {
+            info!("{notice}");
+            message.push_str(&format!(". {notice}"));
+        }
+        Ok(message)
+    }
+
+    fn download(&mut self, item_id: &str) -> Result<String> {
+        let cfg = load_config(self.files.config.clone())?.unwrap_or_default();
+        let history = History::load(self.files.history.clone())?;
+        let (channel, item) = find_item(&cfg, &history, item_id)?;
+        let downloading = self
+            .downloads
+            .downloads
+            .iter()
+            .any(|download| download.is_active() && download.request.item_id == item_id);
+        if downloading {
+            return Ok(format!("Already downloading {}", item.get_title()));
+        }
+        let request = cfg
+            .downloads
+            .download_request(&channel, &item)
+            .ok_or(anyhow!("{} has nothing to download", item.get_title()))?;
+        self.downloads.enqueue(request);
+        Ok(format!("Downloading {}", item.get_title()))
+    }
+
+    fn mark_read(&self, ids: &[String], read: bool) -> Result<String> {
+        let mut changed = 0;
+        ItemStates::update(self.files.state.clone(), |item_states| {
+            changed = ids
+                .iter()
+                .filter(|id| item_states.set_read(id, read))
+                .count();
+        })?;
+        Ok(format!("Marked {changed} items"))
+    }
+
+    fn status(&self) -> String {
+        let active = self
+            .downloads
+            .downloads
+            .iter()
+            .filter(|download| download.is_active())
+            .count();
+        let last_refresh = self
+            .last_refresh
+            .map(|last_refresh| last_refresh.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or("never".to_string());
+        format!("Last refreshed {last_refresh}, {active} downloads running")
+    }
+
+    ///Record where finished downloads went, then prune by the retention rules
+    fn poll_downloads(&mut self) -> Result<()> {
+        let finished = self.downloads.poll();
+        if finished.is_empty() {
+            return Ok(());
+        }
+        let cfg = load_config(self.files.config.clone())?.unwrap_or_default();
+        let history = History::load(self.files.history.clone())?;
+        let channels = with_stored_items(channels(&cfg), &history);
+        //the tui may have written since we last looked, so we go from the file
+        ItemStates::update(self.files.state.clone(), |item_states| {
+            for download in finished {
+                item_states.set_downloaded(&download.request.item_id, Some(download.request.path));
+            }
+            prune_downloads(&cfg.downloads, &channels, item_states);
+        })?;
+        Ok(())
+    }
+}
+
+///Run until told to stop: refresh on the schedule, run downloads and answer commands on the socket
+#[cfg(unix)]
+pub async fn run_daemon(socket: &Path, files: StoreFiles, refresh_every: Duration) -> Result<()> {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UnixListener,
+        sync::{mpsc, oneshot},
+        time::{interval, MissedTickBehavior},
+    };
+
+    if socket.exists() {
+        if send(socket, &DaemonCommand::Status).await.is_ok() {
+            return Err(anyhow!(
+                "A daemon is already listening on {}",
+                socket.display()
+            ));
+        }
+        //left behind by a daemon that did not get to clean up
+        std::fs::remove_file(socket)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    eprintln!("Daemon listening on {}", socket.display());
+
+    let (command_tx, mut command_rx) =
+        mpsc::channel::<(DaemonCommand, oneshot::Sender<DaemonReply>)>(16);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let command_tx = command_tx.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let reply = match serde_json::from_str::<DaemonCommand>(&line) {
+                        Ok(command) => {
+                            let (reply_tx, reply_rx) = oneshot::channel();
+                            if command_tx.send((command, reply_tx)).await.is_err() {
+                                break;
+                            }
+                            reply_rx.await.unwrap_or_default()
+                        }
+                        Err(e) => DaemonReply::failed(anyhow!("Bad command: {e}")),
+                    };
+                    let Ok(reply) = serde_json::to_string(&reply) else {
+                        break;
+                    };
+                    if writer
+                        .write_all(format!("{reply}\n").as_bytes())
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    type RefreshReply = Option<oneshot::Sender<DaemonReply>>;
+    ///Refresh in a task, sending back what came in along with who to answer
+    fn spawn_refresh(
+        files: &StoreFiles,
+        channel: Option<String>,
+        reply_tx: RefreshReply,
+        refreshed_tx: &mpsc::UnboundedSender<(Result<Refreshed>, RefreshReply)>,
+    ) {
+        let (files, refreshed_tx) = (files.clone(), refreshed_tx.clone());
+        tokio::spawn(async move {
+            let refreshed = refresh_stores(files, channel).await;
+            let _ = refreshed_tx.send((refreshed, reply_tx));
+        });
+    }
+
+    let (refreshed_tx, mut refreshed_rx) = mpsc::unbounded_channel();
+    let mut daemon = Daemon {
+        files,
+        downloads: DownloadManager::default(),
+        last_refresh: None,
+        refreshing: 0,
+    };
+    let mut refresh = interval(refresh_every);
+    refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut poll = interval(DOWNLOAD_POLL);
+    loop {
+        tokio::select! {
+            _ = refresh.tick() => {
+                //no sense starting another while one is still fetching
+                if daemon.refreshing == 0 {
+                    daemon.refreshing += 1;
+                    spawn_refresh(&daemon.files, None, None, &refreshed_tx);
+                }
+            }
+            Some((refreshed, reply_tx)) = refreshed_rx.recv() => {
+                daemon.refreshing -= 1;
+                let result = daemon.finish_refresh(refreshed);
+                match (reply_tx, result) {
+                    (Some(reply_tx), result) => {
+                        let _ = reply_tx.send(daemon.reply(result));
+                    }
+                    (None, Ok(message)) => info!("{message}"),
+                    (None, Err(e)) => error!("Scheduled refresh failed: {e}"),
+                }
+            }
+            _ = poll.tick() => {
+                if let Err(e) = daemon.poll_downloads() {
+                    error!("Unable to record downloads: {e}");
+                }
+            }
+            Some((command, reply_tx)) = command_rx.recv() => {
+                let stop = command == DaemonCommand::Stop;
+                if let DaemonCommand::Refresh { channel } = command {
+                    daemon.refreshing += 1;
+                    spawn_refresh(&daemon.files, channel, Some(reply_tx), &refreshed_tx);
+                } else {
+                    let _ = reply_tx.send(daemon.handle(command).await);
+                }
+                if stop {
+                    break;
+                }
+            }
+        }
+    }
+    std::fs::remove_file(socket)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn run_daemon(
+    _socket: &Path,
+    _files: StoreFiles,
+    _refresh_every: Duration,
+) -> Result<()> {
+    Err(anyhow!("The daemon needs unix sockets"))
+}
+
+///Send one command to the daemon and wait for what it says
+#[cfg(unix)]
+pub async fn send(socket: &Path, command: &DaemonCommand) -> Result<DaemonReply> {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UnixStream,
+    };
+
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|e| anyhow!("No daemon at {}: {e}", socket.display()))?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("{}\n", serde_json::to_string(command)?).as_bytes())
+        .await?;
+    let reply = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or(anyhow!("The daemon hung up"))?;
+    Ok(serde_json::from_str(&reply)?)
+}
+
+#[cfg(not(unix))]
+pub async fn send(_socket: &Path, _command: &DaemonCommand) -> Result<DaemonReply> {
+    Err(anyhow!("The daemon needs unix sockets"))
+}
+
+///Whether there is a daemon answering on the socket
+pub async fn is_running(socket: &Path) -> bool {
+    socket.exists() && send(socket, &DaemonCommand::Status).await.is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_command_json() {
+        assert_eq!(
+            r#"{"command":"mark-read","ids":["guid-1"],"unread":false}"#,
+            serde_json::to_string(&DaemonCommand::MarkRead {
+                ids: vec!["guid-1".to_string()],
+                unread: false,
+            })
+            .unwrap()
+        );
+        assert_eq!(
+            DaemonCommand::MarkRead {
+                ids: vec!["guid-1".to_string()],
+                unread: false,
+            },
+            serde_json::from_str(r#"{"command":"mark-read","ids":["guid-1"]}"#).unwrap()
+        );
+        assert_eq!(
+            DaemonCommand::Refresh { channel: None },
+            serde_json::from_str(r#"{"command":"refresh"}"#).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    pub async fn test_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| Some(dir.path().join(name).display().to_string());
+        let files = StoreFiles {
+            config: path("rrss.toml"),
+            history: path("history.toml"),
+            state: path("state.toml"),
+            sync: path("sync.toml"),
+            health: path("health.toml"),
+            digest: path("digest.toml"),
+            socket: path("rrss.sock"),
+        };
+        let socket = dir.path().join("rrss.sock");
+        let daemon = tokio::spawn({
+            let (socket, files) = (socket.clone(), files.clone());
+            async move { run_daemon(&socket, files, Duration::from_secs(3600)).await }
+        });
+        for _ in 0..50 {
+            if is_running(&socket).await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let reply = send(
+            &socket,
+            &DaemonCommand::MarkRead {
+                ids: vec!["guid-1".to_string(), "guid-2".to_string()],
+                unread: false,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!("Marked 2 items", reply.message);
+        assert!(ItemStates::load(files.state.clone())
+            .unwrap()
+            .is_read("guid-2"));
+
+        let reply = send(
+            &socket,
+            &DaemonCommand::Download {
+                item: "nope".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+        assert!(!reply.ok);
+
+        let feed = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+<title>Test Pod</title>
+<link>https://example.com</link>
+<description>A pod for testing</description>
+<item><title>Episode 1</title><guid>ep-1</guid></item>
+</channel>
+</rss>"#;
+        let (feed_addr, _) = crate::testing::mock_server(move |_: &str| feed).await;
+        let url = format!("http://{feed_addr}/feed.rss");
+        let mut cfg = RssConfig::default();
+        cfg.channels.insert("Test Pod".to_string(), url.clone());
+        crate::config::save_config(files.config.clone(), cfg).unwrap();
+        let reply = send(
+            &socket,
+            &DaemonCommand::Refresh {
+                channel: Some("test pod".to_string()),
+            },
+        )
+        .await
+        .unwrap();
+        assert!(reply.ok, "{}", reply.message);
+        assert!(
+            reply.message.starts_with("Fetched 1 channels, 1 new items"),
+            "{}",
+            reply.message
+        );
+        let history = History::load(files.history.clone()).unwrap();
+        assert_eq!(1, history.items_for(&url).len());
+
+        let reply = send(&socket, &DaemonCommand::Stop).await.unwrap();
+        assert!(reply.ok);
+        daemon.await.unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+}
@@ -0,0 +1,329 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Write};
+
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M %:z";
+
+///Formats we try once the date has been tidied up, with a timezone
+const ZONED_FORMATS: [&str; 8] = [
+    "%d %b %Y %H:%M:%S %z",
+    "%d %b %Y %H:%M %z",
+    "%b %d %Y %H:%M:%S %z",
+    "%b %d %Y %H:%M %z",
+    "%d %b %y %H:%M:%S %z",
+    "%d %b %y %H:%M %z",
+    "%Y-%m-%d %H:%M:%S %z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+];
+
+///Formats we try when there is no timezone, these we take to be UTC
+const NAIVE_FORMATS: [&str; 7] = [
+    "%d %b %Y %H:%M:%S",
+    "%d %b %Y %H:%M",
+    "%b %d %Y %H:%M:%S",
+    "%b %d %Y %H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+];
+
+///Named timezones that turn up in feeds, rfc 822 only knows a handful of them
+const ZONE_NAMES: [(&str, &str); 16] = [
+    ("GMT", "+0000"),
+    ("UTC", "+0000"),
+    ("UT", "+0000"),
+    ("Z", "+0000"),
+    ("EST", "-0500"),
+    ("EDT", "-0400"),
+    ("CST", "-0600"),
+    ("CDT", "-0500"),
+    ("MST", "-0700"),
+    ("MDT", "-0600"),
+    ("PST", "-0800"),
+    ("PDT", "-0700"),
+    ("BST", "+0100"),
+    ("CET", "+0100"),
+    ("CEST", "+0200"),
+    ("AEST", "+1000"),
+];
+
+const WEEKDAY_NAMES: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+///Mon, Tues, Thurs, Wednesday and so on, at least the first three letters of one
+fn is_weekday(part: &str) -> bool {
+    let lower = part.to_lowercase();
+    lower.len() >= 3 && WEEKDAY_NAMES.iter().any(|day| day.starts_with(&lower))
+}
+
+const MONTH_NAMES: [(&str, &str); 13] = [
+    ("january", "Jan"),
+    ("february", "Feb"),
+    ("march", "Mar"),
+    ("april", "Apr"),
+    ("june", "Jun"),
+    ("july", "Jul"),
+    ("august", "Aug"),
+    ("september", "Sep"),
+    ("sept", "Sep"),
+    ("october", "Oct"),
+    ("november", "Nov"),
+    ("december", "Dec"),
+    ("may", "May"),
+];
+
+///Parse the dates feeds give us.
+///Properly that is rfc 822 for rss and rfc 3339 for atom, but we also put up with
+///missing or misspelt weekdays, named timezones, full month names, the month first,
+///missing seconds and missing timezones.
+pub fn parse_date(date_str: &str) -> Option<DateTime<FixedOffset>> {
+    let date_str = date_str.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if date_str.is_empty() {
+        return None;
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc2822(&date_str) {
+        return Some(dt);
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(&date_str) {
+        return Some(dt);
+    }
+
+    let tidied = tidy_date(&date_str);
+    for fmt in ZONED_FORMATS {
+        if let Ok(dt) = DateTime::parse_from_str(&tidied, fmt) {
+            return Some(dt);
+        }
+    }
+    for fmt in NAIVE_FORMATS {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(&tidied, fmt) {
+            return Some(Utc.from_utc_datetime(&dt).fixed_offset());
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(&tidied, "%Y-%m-%d") {
+        let dt = date.and_hms_opt(0, 0, 0)?;
+        return Some(Utc.from_utc_datetime(&dt).fixed_offset());
+    }
+    None
+}
+
+///Drop the weekday, shorten the month and swap named timezones for offsets
+fn tidy_date(date_str: &str) -> String {
+    let mut parts: Vec<String> = date_str
+        .replace(',', " ")
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect();
+    //only a weekday, a leading month is part of the date
+    if parts.first().is_some_and(|first| is_weekday(first)) {
+        parts.remove(0);
+    }
+    for part in parts.iter_mut() {
+        let lower = part.to_lowercase();
+        if let Some((_, short)) = MONTH_NAMES.iter().find(|(long, _)| *long == lower) {
+            *part = short.to_string();
+        } else if let Some((_, offset)) = ZONE_NAMES.iter().find(|(name, _)| *name == part) {
+            *part = offset.to_string();
+        } else if part.len() == 6
+            && (part.starts_with('+') || part.starts_with('-'))
+            && part.chars().nth(3) == Some(':')
+        {
+            //+02:00 where there should be +0200
+            *part = part.replace(':', "");
+        }
+    }
+    parts.join(" ")
+}
+
+///How long ago, in the largest unit that fits, e.g. "3h" or "2d"
+pub fn relative_age(dt: &DateTime<FixedOffset>, now: &DateTime<Utc>) -> String {
+    let age = now.signed_duration_since(dt);
+    let minutes = age.num_minutes();
+    if minutes < 1 {
+        return "now".to_string();
+    }
+    let hours = age.num_hours();
+    let days = age.num_days();
+    if hours < 1 {
+        format!("{minutes}m")
+    } else if days < 1 {
+        format!("{hours}h")
+    } else if days < 14 {
+        format!("{days}d")
+    } else if days < 60 {
+        format!("{}w", days / 7)
+    } else if days < 365 {
+        format!("{}mo", days / 30)
+    } else {
+        format!("{}y", days / 365)
+    }
+}
+
+///Show dates in the item list as an age, or as a timestamp
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DateStyle {
+    #[default]
+    Relative,
+    Absolute,
+}
+
+///How we display the dates of items
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DisplayConfig {
+    pub date_style: DateStyle,
+    ///"local", "utc" or an offset like "+02:00"
+    pub timezone: String,
+    ///chrono format string for full timestamps
+    pub timestamp_format: String,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            date_style: DateStyle::Relative,
+            timezone: "local".to_string(),
+            timestamp_format: DEFAULT_TIMESTAMP_FORMAT.to_string(),
+        }
+    }
+}
+
+impl DisplayConfig {
+    ///Swap a timestamp format chrono cannot make sense of for the default,
+    ///so a typo in the config does not bring down the tui
+    pub fn validate(&mut self) {
+        if StrftimeItems::new(&self.timestamp_format).any(|item| item == Item::Error) {
+            warn!(
+                "Could not make sense of timestamp_format '{}', using '{DEFAULT_TIMESTAMP_FORMAT}'",
+                self.timestamp_format
+            );
+            self.timestamp_format = DEFAULT_TIMESTAMP_FORMAT.to_string();
+        }
+    }
+
+    ///The full timestamp, in the timezone we are configured for
+    pub fn format_timestamp(&self, dt: &DateTime<FixedOffset>) -> String {
+        let fmt = self.timestamp_format.as_str();
+        match self.timezone.to_lowercase().as_str() {
+            "local" => format_or_default(dt.with_timezone(&Local), fmt),
+            "utc" => format_or_default(dt.with_timezone(&Utc), fmt),
+            offset => match parse_offset(offset) {
+                Some(tz) => format_or_default(dt.with_timezone(&tz), fmt),
+                None => format_or_default(*dt, fmt),
+            },
+        }
+    }
+
+    ///Short date for lists, an age or a timestamp depending on the config
+    pub fn format_list_date(&self, dt: &DateTime<FixedOffset>) -> String {
+        match self.date_style {
+            DateStyle::Relative => relative_age(dt, &Utc::now()),
+            DateStyle::Absolute => self.format_timestamp(dt),
+        }
+    }
+}
+
+///chrono fails on a bad format when written out, rather than when parsed
+fn format_or_default<Tz: TimeZone>(dt: DateTime<Tz>, fmt: &str) -> String
+where
+    Tz::Offset: Display,
+{
+    let mut formatted = String::new();
+    match write!(formatted, "{}", dt.format(fmt)) {
+        Ok(()) => formatted,
+        Err(_) => dt.format(DEFAULT_TIMESTAMP_FORMAT).to_string(),
+    }
+}
+
+fn parse_offset(offset: &str) -> Option<FixedOffset> {
+    DateTime::parse_from_str(&format!("2000-01-01 00:00 {offset}"), "%Y-%m-%d %H:%M %z")
+        .ok()
+        .map(|dt| *dt.offset())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_parses_to(date_str: &str, expected: &str) {
+        let parsed = parse_date(date_str).unwrap_or_else(|| panic!("Could not parse {date_str}"));
+        assert_eq!(expected, parsed.to_rfc3339(), "Parsed {date_str} wrong");
+    }
+
+    #[test]
+    pub fn test_parse_date() {
+        assert_parses_to(
+            "Thu, 30 May 2024 14:30:13 -0400",
+            "2024-05-30T14:30:13-04:00",
+        );
+        assert_parses_to("2024-05-30T14:30:13Z", "2024-05-30T14:30:13+00:00");
+        assert_parses_to("Thu, 30 May 2024 14:30:13 GMT", "2024-05-30T14:30:13+00:00");
+        assert_parses_to("Thu, 30 May 2024 14:30:13 PDT", "2024-05-30T14:30:13-07:00");
+        assert_parses_to("Tue, 9 Jan 2024 08:00 +0000", "2024-01-09T08:00:00+00:00");
+        assert_parses_to(
+            "Wednesday, 04 September 2024 10:00:00 +02:00",
+            "2024-09-04T10:00:00+02:00",
+        );
+        assert_parses_to("Fri, 31 May 2024 14:30:13", "2024-05-31T14:30:13+00:00");
+        assert_parses_to(
+            "Mon, 30 May 2024 14:30:13 +0000",
+            "2024-05-30T14:30:13+00:00",
+        );
+        assert_parses_to("2024-05-30 14:30:13", "2024-05-30T14:30:13+00:00");
+        assert_parses_to("2024-05-30", "2024-05-30T00:00:00+00:00");
+        assert_parses_to("May 30, 2024 10:00 GMT", "2024-05-30T10:00:00+00:00");
+        assert_parses_to(
+            "Thursday, September 5, 2024 10:00:00 EDT",
+            "2024-09-05T10:00:00-04:00",
+        );
+        assert!(parse_date("").is_none());
+        assert!(parse_date("last tuesday").is_none());
+    }
+
+    #[test]
+    pub fn test_relative_age() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let age = |date_str: &str| relative_age(&parse_date(date_str).unwrap(), &now);
+        assert_eq!("now", age("2024-06-01T12:00:00Z"));
+        assert_eq!("5m", age("2024-06-01T11:55:00Z"));
+        assert_eq!("3h", age("2024-06-01T09:00:00Z"));
+        assert_eq!("2d", age("2024-05-30T12:00:00Z"));
+        assert_eq!("3w", age("2024-05-10T12:00:00Z"));
+        assert_eq!("2y", age("2022-05-10T12:00:00Z"));
+    }
+
+    #[test]
+    pub fn test_format_timestamp() {
+        let dt = parse_date("Thu, 30 May 2024 14:30:13 -0400").unwrap();
+        let utc = DisplayConfig {
+            timezone: "utc".to_string(),
+            ..Default::default()
+        };
+        assert_eq!("2024-05-30 18:30 +00:00", utc.format_timestamp(&dt));
+        let plus_two = DisplayConfig {
+            timezone: "+02:00".to_string(),
+            ..Default::default()
+        };
+        assert_eq!("2024-05-30 20:30 +02:00", plus_two.format_timestamp(&dt));
+
+        let mut broken = DisplayConfig {
+            timezone: "utc".to_string(),
+            timestamp_format: "%Y-%m-%d %Q".to_string(),
+            ..Default::default()
+        };
+        assert_eq!("2024-05-30 18:30 +00:00", broken.format_timestamp(&dt));
+        broken.validate();
+        assert_eq!(DEFAULT_TIMESTAMP_FORMAT, broken.timestamp_format);
+    }
+}
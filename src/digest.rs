@@ -0,0 +1,359 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use clap::ValueEnum;
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{fs::read_to_string, path::Path};
+
+use crate::{
+    dates::{parse_date, DisplayConfig},
+    model::{strip_html, Item},
+};
+
+///How much of the description goes into the digest
+const SUMMARY_CHARS: usize = 200;
+///The window when there is no last run to go by
+const DEFAULT_WINDOW_HOURS: i64 = 24;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum DigestFormat {
+    #[default]
+    Text,
+    Markdown,
+    Html,
+}
+
+///The new items of one channel, or why we could not get them
+#[derive(Debug, Default)]
+pub struct DigestChannel {
+    pub name: String,
+    pub items: Vec<Item>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Digest {
+    pub since: DateTime<FixedOffset>,
+    pub channels: Vec<DigestChannel>,
+}
+
+///When the digest last ran, so the next one picks up from there
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct DigestState {
+    pub last_run: Option<DateTime<FixedOffset>>,
+}
+
+fn digest_file(path: Option<String>) -> String {
+    path.unwrap_or(".rrss_digest.toml".to_string())
+}
+
+impl DigestState {
+    ///Load the digest state from file, if the file exists
+    ///If no file passed, will default to checking for './.rrss_digest.toml'
+    pub fn load(path: Option<String>) -> Result<DigestState> {
+        let digest_file = digest_file(path);
+        if !Path::new(&digest_file).exists() {
+            info!("No digest state found at {digest_file}");
+            return Ok(DigestState::default());
+        }
+        let toml_str = read_to_string(digest_file)?;
+        Ok(toml::from_str(&toml_str)?)
+    }
+
+    pub fn save(&self, path: Option<String>) -> Result<()> {
+        let digest_file = digest_file(path);
+        info!("Saving digest state to {digest_file}");
+        std::fs::write(digest_file, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+///The start of the digest: the window if one is asked for, else the last run, else a day ago
+pub fn digest_start(
+    since: Option<&str>,
+    last_run: Option<DateTime<FixedOffset>>,
+    now: DateTime<Utc>,
+) -> Result<DateTime<FixedOffset>> {
+    match since {
+        Some(since) => parse_since(since, now),
+        None => {
+            Ok(last_run.unwrap_or((now - Duration::hours(DEFAULT_WINDOW_HOURS)).fixed_offset()))
+        }
+    }
+}
+
+///Either a window back from now, like "30m", "24h", "7d" or "2w", or a date
+pub fn parse_since(since: &str, now: DateTime<Utc>) -> Result<DateTime<FixedOffset>> {
+    let since = since.trim();
+    let split = since
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(since.len());
+    let (amount, unit) = since.split_at(split);
+    if let Ok(amount) = amount.parse::<i64>() {
+        let window = match unit.trim() {
+            "m" | "min" => Some(Duration::try_minutes(amount)),
+            "h" => Some(Duration::try_hours(amount)),
+            "d" => Some(Duration::try_days(amount)),
+            "w" => Some(Duration::try_weeks(amount)),
+            _ => None,
+        };
+        if let Some(window) = window {
+            return window
+                .and_then(|window| now.checked_sub_signed(window))
+                .map(|start| start.fixed_offset())
+                .ok_or(anyhow!(
+                    "--since {since} goes back further than we can count"
+                ));
+        }
+    }
+    parse_date(since).ok_or(anyhow!(
+        "Cannot make sense of --since {since}, try something like 24h, 7d or 2024-05-30"
+    ))
+}
+
+///Whether the item belongs in a digest starting at `since`.
+///Items without a date go by whether we had seen them before
+pub fn is_new(item: &Item, since: &DateTime<FixedOffset>, unseen: bool) -> bool {
+    match item.published() {
+        Some(published) => published > *since,
+        None => unseen,
+    }
+}
+
+fn summary(item: &Item) -> Option<String> {
+    let text = strip_html(&item.description.clone().unwrap_or_default());
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if text.is_empty() {
+        return None;
+    }
+    if text.chars().count() <= SUMMARY_CHARS {
+        return Some(text);
+    }
+    let short: String = text.chars().take(SUMMARY_CHARS).collect();
+    Some(format!("{}…", short.trim_end()))
+}
+
+///Markdown would take these as formatting
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "\\`*_[]<>#|".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Digest {
+    pub fn item_count(&self) -> usize {
+        self.channels
+            .iter()
+            .map(|channel| channel.items.len())
+            .sum()
+    }
+
+    ///Channels with something to say, new items or an error
+    fn channels_to_show(&self) -> impl Iterator<Item = &DigestChannel> {
+        self.channels
+            .iter()
+            .filter(|channel| !channel.items.is_empty() || channel.error.is_some())
+    }
+
+    pub fn render(&self, format: DigestFormat, display: &DisplayConfig) -> String {
+        match format {
+            DigestFormat::Text => self.render_text(display),
+            DigestFormat::Markdown => self.render_markdown(display),
+            DigestFormat::Html => self.render_html(display),
+        }
+    }
+
+    fn heading(&self, display: &DisplayConfig) -> String {
+        format!(
+            "{} new items since {}",
+            self.item_count(),
+            display.format_timestamp(&self.since)
+        )
+    }
+
+    fn render_text(&self, display: &DisplayConfig) -> String {
+        let mut lines = vec![self.heading(display)];
+        for channel in self.channels_to_show() {
+            lines.push(String::new());
+            lines.push(channel.name.clone());
+            lines.push("=".repeat(channel.name.chars().count()));
+            if let Some(error) = &channel.error {
+                lines.push(format!("Could not fetch: {error}"));
+            }
+            for item in channel.items.iter() {
+                lines.push(format!("* {}", item.get_title()));
+                if let Some(link) = item.get_link() {
+                    lines.push(format!("  {link}"));
+                }
+                if let Some(summary) = summary(item) {
+                    lines.push(format!("  {summary}"));
+                }
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn render_markdown(&self, display: &DisplayConfig) -> String {
+        let mut lines = vec![format!("# {}", escape_markdown(&self.heading(display)))];
+        for channel in self.channels_to_show() {
+            lines.push(String::new());
+            lines.push(format!("## {}", escape_markdown(&channel.name)));
+            lines.push(String::new());
+            if let Some(error) = &channel.error {
+                lines.push(format!("_Could not fetch: {}_", escape_markdown(error)));
+                lines.push(String::new());
+            }
+            for item in channel.items.iter() {
+                let title = escape_markdown(&item.get_title());
+                let mut line = match item.get_link() {
+                    Some(link) => format!("- [{title}](<{link}>)"),
+                    None => format!("- {title}"),
+                };
+                if let Some(summary) = summary(item) {
+                    line.push_str(&format!(" — {}", escape_markdown(&summary)));
+                }
+                lines.push(line);
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn render_html(&self, display: &DisplayConfig) -> String {
+        let mut html = vec![
+            "<!DOCTYPE html>".to_string(),
+            "<html><head><meta charset=\"utf-8\"><title>rrss digest</title></head><body>"
+                .to_string(),
+            format!("<h1>{}</h1>", encode_text(&self.heading(display))),
+        ];
+        for channel in self.channels_to_show() {
+            html.push(format!("<h2>{}</h2>", encode_text(&channel.name)));
+            if let Some(error) = &channel.error {
+                html.push(format!(
+                    "<p><em>Could not fetch: {}</em></p>",
+                    encode_text(error)
+                ));
+            }
+            if channel.items.is_empty() {
+                continue;
+            }
+            html.push("<ul>".to_string());
+            for item in channel.items.iter() {
+                let title = encode_text(&item.get_title()).to_string();
+                let mut entry = match item.get_link() {
+                    Some(link) => format!(
+                        "<li><a href=\"{}\">{title}</a>",
+                        encode_double_quoted_attribute(&link)
+                    ),
+                    None => format!("<li>{title}"),
+                };
+                if let Some(summary) = summary(item) {
+                    entry.push_str(&format!("<br>{}", encode_text(&summary)));
+                }
+                entry.push_str("</li>");
+                html.push(entry);
+            }
+            html.push("</ul>".to_string());
+        }
+        html.push("</body></html>".to_string());
+        html.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    pub fn test_digest_start() {
+        assert_eq!(
+            "2024-05-31T12:00:00+00:00",
+            parse_since("24h", now()).unwrap().to_rfc3339()
+        );
+        assert_eq!(
+            "2024-05-18T12:00:00+00:00",
+            parse_since("2w", now()).unwrap().to_rfc3339()
+        );
+        assert_eq!(
+            "2024-05-30T00:00:00+00:00",
+            parse_since("2024-05-30", now()).unwrap().to_rfc3339()
+        );
+        assert!(parse_since("yesterday-ish", now()).is_err());
+        assert!(parse_since("9999999999999999w", now()).is_err());
+        assert!(parse_since("999999999999h", now()).is_err());
+
+        let last_run = parse_date("2024-06-01T08:00:00Z");
+        assert_eq!(last_run, digest_start(None, last_run, now()).ok());
+        assert_eq!(
+            "2024-05-31T12:00:00+00:00",
+            digest_start(None, None, now()).unwrap().to_rfc3339()
+        );
+        assert_eq!(
+            "2024-06-01T11:30:00+00:00",
+            digest_start(Some("30m"), last_run, now())
+                .unwrap()
+                .to_rfc3339()
+        );
+    }
+
+    #[test]
+    pub fn test_render() {
+        let item = Item {
+            title: Some(vec!["Goblins & <markets>".to_string()]),
+            link: Some("https://example.com/goblins".to_string()),
+            description: Some("<p>All about   goblins</p>".to_string()),
+            pub_date: Some("2024-06-01T10:00:00Z".to_string()),
+            ..Default::default()
+        };
+        let since = parse_since("24h", now()).unwrap();
+        assert!(is_new(&item, &since, false));
+        let digest = Digest {
+            since,
+            channels: vec![
+                DigestChannel {
+                    name: "Pod".to_string(),
+                    items: vec![item],
+                    error: None,
+                },
+                DigestChannel {
+                    name: "Quiet".to_string(),
+                    ..Default::default()
+                },
+                DigestChannel {
+                    name: "Broken".to_string(),
+                    error: Some("404 Not Found".to_string()),
+                    ..Default::default()
+                },
+            ],
+        };
+        let display = DisplayConfig {
+            timezone: "utc".to_string(),
+            ..Default::default()
+        };
+
+        let text = digest.render(DigestFormat::Text, &display);
+        assert_eq!(
+            "1 new items since 2024-05-31 12:00 +00:00\n\nPod\n===\n* Goblins & <markets>\n  https://example.com/goblins\n  All about goblins\n\nBroken\n======\nCould not fetch: 404 Not Found",
+            text
+        );
+        let markdown = digest.render(DigestFormat::Markdown, &display);
+        assert!(markdown.contains(
+            "- [Goblins & \\<markets\\>](<https://example.com/goblins>) — All about goblins"
+        ));
+        let html = digest.render(DigestFormat::Html, &display);
+        assert!(html.contains(
+            "<li><a href=\"https://example.com/goblins\">Goblins &amp; &lt;markets&gt;</a><br>All about goblins</li>"
+        ));
+        assert!(!html.contains("Quiet"));
+    }
+}
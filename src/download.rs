@@ -0,0 +1,760 @@
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use reqwest::{
+    header::{HeaderValue, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_RANGE, RANGE},
+    Client, Response, StatusCode,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+
+use crate::paths::{content_disposition_filename, file_extension, unique_path};
+
+///How many downloads we run at the same time, the rest wait in the queue
+const MAX_RUNNING_DOWNLOADS: usize = 2;
+///We do not need to hear about every chunk
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+///Everything we need to know to fetch an enclosure
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadRequest {
+    pub item_id: String,
+    pub title: String,
+    pub url: String,
+    pub path: PathBuf,
+    ///What the enclosure says the size is
+    pub expected_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed(String),
+    Cancelled,
+}
+
+///A download in the queue, and how far along it is
+#[derive(Debug, Clone)]
+pub struct Download {
+    pub id: usize,
+    pub request: DownloadRequest,
+    pub status: DownloadStatus,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+    ///What we already had from an earlier attempt
+    resumed_from: u64,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Download {
+    ///Fraction done, if we know how big it is
+    pub fn ratio(&self) -> Option<f64> {
+        match (self.status.clone(), self.total) {
+            (DownloadStatus::Finished, _) => Some(1.0),
+            (_, Some(total)) if total > 0 => Some((self.downloaded as f64 / total as f64).min(1.0)),
+            _ => None,
+        }
+    }
+
+    ///Average bytes per second since we started
+    pub fn speed(&self) -> Option<f64> {
+        let started = self.started?;
+        let elapsed = self
+            .finished
+            .unwrap_or(Instant::now())
+            .duration_since(started)
+            .as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        Some(self.downloaded.saturating_sub(self.resumed_from) as f64 / elapsed)
+    }
+
+    ///Time left at the speed we have had so far
+    pub fn eta(&self) -> Option<Duration> {
+        if self.status != DownloadStatus::Running {
+            return None;
+        }
+        let remaining = self.total?.saturating_sub(self.downloaded);
+        let speed = self.speed().filter(|speed| *speed > 0.0)?;
+        Some(Duration::from_secs_f64(remaining as f64 / speed))
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.status,
+            DownloadStatus::Queued | DownloadStatus::Running
+        )
+    }
+}
+
+///What the download tasks tell the manager
+#[derive(Debug)]
+enum DownloadEvent {
+    Started {
+        id: usize,
+        total: Option<u64>,
+        resumed_from: u64,
+    },
+    Progress {
+        id: usize,
+        downloaded: u64,
+    },
+    ///The path can differ from the one asked for, if the server named the file
+    Finished {
+        id: usize,
+        path: PathBuf,
+    },
+    Failed {
+        id: usize,
+        reason: String,
+    },
+    Cancelled {
+        id: usize,
+    },
+}
+
+///Runs downloads in the background and keeps track of them
+pub struct DownloadManager {
+    pub downloads: Vec<Download>,
+    next_id: usize,
+    events_tx: UnboundedSender<DownloadEvent>,
+    events_rx: UnboundedReceiver<DownloadEvent>,
+}
+
+impl Default for DownloadManager {
+    fn default() -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        Self {
+            downloads: Vec::new(),
+            next_id: 0,
+            events_tx,
+            events_rx,
+        }
+    }
+}
+
+impl DownloadManager {
+    ///Put the download in the queue, it starts as soon as there is room.
+    ///The path gets a number added if another file or download already has it
+    pub fn enqueue(&mut self, mut request: DownloadRequest) -> usize {
+        request.path = unique_path(&request.path, |path| self.is_taken(path));
+        let id = self.next_id;
+        self.next_id += 1;
+        info!("Queueing download {id} of {}", request.url);
+        self.downloads.push(Download {
+            id,
+            request,
+            status: DownloadStatus::Queued,
+            downloaded: 0,
+            total: None,
+            resumed_from: 0,
+            started: None,
+            finished: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+        });
+        self.start_queued();
+        id
+    }
+
+    pub fn cancel(&mut self, id: usize) {
+        if let Some(download) = self.downloads.iter_mut().find(|d| d.id == id) {
+            match download.status {
+                DownloadStatus::Running => download.cancel.store(true, Ordering::Relaxed),
+                DownloadStatus::Queued => download.status = DownloadStatus::Cancelled,
+                _ => {}
+            }
+        }
+    }
+
+    ///Try again with a download that failed or was cancelled
+    pub fn retry(&mut self, id: usize) {
+        if let Some(download) = self.downloads.iter_mut().find(|d| d.id == id) {
+            if matches!(
+                download.status,
+                DownloadStatus::Failed(_) | DownloadStatus::Cancelled
+            ) {
+                download.status = DownloadStatus::Queued;
+                download.downloaded = 0;
+                download.total = None;
+                download.resumed_from = 0;
+                download.started = None;
+                download.finished = None;
+                download.cancel = Arc::new(AtomicBool::new(false));
+            }
+        }
+        self.start_queued();
+    }
+
+    ///Whether something is there already, or another download is headed there
+    fn is_taken(&self, path: &Path) -> bool {
+        path.exists()
+            || self
+                .downloads
+                .iter()
+                .any(|d| d.is_active() && d.request.path == path)
+    }
+
+    ///Whether the item has been downloaded, or tried to be, this session
+    pub fn has_item(&self, item_id: &str) -> bool {
+        self.downloads.iter().any(|d| d.request.item_id == item_id)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        !self.downloads.iter().any(|d| d.is_active())
+    }
+
+    ///Take in what the download tasks have told us and start whatever is waiting.
+    ///Returns the downloads that finished since we last looked
+    pub fn poll(&mut self) -> Vec<Download> {
+        let mut finished = Vec::new();
+        while let Ok(event) = self.events_rx.try_recv() {
+            match event {
+                DownloadEvent::Started {
+                    id,
+                    total,
+                    resumed_from,
+                } => {
+                    if let Some(download) = self.get_mut(id) {
+                        download.total = total;
+                        download.resumed_from = resumed_from;
+                        download.downloaded = resumed_from;
+                    }
+                }
+                DownloadEvent::Progress { id, downloaded } => {
+                    if let Some(download) = self.get_mut(id) {
+                        download.downloaded = downloaded;
+                    }
+                }
+                DownloadEvent::Finished { id, path } => {
+                    if let Some(download) = self.get_mut(id) {
+                        download.request.path = path;
+                        download.status = DownloadStatus::Finished;
+                        download.finished = Some(Instant::now());
+                        finished.push(download.clone());
+                    }
+                }
+                DownloadEvent::Failed { id, reason } => {
+                    if let Some(download) = self.get_mut(id) {
+                        download.status = DownloadStatus::Failed(reason);
+                        download.finished = Some(Instant::now());
+                    }
+                }
+                DownloadEvent::Cancelled { id } => {
+                    if let Some(download) = self.get_mut(id) {
+                        download.status = DownloadStatus::Cancelled;
+                        download.finished = Some(Instant::now());
+                    }
+                }
+            }
+        }
+        self.start_queued();
+        finished
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Download> {
+        self.downloads.iter_mut().find(|d| d.id == id)
+    }
+
+    fn start_queued(&mut self) {
+        let mut running = self
+            .downloads
+            .iter()
+            .filter(|d| d.status == DownloadStatus::Running)
+            .count();
+        for download in self.downloads.iter_mut() {
+            if running >= MAX_RUNNING_DOWNLOADS {
+                break;
+            }
+            if download.status != DownloadStatus::Queued {
+                continue;
+            }
+            download.status = DownloadStatus::Running;
+            download.started = Some(Instant::now());
+            running += 1;
+            let (id, request, cancel, tx) = (
+                download.id,
+                download.request.clone(),
+                download.cancel.clone(),
+                self.events_tx.clone(),
+            );
+            tokio::spawn(async move {
+                let event = match stream_to_file(id, &request, &cancel, &tx).await {
+                    Ok(Some(path)) => DownloadEvent::Finished { id, path },
+                    Ok(None) => {
+                        //do not leave half a file lying around
+                        let _ = tokio::fs::remove_file(part_path(&request.path)).await;
+                        DownloadEvent::Cancelled { id }
+                    }
+                    Err(e) => {
+                        //we keep the .part file, so a retry can pick up where we stopped
+                        error!("Download of {} failed: {e}", request.url);
+                        DownloadEvent::Failed {
+                            id,
+                            reason: e.to_string(),
+                        }
+                    }
+                };
+                //if the manager is gone there is nobody left to tell
+                let _ = tx.send(event);
+            });
+        }
+    }
+}
+
+///Where the download lives until it is complete
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+///The full size from a Content-Range header like "bytes 100-199/200"
+fn content_range_total(content_range: &str) -> Option<u64> {
+    content_range.rsplit('/').next()?.trim().parse().ok()
+}
+
+///Make sure we got the whole thing
+fn check_size(downloaded: u64, expected: Option<u64>) -> Result<()> {
+    match expected {
+        Some(expected) if downloaded != expected => Err(anyhow!(
+            "Downloaded {downloaded} bytes but expected {expected}"
+        )),
+        _ => Ok(()),
+    }
+}
+
+///Ask the server whether we can fetch just the end of the file
+async fn accepts_ranges(client: &Client, url: &str) -> bool {
+    match client.head(url).send().await {
+        Ok(response) => response
+            .headers()
+            .get(ACCEPT_RANGES)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.trim().eq_ignore_ascii_case("bytes")),
+        Err(e) => {
+            info!("HEAD of {url} failed, not resuming: {e}");
+            false
+        }
+    }
+}
+
+///Write the response to disk a chunk at a time, so we never hold the whole thing in memory.
+///We write to a .part file first, and pick up where it left off if the server lets us.
+///Returns where the file ended up, or None if we were cancelled along the way
+async fn stream_to_file(
+    id: usize,
+    request: &DownloadRequest,
+    cancel: &AtomicBool,
+    tx: &UnboundedSender<DownloadEvent>,
+) -> Result<Option<PathBuf>> {
+    let client = Client::new();
+    let part_path = part_path(&request.path);
+    let existing = match tokio::fs::metadata(&part_path).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+    let resume = existing > 0 && accepts_ranges(&client, &request.url).await;
+
+    let mut get = client.get(&request.url);
+    if resume {
+        info!("Resuming download {id} from byte {existing}");
+        get = get.header(RANGE, format!("bytes={existing}-"));
+    }
+    let mut response = get.send().await?;
+    let status = response.status();
+    if resume && status == StatusCode::RANGE_NOT_SATISFIABLE {
+        return finish_complete_part(request, &part_path, existing, &response).await;
+    }
+    if !status.is_success() {
+        let text = response.text().await?;
+        return Err(anyhow!(
+            "Unable to download pod? status={}, message={}",
+            status,
+            text
+        ));
+    }
+
+    //a 200 to a range request means the server is sending the whole file again
+    let resumed_from = if status == StatusCode::PARTIAL_CONTENT {
+        existing
+    } else {
+        0
+    };
+    let server_total = if resumed_from > 0 {
+        response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .and_then(content_range_total)
+            .or(response.content_length().map(|len| len + resumed_from))
+    } else {
+        response.content_length()
+    };
+    if let (Some(server_total), Some(enclosure_size)) = (server_total, request.expected_size) {
+        if server_total != enclosure_size {
+            warn!("Feed says {enclosure_size} bytes but the server says {server_total}, going with the server");
+        }
+    }
+    let expected = server_total.or(request.expected_size);
+    let final_path = final_path(&request.path, response.headers().get(CONTENT_DISPOSITION));
+    let _ = tx.send(DownloadEvent::Started {
+        id,
+        total: expected,
+        resumed_from,
+    });
+
+    if let Some(parent) = request.path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = if resumed_from > 0 {
+        OpenOptions::new().append(true).open(&part_path).await?
+    } else {
+        File::create(&part_path).await?
+    };
+    let mut downloaded = resumed_from;
+    let mut last_progress = Instant::now();
+    while let Some(chunk) = response.chunk().await? {
+        if cancel.load(Ordering::Relaxed) {
+            info!("Download {id} cancelled");
+            return Ok(None);
+        }
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            let _ = tx.send(DownloadEvent::Progress { id, downloaded });
+            last_progress = Instant::now();
+        }
+    }
+    file.flush().await?;
+    let _ = tx.send(DownloadEvent::Progress { id, downloaded });
+
+    //only the server's word is good enough to fail on, feeds get the length wrong all the time
+    match (server_total, request.expected_size) {
+        (Some(_), _) => check_size(downloaded, server_total)?,
+        (None, Some(enclosure_size)) if downloaded != enclosure_size => {
+            warn!("Feed says {enclosure_size} bytes but we got {downloaded}, keeping it")
+        }
+        _ => {}
+    }
+    tokio::fs::rename(&part_path, &final_path).await?;
+    Ok(Some(final_path))
+}
+
+///Asking for the rest of a .part file that is already whole gets a 416.
+///When the server's size agrees with the file we are done, otherwise the file is no good
+async fn finish_complete_part(
+    request: &DownloadRequest,
+    part_path: &Path,
+    existing: u64,
+    response: &Response,
+) -> Result<Option<PathBuf>> {
+    let server_total = response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(content_range_total);
+    if server_total != Some(existing) {
+        tokio::fs::remove_file(part_path).await?;
+        return Err(anyhow!(
+            "The partial download does not match the file on the server, starting over on retry"
+        ));
+    }
+    info!("{} was already complete", part_path.display());
+    let final_path = final_path(&request.path, response.headers().get(CONTENT_DISPOSITION));
+    tokio::fs::rename(part_path, &final_path).await?;
+    Ok(Some(final_path))
+}
+
+///If we could not tell the extension up front, the server may tell us through the file name it gives
+fn final_path(path: &Path, content_disposition: Option<&HeaderValue>) -> PathBuf {
+    if path.extension().is_some() {
+        return path.to_path_buf();
+    }
+    let ext = content_disposition
+        .and_then(|header| header.to_str().ok())
+        .and_then(content_disposition_filename)
+        .and_then(|file_name| file_extension(&file_name));
+    match ext {
+        Some(ext) => unique_path(&path.with_extension(ext), |path| path.exists()),
+        None => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::mock_server;
+    use std::sync::Mutex;
+
+    ///Serve the body to every request that comes in, honouring ranges if we are told to.
+    ///Returns the url to ask for it, and the requests that came in
+    async fn serve(body: Vec<u8>, ranges: bool) -> (String, Arc<Mutex<Vec<String>>>) {
+        let (addr, requests) = mock_server(move |request: &str| {
+            let range_start = request
+                .lines()
+                .find_map(|line| line.strip_prefix("range: bytes="))
+                .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok())
+                .filter(|_| ranges);
+            let accept_ranges = if ranges {
+                "Accept-Ranges: bytes\r\n"
+            } else {
+                ""
+            };
+            let (status, content) = match range_start {
+                Some(start) if start >= body.len() => (
+                    format!("416 Range Not Satisfiable\r\nContent-Range: bytes */{}", body.len()),
+                    &body[..0],
+                ),
+                Some(start) => (
+                    format!(
+                        "206 Partial Content\r\nContent-Range: bytes {start}-{}/{}",
+                        body.len() - 1,
+                        body.len()
+                    ),
+                    &body[start..],
+                ),
+                None => ("200 OK".to_string(), &body[..]),
+            };
+            let mut response = format!(
+                "HTTP/1.1 {status}\r\n{accept_ranges}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                content.len()
+            )
+            .into_bytes();
+            if !request.starts_with("HEAD") {
+                response.extend_from_slice(content);
+            }
+            response
+        })
+        .await;
+        (format!("http://{addr}/episode.mp3"), requests)
+    }
+
+    fn request(url: &str, path: &Path) -> DownloadRequest {
+        DownloadRequest {
+            item_id: "guid-1".to_string(),
+            title: "Episode".to_string(),
+            url: url.to_string(),
+            path: path.to_path_buf(),
+            expected_size: None,
+        }
+    }
+
+    fn test_body() -> Vec<u8> {
+        (0..200_000).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn wait_until_idle(manager: &mut DownloadManager) -> Vec<Download> {
+        let mut finished = Vec::new();
+        for _ in 0..100 {
+            finished.extend(manager.poll());
+            if manager.is_idle() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        finished
+    }
+
+    #[test]
+    pub fn test_content_range_total() {
+        assert_eq!(Some(200), content_range_total("bytes 100-199/200"));
+        assert_eq!(None, content_range_total("bytes 100-199/*"));
+    }
+
+    #[test]
+    pub fn test_final_path() {
+        let header = HeaderValue::from_static(r#"attachment; filename="episode.m4a""#);
+        assert_eq!(
+            PathBuf::from("pods/episode.mp3"),
+            final_path(Path::new("pods/episode.mp3"), Some(&header))
+        );
+        assert_eq!(
+            PathBuf::from("pods/episode.m4a"),
+            final_path(Path::new("pods/episode"), Some(&header))
+        );
+        assert_eq!(
+            PathBuf::from("pods/episode"),
+            final_path(Path::new("pods/episode"), None)
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_enqueue_avoids_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("episode.mp3");
+        std::fs::write(&path, b"already here").unwrap();
+        let mut manager = DownloadManager::default();
+        manager.enqueue(request("http://127.0.0.1:9/episode.mp3", &path));
+        manager.enqueue(request("http://127.0.0.1:9/episode.mp3", &path));
+        assert_eq!(
+            dir.path().join("episode (1).mp3"),
+            manager.downloads[0].request.path
+        );
+        assert_eq!(
+            dir.path().join("episode (2).mp3"),
+            manager.downloads[1].request.path
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_download_streams_to_file() {
+        let body = test_body();
+        let (url, _) = serve(body.clone(), false).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pods").join("episode.mp3");
+
+        let mut manager = DownloadManager::default();
+        let id = manager.enqueue(request(&url, &path));
+        let finished = wait_until_idle(&mut manager).await;
+
+        assert_eq!(1, finished.len());
+        assert_eq!(id, finished[0].id);
+        assert_eq!(Some(1.0), finished[0].ratio());
+        assert_eq!(Some(body.len() as u64), finished[0].total);
+        assert_eq!(body, std::fs::read(&path).unwrap());
+        assert!(!part_path(&path).exists());
+    }
+
+    #[tokio::test]
+    pub async fn test_download_resumes_part_file() {
+        let body = test_body();
+        let (url, requests) = serve(body.clone(), true).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("episode.mp3");
+        std::fs::write(part_path(&path), &body[..50_000]).unwrap();
+
+        let mut manager = DownloadManager::default();
+        manager.enqueue(DownloadRequest {
+            expected_size: Some(body.len() as u64),
+            ..request(&url, &path)
+        });
+        let finished = wait_until_idle(&mut manager).await;
+
+        assert_eq!(1, finished.len());
+        assert_eq!(body, std::fs::read(&path).unwrap());
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().any(|r| r.contains("range: bytes=50000-")));
+    }
+
+    #[tokio::test]
+    pub async fn test_download_starts_over_without_ranges() {
+        let body = test_body();
+        let (url, requests) = serve(body.clone(), false).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("episode.mp3");
+        std::fs::write(part_path(&path), b"left over from some other download").unwrap();
+
+        let mut manager = DownloadManager::default();
+        manager.enqueue(request(&url, &path));
+        wait_until_idle(&mut manager).await;
+
+        assert_eq!(body, std::fs::read(&path).unwrap());
+        let requests = requests.lock().unwrap();
+        assert!(!requests.iter().any(|r| r.contains("range:")));
+    }
+
+    #[tokio::test]
+    pub async fn test_server_size_beats_feed_size() {
+        let body = test_body();
+        let (url, _) = serve(body.clone(), false).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("episode.mp3");
+
+        let mut manager = DownloadManager::default();
+        //feeds often get the enclosure length wrong, the server knows better
+        manager.enqueue(DownloadRequest {
+            expected_size: Some(body.len() as u64 + 1),
+            ..request(&url, &path)
+        });
+        wait_until_idle(&mut manager).await;
+        assert_eq!(DownloadStatus::Finished, manager.downloads[0].status);
+
+        assert!(check_size(100, Some(200)).is_err());
+        assert!(check_size(200, Some(200)).is_ok());
+        assert!(check_size(200, None).is_ok());
+    }
+
+    #[tokio::test]
+    pub async fn test_feed_size_alone_does_not_fail() {
+        let body = test_body();
+        let (addr, _) = mock_server({
+            let body = body.clone();
+            //chunked, so there is no content length to go by
+            move |_: &str| {
+                let mut response =
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n"
+                        .to_vec();
+                response.extend(format!("{:x}\r\n", body.len()).into_bytes());
+                response.extend(&body);
+                response.extend(b"\r\n0\r\n\r\n");
+                response
+            }
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("episode.mp3");
+
+        let mut manager = DownloadManager::default();
+        manager.enqueue(DownloadRequest {
+            expected_size: Some(body.len() as u64 + 1),
+            ..request(&format!("http://{addr}/episode.mp3"), &path)
+        });
+        wait_until_idle(&mut manager).await;
+        assert_eq!(DownloadStatus::Finished, manager.downloads[0].status);
+        assert_eq!(body, std::fs::read(&path).unwrap());
+    }
+
+    #[tokio::test]
+    pub async fn test_complete_part_file_is_finished() {
+        let body = test_body();
+        let (url, _) = serve(body.clone(), true).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("episode.mp3");
+        //we had it all, but went away before renaming it
+        std::fs::write(part_path(&path), &body).unwrap();
+
+        let mut manager = DownloadManager::default();
+        manager.enqueue(request(&url, &path));
+        wait_until_idle(&mut manager).await;
+        assert_eq!(DownloadStatus::Finished, manager.downloads[0].status);
+        assert_eq!(body, std::fs::read(&path).unwrap());
+        assert!(!part_path(&path).exists());
+    }
+
+    #[tokio::test]
+    pub async fn test_failed_download_can_be_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = DownloadManager::default();
+        //nothing listens on port 9 of localhost
+        let id = manager.enqueue(request(
+            "http://127.0.0.1:9/episode.mp3",
+            &dir.path().join("episode.mp3"),
+        ));
+        wait_until_idle(&mut manager).await;
+        assert!(matches!(
+            manager.downloads[0].status,
+            DownloadStatus::Failed(_)
+        ));
+
+        manager.retry(id);
+        assert!(!manager.is_idle());
+        manager.cancel(id);
+        wait_until_idle(&mut manager).await;
+        assert!(manager.is_idle());
+    }
+}
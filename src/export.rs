@@ -0,0 +1,405 @@
+use ammonia::{Builder, UrlRelative};
+use anyhow::Result;
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use log::info;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::{
+    dates::DisplayConfig,
+    model::{format_size, Channel, Item},
+    paths::sanitize,
+    state::ItemStates,
+};
+
+///How many items the river on the index page shows
+const RIVER_ITEMS: usize = 200;
+
+///Tags we let through from feed content, everything else is dropped but its text kept
+const ALLOWED_TAGS: [&str; 38] = [
+    "a",
+    "abbr",
+    "b",
+    "blockquote",
+    "br",
+    "caption",
+    "cite",
+    "code",
+    "dd",
+    "del",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+///Tags we drop along with everything inside them
+const DROPPED_TAGS: [&str; 9] = [
+    "script", "style", "iframe", "object", "embed", "form", "noscript", "template", "svg",
+];
+///Attributes we keep, by tag
+const ALLOWED_ATTRIBUTES: [(&str, &str); 5] = [
+    ("a", "href"),
+    ("a", "title"),
+    ("img", "src"),
+    ("img", "alt"),
+    ("img", "title"),
+];
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:50em;margin:auto;padding:1em;line-height:1.5;color:#222}\
+a{color:#b5651d}\
+.meta{color:#666;font-size:0.9em}\
+ul.river{list-style:none;padding:0}\
+ul.river li{margin-bottom:0.6em}\
+.unread>a{font-weight:bold}\
+img{max-width:100%;height:auto}\
+nav{margin-bottom:1em}";
+
+///What we wrote
+#[derive(Debug, PartialEq)]
+pub struct ExportSummary {
+    pub index: PathBuf,
+    pub channels: usize,
+    pub items: usize,
+}
+
+///Write a static site to the directory: a river of news as index.html,
+///a page per channel under channels/ and a page per item under items/.
+///All links are relative, so it works from a file share as well as a web server
+pub fn export_html(
+    dir: &Path,
+    channels: &[Channel],
+    item_states: &ItemStates,
+    display: &DisplayConfig,
+) -> Result<ExportSummary> {
+    fs::create_dir_all(dir.join("channels"))?;
+    fs::create_dir_all(dir.join("items"))?;
+    let mut items = 0;
+    for channel in channels {
+        let page = channel_page(channel, item_states, display);
+        fs::write(dir.join("channels").join(channel_file(channel)), page)?;
+        for item in channel.items.iter() {
+            let page = item_page(channel, item, display);
+            fs::write(dir.join("items").join(item_file(channel, item)), page)?;
+            items += 1;
+        }
+    }
+    let index = dir.join("index.html");
+    fs::write(&index, index_page(channels, item_states, display))?;
+    info!("Exported {items} items to {}", dir.display());
+    Ok(ExportSummary {
+        index,
+        channels: channels.len(),
+        items,
+    })
+}
+
+fn channel_file(channel: &Channel) -> String {
+    format!(
+        "{}-{:016x}.html",
+        slug(&channel.title),
+        fnv1a(&channel.get_link())
+    )
+}
+
+///Ids are often urls, so we hash them for a file name that is short, safe and stable
+fn item_file(channel: &Channel, item: &Item) -> String {
+    format!(
+        "{}-{:016x}.html",
+        slug(&item.get_title()),
+        fnv1a(&format!("{}|{}", channel.get_link(), item.id()))
+    )
+}
+
+fn slug(text: &str) -> String {
+    let slug: String = sanitize(text)
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    let slug = slug
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+    slug.chars().take(60).collect()
+}
+
+///Stable across runs and rust versions, unlike the std hasher
+pub fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn page(title: &str, depth: usize, body: &str) -> String {
+    let root = "../".repeat(depth);
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+<title>{}</title><style>{STYLE}</style></head>\n<body>\n\
+<nav><a href=\"{root}index.html\">All items</a></nav>\n{body}\n</body></html>\n",
+        encode_text(title)
+    )
+}
+
+fn item_date(item: &Item, display: &DisplayConfig) -> String {
+    item.published()
+        .map(|published| display.format_timestamp(&published))
+        .unwrap_or_default()
+}
+
+///One line for an item in a list, linking to its page
+fn item_entry(
+    channel: &Channel,
+    item: &Item,
+    item_states: &ItemStates,
+    display: &DisplayConfig,
+    depth: usize,
+    show_channel: bool,
+) -> String {
+    let root = "../".repeat(depth);
+    let channel_part = if show_channel {
+        format!(
+            " · <a href=\"{root}channels/{}\">{}</a>",
+            channel_file(channel),
+            encode_text(&channel.title)
+        )
+    } else {
+        String::new()
+    };
+    let unread = if item_states.is_read(&item.id()) {
+        ""
+    } else {
+        " class=\"unread\""
+    };
+    format!(
+        "<li{unread}><a href=\"{root}items/{}\">{}</a><br><span class=\"meta\">{}{channel_part}</span></li>",
+        item_file(channel, item),
+        encode_text(&item.get_title()),
+        encode_text(&item_date(item, display)),
+    )
+}
+
+fn index_page(channels: &[Channel], item_states: &ItemStates, display: &DisplayConfig) -> String {
+    let mut river: Vec<(&Channel, &Item)> = channels
+        .iter()
+        .flat_map(|channel| channel.items.iter().map(move |item| (channel, item)))
+        .collect();
+    river.sort_by_key(|(_, item)| std::cmp::Reverse(item.published()));
+    let entries = river
+        .into_iter()
+        .take(RIVER_ITEMS)
+        .map(|(channel, item)| item_entry(channel, item, item_states, display, 0, true))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let channel_links = channels
+        .iter()
+        .map(|channel| {
+            format!(
+                "<li><a href=\"channels/{}\">{}</a> <span class=\"meta\">{} items</span></li>",
+                channel_file(channel),
+                encode_text(&channel.title),
+                channel.items.len()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    page(
+        "rrss",
+        0,
+        &format!(
+            "<h1>Latest</h1>\n<ul class=\"river\">\n{entries}\n</ul>\n<h2>Channels</h2>\n<ul>\n{channel_links}\n</ul>"
+        ),
+    )
+}
+
+fn channel_page(channel: &Channel, item_states: &ItemStates, display: &DisplayConfig) -> String {
+    let entries = channel
+        .items
+        .iter()
+        .map(|item| item_entry(channel, item, item_states, display, 1, false))
+        .collect::<Vec<String>>()
+        .join("\n");
+    page(
+        &channel.title,
+        1,
+        &format!(
+            "<h1>{}</h1>\n<p class=\"meta\"><a href=\"{}\">{}</a></p>\n<ul class=\"river\">\n{entries}\n</ul>",
+            encode_text(&channel.title),
+            encode_double_quoted_attribute(&safe_url(&channel.get_link()).unwrap_or_default()),
+            encode_text(&channel.get_link()),
+        ),
+    )
+}
+
+fn item_page(channel: &Channel, item: &Item, display: &DisplayConfig) -> String {
+    let mut meta = vec![format!(
+        "<a href=\"../channels/{}\">{}</a>",
+        channel_file(channel),
+        encode_text(&channel.title)
+    )];
+    let date = item_date(item, display);
+    if !date.is_empty() {
+        meta.push(encode_text(&date).to_string());
+    }
+    if let Some(author) = item.get_author() {
+        meta.push(encode_text(&author).to_string());
+    }
+    if let Some(link) = item.get_link().and_then(|link| safe_url(&link)) {
+        meta.push(format!(
+            "<a href=\"{}\">Original</a>",
+            encode_double_quoted_attribute(&link)
+        ));
+    }
+    if let Some(enclosure) = &item.enclosure {
+        if let Some(url) = safe_url(&enclosure.url) {
+            let size = enclosure
+                .size_bytes()
+                .map(|size| format!(" ({})", format_size(size)))
+                .unwrap_or_default();
+            meta.push(format!(
+                "<a href=\"{}\">Enclosure</a>{}",
+                encode_double_quoted_attribute(&url),
+                encode_text(&size)
+            ));
+        }
+    }
+    let content = sanitize_html(&item.get_content().unwrap_or_default());
+    page(
+        &item.get_title(),
+        1,
+        &format!(
+            "<article>\n<h1>{}</h1>\n<p class=\"meta\">{}</p>\n{content}\n</article>",
+            encode_text(&item.get_title()),
+            meta.join(" · ")
+        ),
+    )
+}
+
+///Only links we are happy to have people click on
+fn safe_url(url: &str) -> Option<String> {
+    let url = html_escape::decode_html_entities(url.trim()).to_string();
+    let lower = url.to_lowercase();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+        .then_some(url)
+}
+
+///Keep the formatting of feed content but nothing that runs, loads or styles anything:
+///tags outside the allowed list go, scripts and the like go with their contents,
+///and links only keep http, https and mailto urls
+pub fn sanitize_html(html: &str) -> String {
+    static CLEANER: OnceLock<Builder<'static>> = OnceLock::new();
+    let cleaner = CLEANER.get_or_init(|| {
+        let mut tag_attributes: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for (tag, attribute) in ALLOWED_ATTRIBUTES {
+            tag_attributes.entry(tag).or_default().insert(attribute);
+        }
+        let mut cleaner = Builder::empty();
+        cleaner
+            .add_tags(ALLOWED_TAGS)
+            .clean_content_tags(HashSet::from(DROPPED_TAGS))
+            .tag_attributes(tag_attributes)
+            .generic_attributes(HashSet::new())
+            .url_schemes(HashSet::from(["http", "https", "mailto"]))
+            .url_relative(UrlRelative::Deny)
+            .link_rel(Some("noopener noreferrer"));
+        cleaner
+    });
+    cleaner.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_sanitize_html() {
+        assert_eq!(
+            "<p>Hello <strong>there</strong></p>",
+            sanitize_html("<p class=\"x\" onclick=\"evil()\">Hello <strong>there</strong></p>")
+        );
+        assert_eq!(
+            "<p>before after</p>",
+            sanitize_html("<p>before <script>alert('<p>hi</p>')</script>after</p>")
+        );
+        assert_eq!(
+            "<a href=\"https://example.com/?a=1&amp;b=2\" rel=\"noopener noreferrer\">link</a> <a rel=\"noopener noreferrer\">bad</a>",
+            sanitize_html(
+                "<a href='https://example.com/?a=1&amp;b=2' target=_blank>link</a> <a href=\"javascript:alert(1)\">bad</a>"
+            )
+        );
+        assert_eq!(
+            "<img src=\"https://example.com/a.png\" alt=\"A &quot;cat&quot;\">text",
+            sanitize_html(
+                "<img src=\"https://example.com/a.png\" alt='A \"cat\"' onerror=\"x()\"><!-- hidden -->text<iframe src=\"https://evil\"></iframe>"
+            )
+        );
+        assert_eq!("1 &lt; 2", sanitize_html("1 < 2"));
+    }
+
+    #[test]
+    pub fn test_export_html() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut channel = Channel {
+            title: "Between Two Cairns".to_string(),
+            items: vec![Item {
+                title: Some(vec!["Goblins <3".to_string()]),
+                link: Some("https://example.com/goblins".to_string()),
+                description: Some("<p>Markets<script>steal()</script></p>".to_string()),
+                pub_date: Some("2024-06-01T10:00:00Z".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        channel.set_link("https://feeds.buzzsprout.com/2042709.rss");
+        let summary = export_html(
+            dir.path(),
+            &[channel.clone()],
+            &ItemStates::default(),
+            &DisplayConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(1, summary.items);
+
+        let index = fs::read_to_string(summary.index).unwrap();
+        let item_file = item_file(&channel, &channel.items[0]);
+        assert!(item_file.starts_with("goblins-3-"));
+        assert!(index.contains(&format!("href=\"items/{item_file}\">Goblins &lt;3</a>")));
+        assert!(index.contains(&format!("href=\"channels/{}\"", channel_file(&channel))));
+
+        let item_page = fs::read_to_string(dir.path().join("items").join(item_file)).unwrap();
+        assert!(item_page.contains("<p>Markets</p>"));
+        assert!(!item_page.contains("steal"));
+        assert!(item_page.contains("href=\"../index.html\""));
+    }
+}
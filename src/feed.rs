@@ -0,0 +1,406 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use clap::ValueEnum;
+use quick_xml::{
+    events::{BytesDecl, BytesText, Event},
+    Writer,
+};
+use std::{cmp::Reverse, io};
+
+use crate::{
+    export::fnv1a,
+    model::{Channel, Item},
+    state::ItemStates,
+};
+
+type XmlWriter = Writer<Vec<u8>>;
+
+///Which kind of feed to write
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum FeedFormat {
+    ///RSS 2.0
+    #[default]
+    Rss,
+    Atom,
+}
+
+///What the feed says about itself
+#[derive(Debug, Default)]
+pub struct FeedInfo {
+    pub title: String,
+    ///Where the feed will be published, for its self link and id
+    pub link: Option<String>,
+    pub description: String,
+}
+
+///The items to put in the feed, newest first, along with the channel each came from
+pub fn select_entries<'a>(
+    channels: &'a [Channel],
+    item_states: &ItemStates,
+    starred_only: bool,
+    limit: usize,
+) -> Vec<(&'a Channel, &'a Item)> {
+    let mut entries: Vec<(&Channel, &Item)> = channels
+        .iter()
+        .flat_map(|channel| channel.items.iter().map(move |item| (channel, item)))
+        .filter(|(_, item)| !starred_only || item_states.is_starred(&item.id()))
+        .collect();
+    entries.sort_by_key(|(_, item)| Reverse(item.published()));
+    entries.truncate(limit);
+    entries
+}
+
+pub fn write_feed(
+    format: FeedFormat,
+    info: &FeedInfo,
+    entries: &[(&Channel, &Item)],
+    now: DateTime<Utc>,
+) -> String {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer
+        .write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))
+        .and_then(|()| match format {
+            FeedFormat::Rss => write_rss(&mut writer, info, entries, now),
+            FeedFormat::Atom => write_atom(&mut writer, info, entries, now),
+        })
+        .expect("Writing to memory does not fail");
+    String::from_utf8(writer.into_inner()).expect("We only write strings")
+}
+
+fn write_rss(
+    writer: &mut XmlWriter,
+    info: &FeedInfo,
+    entries: &[(&Channel, &Item)],
+    now: DateTime<Utc>,
+) -> io::Result<()> {
+    writer
+        .create_element("rss")
+        .with_attributes([
+            ("version", "2.0"),
+            ("xmlns:atom", "http://www.w3.org/2005/Atom"),
+            ("xmlns:content", "http://purl.org/rss/1.0/modules/content/"),
+            ("xmlns:dc", "http://purl.org/dc/elements/1.1/"),
+            ("xmlns:itunes", "http://www.itunes.com/dtds/podcast-1.0.dtd"),
+        ])
+        .write_inner_content(|writer| {
+            writer
+                .create_element("channel")
+                .write_inner_content(|writer| {
+                    element(writer, "title", &info.title)?;
+                    if let Some(link) = &info.link {
+                        element(writer, "link", link)?;
+                        writer
+                            .create_element("atom:link")
+                            .with_attributes([
+                                ("href", link.as_str()),
+                                ("rel", "self"),
+                                ("type", "application/rss+xml"),
+                            ])
+                            .write_empty()?;
+                    }
+                    element(writer, "description", &info.description)?;
+                    element(writer, "lastBuildDate", &now.to_rfc2822())?;
+                    element(writer, "generator", "rrss")?;
+                    for (channel, item) in entries {
+                        rss_item(writer, channel, item)?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+fn rss_item(writer: &mut XmlWriter, channel: &Channel, item: &Item) -> io::Result<()> {
+    writer
+        .create_element("item")
+        .write_inner_content(|writer| {
+            element(writer, "title", &item.get_title())?;
+            if let Some(link) = item.get_link() {
+                element(writer, "link", &link)?;
+            }
+            //the id is what readers dedupe on, so it goes out as the guid even when it was made up
+            let id = item.id();
+            let is_perma_link =
+                id.starts_with("http") && item.guid_tag().is_none_or(|guid| guid.is_perma_link());
+            writer
+                .create_element("guid")
+                .with_attribute(("isPermaLink", is_perma_link.to_string().as_str()))
+                .write_text_content(BytesText::new(&id))?;
+            if let Some(published) = item.published() {
+                element(writer, "pubDate", &published.to_rfc2822())?;
+            }
+            if let Some(author) = item.get_author() {
+                element(writer, "dc:creator", &author)?;
+            }
+            for category in item.get_categories() {
+                element(writer, "category", &category)?;
+            }
+            if let Some(description) = &item.description {
+                element(writer, "description", description)?;
+            }
+            if let Some(encoded) = item.get_encoded() {
+                element(writer, "content:encoded", &encoded)?;
+            }
+            if let Some(enclosure) = &item.enclosure {
+                writer
+                    .create_element("enclosure")
+                    .with_attributes([
+                        ("url", enclosure.url.as_str()),
+                        ("length", &enclosure.size_bytes().unwrap_or(0).to_string()),
+                        ("type", &enclosure.enclosure_type),
+                    ])
+                    .write_empty()?;
+            }
+            if let Some(duration) = item.get_duration() {
+                element(writer, "itunes:duration", &duration)?;
+            }
+            //rss wants the url of the feed the item came from, so without one there is no source
+            if let Some(feed_url) = channel.feed_url() {
+                writer
+                    .create_element("source")
+                    .with_attribute(("url", feed_url.as_str()))
+                    .write_text_content(BytesText::new(&channel.title))?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+fn write_atom(
+    writer: &mut XmlWriter,
+    info: &FeedInfo,
+    entries: &[(&Channel, &Item)],
+    now: DateTime<Utc>,
+) -> io::Result<()> {
+    let updated = entries
+        .iter()
+        .filter_map(|(_, item)| item.published())
+        .max()
+        .unwrap_or(now.fixed_offset());
+    writer
+        .create_element("feed")
+        .with_attribute(("xmlns", "http://www.w3.org/2005/Atom"))
+        .write_inner_content(|writer| {
+            element(writer, "title", &info.title)?;
+            element(writer, "subtitle", &info.description)?;
+            element(
+                writer,
+                "id",
+                &info
+                    .link
+                    .clone()
+                    .unwrap_or(atom_id(&format!("rrss|{}", info.title))),
+            )?;
+            element(writer, "updated", &updated.to_rfc3339())?;
+            element(writer, "generator", "rrss")?;
+            if let Some(link) = &info.link {
+                writer
+                    .create_element("link")
+                    .with_attributes([("rel", "self"), ("href", link.as_str())])
+                    .write_empty()?;
+            }
+            for (channel, item) in entries {
+                atom_entry(writer, channel, item, now.fixed_offset())?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+fn atom_entry(
+    writer: &mut XmlWriter,
+    channel: &Channel,
+    item: &Item,
+    now: DateTime<FixedOffset>,
+) -> io::Result<()> {
+    let published = item.published();
+    writer
+        .create_element("entry")
+        .write_inner_content(|writer| {
+            element(writer, "title", &item.get_title())?;
+            element(writer, "id", &atom_id(&item.id()))?;
+            element(writer, "updated", &published.unwrap_or(now).to_rfc3339())?;
+            if let Some(published) = published {
+                element(writer, "published", &published.to_rfc3339())?;
+            }
+            if let Some(link) = item.get_link() {
+                writer
+                    .create_element("link")
+                    .with_attributes([("rel", "alternate"), ("href", link.as_str())])
+                    .write_empty()?;
+            }
+            //atom wants an author on every entry, the channel will do when the item has none
+            let author = item.get_author().unwrap_or(channel.title.clone());
+            writer
+                .create_element("author")
+                .write_inner_content(|writer| element(writer, "name", &author))?;
+            for category in item.get_categories() {
+                writer
+                    .create_element("category")
+                    .with_attribute(("term", category.as_str()))
+                    .write_empty()?;
+            }
+            if let Some(description) = &item.description {
+                writer
+                    .create_element("summary")
+                    .with_attribute(("type", "html"))
+                    .write_text_content(BytesText::new(description))?;
+            }
+            if let Some(encoded) = item.get_encoded() {
+                writer
+                    .create_element("content")
+                    .with_attribute(("type", "html"))
+                    .write_text_content(BytesText::new(&encoded))?;
+            }
+            if let Some(enclosure) = &item.enclosure {
+                writer
+                    .create_element("link")
+                    .with_attributes([
+                        ("rel", "enclosure"),
+                        ("href", enclosure.url.as_str()),
+                        ("type", &enclosure.enclosure_type),
+                        ("length", &enclosure.size_bytes().unwrap_or(0).to_string()),
+                    ])
+                    .write_empty()?;
+            }
+            writer
+                .create_element("source")
+                .write_inner_content(|writer| {
+                    element(writer, "title", &channel.title)?;
+                    if let Some(feed_url) = channel.feed_url() {
+                        element(writer, "id", &feed_url)?;
+                        writer
+                            .create_element("link")
+                            .with_attributes([("rel", "self"), ("href", feed_url.as_str())])
+                            .write_empty()?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+///Atom ids have to be IRIs, so ids that are not get turned into a stable urn
+fn atom_id(id: &str) -> String {
+    let is_iri = ["http://", "https://", "urn:", "tag:"]
+        .iter()
+        .any(|scheme| id.starts_with(scheme));
+    if is_iri {
+        id.to_string()
+    } else {
+        format!("urn:rrss:{:016x}", fnv1a(id))
+    }
+}
+
+fn element(writer: &mut XmlWriter, name: &str, text: &str) -> io::Result<()> {
+    writer
+        .create_element(name)
+        .write_text_content(BytesText::new(text))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::parse_rss,
+        model::{Enclosure, Guid},
+    };
+    use chrono::TimeZone;
+
+    fn channel() -> Channel {
+        let mut channel = Channel {
+            title: "Between Two Cairns".to_string(),
+            items: vec![
+                Item {
+                    title: Some(vec!["Goblin markets & <more>".to_string()]),
+                    description: Some("<p>We visit the goblin market</p>".to_string()),
+                    encoded: Some(vec!["<p>And buy a lot of things</p>".to_string()]),
+                    pub_date: Some("Thu, 30 May 2024 14:30:13 -0400".to_string()),
+                    creator: Some(vec!["Yochai Gal".to_string()]),
+                    category: Some(vec!["osr".to_string(), "reviews".to_string()]),
+                    guid: Some(vec![Guid {
+                        is_perma_link: Some("false".to_string()),
+                        value: Some("Buzzsprout-15123456".to_string()),
+                    }]),
+                    enclosure: Some(Enclosure {
+                        url: "https://www.buzzsprout.com/2042709/42.mp3".to_string(),
+                        length: "52300000".to_string(),
+                        enclosure_type: "audio/mpeg".to_string(),
+                    }),
+                    duration: Some(vec!["1:02:03".to_string()]),
+                    ..Default::default()
+                },
+                Item {
+                    title: Some(vec!["Episode 41".to_string()]),
+                    link: Some("https://www.buzzsprout.com/2042709/41".to_string()),
+                    pub_date: Some("Thu, 23 May 2024 14:30:13 -0400".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        channel.set_link("https://feeds.buzzsprout.com/2042709.rss");
+        channel
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    pub fn test_rss_round_trip() {
+        let channels = [channel()];
+        let entries = select_entries(&channels, &ItemStates::default(), false, 50);
+        let info = FeedInfo {
+            title: "Reading list".to_string(),
+            link: Some("https://example.com/reading.xml".to_string()),
+            description: "What the team is reading".to_string(),
+        };
+        let xml = write_feed(FeedFormat::Rss, &info, &entries, now());
+        let parsed = parse_rss(&xml).unwrap();
+        assert_eq!("2.0", parsed.version);
+        assert_eq!("Reading list", parsed.channel.title);
+        assert_eq!(channels[0].items[0], parsed.channel.items[0]);
+        let other_item = &parsed.channel.items[1];
+        assert_eq!(channels[0].items[1].id(), other_item.id());
+        assert_eq!(channels[0].items[1].published(), other_item.published());
+        assert!(xml.contains(r#"<guid isPermaLink="false">Buzzsprout-15123456</guid>"#));
+        assert!(xml
+            .contains(r#"<guid isPermaLink="true">https://www.buzzsprout.com/2042709/41</guid>"#));
+        assert!(xml.contains(
+            r#"<source url="https://feeds.buzzsprout.com/2042709.rss">Between Two Cairns</source>"#
+        ));
+
+        //without the feed's url there is nothing to point a source at
+        let unknown = Channel {
+            link: Vec::new(),
+            ..channel()
+        };
+        let entries = [(&unknown, &unknown.items[1])];
+        let xml = write_feed(FeedFormat::Rss, &info, &entries, now());
+        assert!(parse_rss(&xml).is_ok());
+        assert!(!xml.contains("<source"));
+        assert!(!xml.contains("UNKNOWN"));
+    }
+
+    #[test]
+    pub fn test_starred_atom() {
+        let channels = [channel()];
+        let mut item_states = ItemStates::default();
+        item_states.toggle_starred("https://www.buzzsprout.com/2042709/41");
+        let entries = select_entries(&channels, &item_states, true, 50);
+        assert_eq!(1, entries.len());
+        let info = FeedInfo {
+            title: "Starred".to_string(),
+            ..Default::default()
+        };
+        let xml = write_feed(FeedFormat::Atom, &info, &entries, now());
+        assert!(xml.contains("<id>https://www.buzzsprout.com/2042709/41</id>"));
+        assert!(xml.contains("<updated>2024-05-23T14:30:13-04:00</updated>"));
+        assert!(xml.contains("<name>Between Two Cairns</name>"));
+        assert!(!xml.contains("Goblin"));
+        assert_eq!("urn:rrss:", &atom_id("Buzzsprout-15123456")[..9],);
+    }
+}
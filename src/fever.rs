@@ -0,0 +1,336 @@
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::HashMap;
+
+use crate::{
+    model::Item,
+    sync::{unix_date, Backend, Flag, RemoteFeed, RemoteItem, SyncConfig},
+};
+
+///The most items Fever hands over in one go
+const PAGE_SIZE: usize = 50;
+
+#[derive(Deserialize)]
+struct Auth {
+    auth: u8,
+}
+
+#[derive(Deserialize)]
+struct Groups {
+    groups: Vec<Group>,
+    feeds_groups: Vec<FeedsGroup>,
+}
+
+#[derive(Deserialize)]
+struct Group {
+    id: i64,
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct FeedsGroup {
+    group_id: i64,
+    ///Comma separated
+    feed_ids: String,
+}
+
+#[derive(Deserialize)]
+struct Feeds {
+    feeds: Vec<Feed>,
+}
+
+#[derive(Deserialize)]
+struct Feed {
+    id: i64,
+    title: Option<String>,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct Items {
+    #[serde(default)]
+    items: Vec<FeverItem>,
+}
+
+#[derive(Deserialize)]
+struct FeverItem {
+    id: i64,
+    feed_id: i64,
+    title: Option<String>,
+    author: Option<String>,
+    html: Option<String>,
+    url: Option<String>,
+    is_saved: u8,
+    is_read: u8,
+    created_on_time: Option<i64>,
+}
+
+///Talks to a server with the Fever api, as FreshRSS, Miniflux and Tiny Tiny RSS have.
+///Fever has no way to subscribe to a feed, so we can only take feeds from it
+pub struct FeverClient {
+    client: Client,
+    ///The api root, e.g. https://freshrss.example.com/api/fever.php
+    url: String,
+    api_key: String,
+    ///Feed urls by the server's feed id, the items only have the id
+    feed_urls: HashMap<i64, String>,
+}
+
+impl FeverClient {
+    pub fn new(config: &SyncConfig) -> Self {
+        Self {
+            client: Client::new(),
+            url: config.url.trim_end_matches('/').to_string(),
+            //the md5 of the username and password is the api key
+            api_key: format!(
+                "{:x}",
+                md5::compute(format!("{}:{}", config.username, config.password))
+            ),
+            feed_urls: HashMap::new(),
+        }
+    }
+
+    ///Every call is a post of the api key, with what we want in the query
+    async fn call<T: DeserializeOwned>(&self, query: &[(&str, String)]) -> Result<T> {
+        let response = self
+            .client
+            .post(format!("{}?api", self.url))
+            .query(query)
+            .form(&[("api_key", &self.api_key)])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Fever call failed: {}", response.status()));
+        }
+        let body = response.text().await?;
+        let auth: Auth = serde_json::from_str(&body)?;
+        if auth.auth != 1 {
+            return Err(anyhow!("Fever did not take the username and password"));
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+}
+
+impl Backend for FeverClient {
+    async fn feeds(&mut self) -> Result<Vec<RemoteFeed>> {
+        let groups: Groups = self.call(&[("groups", String::new())]).await?;
+        let group_names: HashMap<i64, String> = groups
+            .groups
+            .into_iter()
+            .map(|group| (group.id, group.title))
+            .collect();
+        let mut folders = HashMap::new();
+        for feeds_group in groups.feeds_groups {
+            for feed_id in feeds_group.feed_ids.split(',') {
+                if let (Ok(feed_id), Some(name)) = (
+                    feed_id.trim().parse::<i64>(),
+                    group_names.get(&feeds_group.group_id),
+                ) {
+                    folders.insert(feed_id, name.clone());
+                }
+            }
+        }
+        let feeds: Feeds = self.call(&[("feeds", String::new())]).await?;
+        self.feed_urls = feeds
+            .feeds
+            .iter()
+            .map(|feed| (feed.id, feed.url.clone()))
+            .collect();
+        Ok(feeds
+            .feeds
+            .into_iter()
+            .map(|feed| RemoteFeed {
+                title: feed.title.unwrap_or(feed.url.clone()),
+                folder: folders.get(&feed.id).cloned(),
+                url: feed.url,
+            })
+            .collect())
+    }
+
+    async fn subscribe(&mut self, _url: &str) -> Result<()> {
+        Err(anyhow!("Fever has no way to subscribe to a feed"))
+    }
+
+    async fn items(&mut self, limit: usize) -> Result<Vec<RemoteItem>> {
+        //a page at a time, working back from the newest
+        let mut fever_items: Vec<FeverItem> = Vec::new();
+        while fever_items.len() < limit {
+            let mut query = vec![("items", String::new())];
+            if let Some(oldest) = fever_items.iter().map(|item| item.id).min() {
+                query.push(("max_id", oldest.to_string()));
+            }
+            let page: Items = self.call(&query).await?;
+            let page_size = page.items.len();
+            fever_items.extend(page.items);
+            if page_size < PAGE_SIZE {
+                break;
+            }
+        }
+        fever_items.truncate(limit);
+        Ok(fever_items
+            .into_iter()
+            .map(|item| RemoteItem {
+                id: item.id.to_string(),
+                feed_url: self.feed_urls.get(&item.feed_id).cloned(),
+                read: item.is_read == 1,
+                starred: item.is_saved == 1,
+                item: Item {
+                    title: item.title.map(|title| vec![title]),
+                    link: item.url,
+                    description: item.html,
+                    pub_date: item.created_on_time.and_then(unix_date),
+                    author: item
+                        .author
+                        .filter(|author| !author.is_empty())
+                        .map(|author| vec![author]),
+                    ..Default::default()
+                },
+            })
+            .collect())
+    }
+
+    async fn set_flag(&mut self, ids: &[String], flag: Flag, on: bool) -> Result<()> {
+        //Fever calls starring saving
+        let state = match (flag, on) {
+            (Flag::Read, true) => "read",
+            (Flag::Read, false) => "unread",
+            (Flag::Starred, true) => "saved",
+            (Flag::Starred, false) => "unsaved",
+        };
+        for id in ids {
+            self.call::<Auth>(&[
+                ("mark", "item".to_string()),
+                ("as", state.to_string()),
+                ("id", id.clone()),
+            ])
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cli::refresh_channels,
+        config::{load_config, save_config, RssConfig, StoreFiles},
+        history::History,
+        model::Channel,
+        state::ItemStates,
+        sync::{sync, SyncApi, SyncRecord, SyncState},
+        testing::mock_server,
+    };
+
+    fn respond(request: &str) -> String {
+        let target = request.split_whitespace().nth(1).unwrap_or_default();
+        let body = if target.contains("&groups") {
+            r#"{"api_version": 3, "auth": 1, "groups": [{"id": 2, "title": "Podcasts"}],
+                "feeds_groups": [{"group_id": 2, "feed_ids": "7,8"}]}"#
+        } else if target.contains("&feeds") {
+            r#"{"api_version": 3, "auth": 1, "feeds": [
+                {"id": 7, "favicon_id": 1, "title": "Between Two Cairns", "url": "https://feeds.buzzsprout.com/2042709.rss", "site_url": "", "is_spark": 0, "last_updated_on_time": 0}],
+                "feeds_groups": [{"group_id": 2, "feed_ids": "7,8"}]}"#
+        } else if target.contains("&items") {
+            r#"{"api_version": 3, "auth": 1, "total_items": 1, "items": [
+                {"id": 501, "feed_id": 7, "title": "Episode 42", "author": "", "html": "<p>Goblins</p>",
+                 "url": "https://www.buzzsprout.com/2042709/42", "is_saved": 1, "is_read": 0, "created_on_time": 1717243200}]}"#
+        } else {
+            r#"{"api_version": 3, "auth": 1}"#
+        };
+        body.to_string()
+    }
+
+    #[tokio::test]
+    pub async fn test_sync() {
+        let (addr, requests) = mock_server(respond).await;
+        let mut client = FeverClient::new(&SyncConfig {
+            url: format!("http://{addr}/api/fever.php"),
+            username: "alice".to_string(),
+            password: "password".to_string(),
+            ..Default::default()
+        });
+        let mut cfg = RssConfig::default();
+        cfg.channels.insert(
+            "Between Two Cairns".to_string(),
+            "https://feeds.buzzsprout.com/2042709.rss".to_string(),
+        );
+        let mut channel = Channel {
+            items: vec![Item {
+                link: Some("https://www.buzzsprout.com/2042709/42".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        channel.set_link("https://feeds.buzzsprout.com/2042709.rss");
+        let mut history = History::default();
+        history.merge(&channel);
+        let mut item_states = ItemStates::default();
+        item_states.set_read("https://www.buzzsprout.com/2042709/42", true);
+        let mut sync_state = SyncState::default();
+
+        let record = sync(
+            &mut client,
+            &mut cfg,
+            &mut history,
+            &mut item_states,
+            &mut sync_state,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            SyncRecord {
+                items_pulled: 1,
+                items_pushed: 1,
+                ..Default::default()
+            },
+            record
+        );
+        assert_eq!(
+            Some(&vec!["Between Two Cairns".to_string()]),
+            cfg.folders.get("Podcasts")
+        );
+        assert!(item_states.is_starred("https://www.buzzsprout.com/2042709/42"));
+
+        let requests = requests.lock().unwrap();
+        let api_key = format!("api_key={:x}", md5::compute("alice:password"));
+        assert!(requests.iter().all(|request| request.ends_with(&api_key)));
+        assert!(requests.iter().any(
+            |request| request.starts_with("POST /api/fever.php?api&mark=item&as=read&id=501 ")
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn test_fetch_through_server() {
+        let (addr, _) = mock_server(respond).await;
+        let dir = tempfile::tempdir().unwrap();
+        let files =
+            StoreFiles::for_config(Some(dir.path().join("rrss.toml").display().to_string()));
+        let mut cfg = RssConfig::default();
+        cfg.channels.insert(
+            "Between Two Cairns".to_string(),
+            "https://feeds.buzzsprout.com/2042709.rss".to_string(),
+        );
+        cfg.sync = Some(SyncConfig {
+            api: SyncApi::Fever,
+            url: format!("http://{addr}/api/fever.php"),
+            username: "alice".to_string(),
+            password: "password".to_string(),
+        });
+        save_config(files.config.clone(), cfg).unwrap();
+        let cfg = load_config(files.config.clone()).unwrap().unwrap();
+
+        //the feed itself is never asked for, the item comes from the server
+        let (fetched, notices) = refresh_channels(&cfg, &files, false).await.unwrap();
+        assert_eq!(1, fetched.len());
+        assert_eq!(1, fetched[0].unseen.len());
+        assert!(fetched[0].error.is_none());
+        assert_eq!(vec!["Synced 1 items with the server"], notices);
+        let history = History::load(files.history.clone()).unwrap();
+        assert_eq!(
+            "Episode 42",
+            history.items_for("https://feeds.buzzsprout.com/2042709.rss")[0].get_title()
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use anyhow::{anyhow, Result};
+use reqwest::{
+    header::{HeaderValue, AUTHORIZATION},
+    Client, RequestBuilder,
+};
+use serde::Deserialize;
+
+use crate::{
+    model::{Enclosure, Item},
+    sync::{unix_date, Backend, Flag, RemoteFeed, RemoteItem, SyncConfig},
+};
+
+const READ_TAG: &str = "user/-/state/com.google/read";
+const STARRED_TAG: &str = "user/-/state/com.google/starred";
+const READING_LIST: &str = "user/-/state/com.google/reading-list";
+
+#[derive(Deserialize)]
+struct SubscriptionList {
+    subscriptions: Vec<SubscriptionJson>,
+}
+
+#[derive(Deserialize)]
+struct SubscriptionJson {
+    id: String,
+    title: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    categories: Vec<Category>,
+}
+
+#[derive(Deserialize)]
+struct Category {
+    label: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamContents {
+    #[serde(default)]
+    items: Vec<StreamItem>,
+}
+
+#[derive(Deserialize)]
+struct StreamItem {
+    id: String,
+    title: Option<String>,
+    author: Option<String>,
+    published: Option<i64>,
+    summary: Option<Content>,
+    content: Option<Content>,
+    #[serde(default)]
+    canonical: Vec<Href>,
+    #[serde(default)]
+    alternate: Vec<Href>,
+    #[serde(default)]
+    enclosure: Vec<Href>,
+    origin: Option<Origin>,
+    #[serde(default)]
+    categories: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Content {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct Href {
+    href: String,
+    #[serde(rename = "type")]
+    href_type: Option<String>,
+    length: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Origin {
+    #[serde(rename = "streamId")]
+    stream_id: String,
+}
+
+impl StreamItem {
+    fn into_remote(self) -> RemoteItem {
+        let has_tag = |tag: &str| {
+            self.categories
+                .iter()
+                .any(|category| category.ends_with(&format!("/state/com.google/{tag}")))
+        };
+        let (read, starred) = (has_tag("read"), has_tag("starred"));
+        //the summary is all some servers send, others send both
+        let (description, encoded) = match (self.summary, self.content) {
+            (Some(summary), Some(content)) => (Some(summary.content), Some(content.content)),
+            (summary, content) => (summary.or(content).map(|c| c.content), None),
+        };
+        let item = Item {
+            title: self.title.map(|title| vec![title]),
+            link: self
+                .canonical
+                .into_iter()
+                .chain(self.alternate)
+                .map(|href| href.href)
+                .next(),
+            description,
+            encoded: encoded.map(|encoded| vec![encoded]),
+            pub_date: self.published.and_then(unix_date),
+            author: self.author.map(|author| vec![author]),
+            enclosure: self.enclosure.into_iter().next().map(|href| Enclosure {
+                url: href.href,
+                length: href.length.unwrap_or("0".to_string()),
+                enclosure_type: href.href_type.unwrap_or_default(),
+            }),
+            ..Default::default()
+        };
+        RemoteItem {
+            id: self.id,
+            feed_url: self
+                .origin
+                .and_then(|origin| origin.stream_id.strip_prefix("feed/").map(str::to_string)),
+            item,
+            read,
+            starred,
+        }
+    }
+}
+
+///Talks to a server with the Google Reader api, as FreshRSS, Miniflux and Inoreader have
+pub struct GReaderClient {
+    client: Client,
+    url: String,
+    auth: String,
+    ///Needed for anything that changes something, we get it the first time we need it
+    token: Option<String>,
+}
+
+impl GReaderClient {
+    pub async fn login(config: &SyncConfig) -> Result<Self> {
+        let url = config.url.trim_end_matches('/').to_string();
+        let client = Client::new();
+        let response = client
+            .post(format!("{url}/accounts/ClientLogin"))
+            .form(&[
+                ("Email", config.username.as_str()),
+                ("Passwd", config.password.as_str()),
+            ])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Login to {url} failed: {}", response.status()));
+        }
+        let body = response.text().await?;
+        let auth = body
+            .lines()
+            .find_map(|line| line.strip_prefix("Auth="))
+            .ok_or(anyhow!("Login to {url} gave no auth token"))?
+            .trim()
+            .to_string();
+        Ok(Self {
+            client,
+            url,
+            auth,
+            token: None,
+        })
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> Result<RequestBuilder> {
+        let auth = HeaderValue::from_str(&format!("GoogleLogin auth={}", self.auth))?;
+        Ok(request.header(AUTHORIZATION, auth))
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> Result<String> {
+        let request = self.client.get(format!("{}{path}", self.url)).query(query);
+        let response = self.authorized(request)?.send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("GET {path} failed: {}", response.status()));
+        }
+        Ok(response.text().await?)
+    }
+
+    async fn post(&mut self, path: &str, form: &[(&str, String)]) -> Result<String> {
+        let token = match &self.token {
+            Some(token) => token.clone(),
+            None => {
+                let token = self
+                    .get("/reader/api/0/token", &[])
+                    .await?
+                    .trim()
+                    .to_string();
+                self.token = Some(token.clone());
+                token
+            }
+        };
+        let mut form = form.to_vec();
+        form.push(("T", token));
+        let request = self.client.post(format!("{}{path}", self.url)).form(&form);
+        let response = self.authorized(request)?.send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("POST {path} failed: {}", response.status()));
+        }
+        Ok(response.text().await?)
+    }
+}
+
+impl Backend for GReaderClient {
+    async fn feeds(&mut self) -> Result<Vec<RemoteFeed>> {
+        let body = self
+            .get(
+                "/reader/api/0/subscription/list",
+                &[("output", "json".to_string())],
+            )
+            .await?;
+        let list: SubscriptionList = serde_json::from_str(&body)?;
+        Ok(list
+            .subscriptions
+            .into_iter()
+            .filter_map(|subscription| {
+                //the id is feed/ and the url, for servers that leave the url out
+                let url = subscription
+                    .url
+                    .or(subscription.id.strip_prefix("feed/").map(str::to_string))?;
+                Some(RemoteFeed {
+                    title: subscription.title.unwrap_or(url.clone()),
+                    url,
+                    folder: subscription
+                        .categories
+                        .into_iter()
+                        .find_map(|category| category.label),
+                })
+            })
+            .collect())
+    }
+
+    async fn subscribe(&mut self, url: &str) -> Result<()> {
+        self.post(
+            "/reader/api/0/subscription/quickadd",
+            &[("quickadd", url.to_string())],
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn items(&mut self, limit: usize) -> Result<Vec<RemoteItem>> {
+        let body = self
+            .get(
+                &format!("/reader/api/0/stream/contents/{READING_LIST}"),
+                &[("n", limit.to_string()), ("output", "json".to_string())],
+            )
+            .await?;
+        let contents: StreamContents = serde_json::from_str(&body)?;
+        Ok(contents
+            .items
+            .into_iter()
+            .map(StreamItem::into_remote)
+            .collect())
+    }
+
+    async fn set_flag(&mut self, ids: &[String], flag: Flag, on: bool) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let tag = match flag {
+            Flag::Read => READ_TAG,
+            Flag::Starred => STARRED_TAG,
+        };
+        let mut form: Vec<(&str, String)> = ids.iter().map(|id| ("i", id.clone())).collect();
+        form.push((if on { "a" } else { "r" }, tag.to_string()));
+        self.post("/reader/api/0/edit-tag", &form).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::RssConfig,
+        history::History,
+        model::Channel,
+        state::ItemStates,
+        sync::{sync, SyncRecord, SyncState},
+        testing::mock_server,
+    };
+
+    const SUBSCRIPTIONS: &str = r#"{"subscriptions": [
+        {"id": "feed/https://feeds.buzzsprout.com/2042709.rss", "title": "Between Two Cairns", "url": "https://feeds.buzzsprout.com/2042709.rss", "categories": []},
+        {"id": "feed/http://feeds.libsyn.com/103241/rss", "title": "Fear of a Black Dragon",
+         "categories": [{"id": "user/-/label/Podcasts", "label": "Podcasts"}]}
+    ]}"#;
+
+    const CONTENTS: &str = r#"{"items": [
+        {"id": "tag:google.com,2005:reader/item/0000000000000041", "title": "Episode 41",
+         "canonical": [{"href": "https://www.buzzsprout.com/2042709/41"}],
+         "origin": {"streamId": "feed/https://feeds.buzzsprout.com/2042709.rss"},
+         "categories": ["user/-/state/com.google/reading-list", "user/-/state/com.google/read"]},
+        {"id": "tag:google.com,2005:reader/item/0000000000000042", "title": "Episode 42",
+         "alternate": [{"href": "https://www.buzzsprout.com/2042709/42"}],
+         "origin": {"streamId": "feed/https://feeds.buzzsprout.com/2042709.rss"},
+         "categories": ["user/-/state/com.google/reading-list", "user/-/state/com.google/starred"]},
+        {"id": "tag:google.com,2005:reader/item/0000000000000099", "title": "Lair of the lich",
+         "published": 1717243200, "author": "Jason",
+         "summary": {"content": "<p>A dungeon</p>"},
+         "alternate": [{"href": "https://fearofablackdragon.libsyn.com/lair"}],
+         "origin": {"streamId": "feed/http://feeds.libsyn.com/103241/rss"},
+         "categories": ["user/1000/state/com.google/read"]}
+    ]}"#;
+
+    ///Knows just enough of the api to sync against
+    fn respond(request: &str) -> String {
+        let target = request.split_whitespace().nth(1).unwrap_or_default();
+        let body = if target.contains("/accounts/ClientLogin") {
+            "SID=none\nLSID=none\nAuth=secret\n"
+        } else if target.contains("/reader/api/0/token") {
+            "edit-token\n"
+        } else if target.contains("/reader/api/0/subscription/list") {
+            SUBSCRIPTIONS
+        } else if target.contains("/reader/api/0/stream/contents") {
+            CONTENTS
+        } else {
+            "OK"
+        };
+        body.to_string()
+    }
+
+    #[tokio::test]
+    pub async fn test_sync() {
+        let (addr, requests) = mock_server(respond).await;
+        let mut client = GReaderClient::login(&SyncConfig {
+            url: format!("http://{addr}/api/greader.php/"),
+            username: "alice".to_string(),
+            password: "api-password".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let mut cfg = RssConfig::default();
+        cfg.channels.insert(
+            "Between Two Cairns".to_string(),
+            "https://feeds.buzzsprout.com/2042709.rss".to_string(),
+        );
+        cfg.channels.insert(
+            "Local only".to_string(),
+            "https://example.org/local.rss".to_string(),
+        );
+        let mut channel = Channel {
+            items: ["41", "42"]
+                .iter()
+                .map(|episode| Item {
+                    link: Some(format!("https://www.buzzsprout.com/2042709/{episode}")),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        channel.set_link("https://feeds.buzzsprout.com/2042709.rss");
+        let mut history = History::default();
+        history.merge(&channel);
+        let mut item_states = ItemStates::default();
+        item_states.set_read("https://www.buzzsprout.com/2042709/42", true);
+        let mut sync_state = SyncState::default();
+
+        let record = sync(
+            &mut client,
+            &mut cfg,
+            &mut history,
+            &mut item_states,
+            &mut sync_state,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            SyncRecord {
+                channels_added: 1,
+                channels_pushed: 1,
+                items_added: 1,
+                items_pulled: 3,
+                items_pushed: 1,
+            },
+            record
+        );
+        assert_eq!(
+            Some(&"http://feeds.libsyn.com/103241/rss".to_string()),
+            cfg.channels.get("Fear of a Black Dragon")
+        );
+        assert_eq!(
+            Some(&vec!["Fear of a Black Dragon".to_string()]),
+            cfg.folders.get("Podcasts")
+        );
+        assert!(item_states.is_read("https://www.buzzsprout.com/2042709/41"));
+        assert!(item_states.is_starred("https://www.buzzsprout.com/2042709/42"));
+        assert!(item_states.is_read("https://www.buzzsprout.com/2042709/42"));
+        let lair = &history.items_for("http://feeds.libsyn.com/103241/rss")[0];
+        assert_eq!("Lair of the lich", lair.get_title());
+        assert_eq!(Some("Jason".to_string()), lair.get_author());
+        assert!(item_states.is_read(&lair.id()));
+        assert_eq!(3, sync_state.items.len());
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[1..]
+            .iter()
+            .all(|request| request.contains("GoogleLogin auth=secret")));
+        assert!(requests.iter().any(|request| request
+            .contains("quickadd=https%3A%2F%2Fexample.org%2Flocal.rss")
+            && request.contains("T=edit-token")));
+        let edit_tag = requests
+            .iter()
+            .find(|request| request.starts_with("POST /api/greader.php/reader/api/0/edit-tag"))
+            .unwrap();
+        assert!(edit_tag.contains("i=tag%3Agoogle.com%2C2005%3Areader%2Fitem%2F0000000000000042"));
+        assert!(edit_tag.contains("a=user%2F-%2Fstate%2Fcom.google%2Fread"));
+    }
+}
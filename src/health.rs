@@ -0,0 +1,281 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, FixedOffset};
+use log::info;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::read_to_string,
+    path::Path,
+};
+
+use crate::{
+    api::StatusError,
+    config::{with_store_lock, write_store},
+};
+
+///The wait after the first failure, doubling with each failure after it
+const BACKOFF_BASE_MINUTES: i64 = 30;
+///However often it fails, we try again at least once a day
+const BACKOFF_MAX_HOURS: i64 = 24;
+///Failing this long without a success, we call it dead
+const DEAD_AFTER_DAYS: i64 = 14;
+///How many of the latest failures we keep for the health view
+const FAILURES_KEPT: usize = 10;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub at: DateTime<FixedOffset>,
+    pub error: String,
+}
+
+///How fetching a feed has been going
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FeedHealth {
+    pub consecutive_failures: u32,
+    pub last_success: Option<DateTime<FixedOffset>>,
+    ///The first of the current run of failures
+    pub failing_since: Option<DateTime<FixedOffset>>,
+    pub last_error: Option<String>,
+    ///The server says the feed is gone, so we stop asking until told otherwise
+    pub disabled: bool,
+    ///The latest failures, oldest first
+    pub failures: Vec<Failure>,
+}
+
+impl FeedHealth {
+    ///When we next try a failing feed, None if it is not failing
+    pub fn next_attempt(&self) -> Option<DateTime<FixedOffset>> {
+        let last_failure = self.failures.last()?.at;
+        if self.consecutive_failures == 0 {
+            return None;
+        }
+        let doublings = (self.consecutive_failures - 1).min(16);
+        let wait = Duration::minutes(BACKOFF_BASE_MINUTES * 2_i64.pow(doublings))
+            .min(Duration::hours(BACKOFF_MAX_HOURS));
+        Some(last_failure + wait)
+    }
+
+    pub fn is_dead(&self, now: DateTime<FixedOffset>) -> bool {
+        self.failing_since
+            .is_some_and(|since| now - since >= Duration::days(DEAD_AFTER_DAYS))
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.disabled || self.consecutive_failures > 0
+    }
+
+    ///One word for the health view and records
+    pub fn status(&self, now: DateTime<FixedOffset>) -> &'static str {
+        if self.disabled {
+            "disabled"
+        } else if self.is_dead(now) {
+            "dead"
+        } else if self.consecutive_failures > 0 {
+            "failing"
+        } else {
+            "ok"
+        }
+    }
+}
+
+///Feed health, keyed on the feed url
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct Health {
+    #[serde(default)]
+    pub feeds: HashMap<String, FeedHealth>,
+    ///Feed urls we changed since loading, which are all `save_changes` writes
+    #[serde(skip)]
+    changed: HashSet<String>,
+}
+
+fn health_file(path: Option<String>) -> String {
+    path.unwrap_or(".rrss_health.toml".to_string())
+}
+
+///The server telling us the feed is gone for good
+pub fn is_gone(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<StatusError>()
+        .is_some_and(|status| status.0 == StatusCode::GONE)
+}
+
+impl Health {
+    ///Load the feed health from file, if the file exists
+    ///If no file passed, will default to checking for './.rrss_health.toml'
+    pub fn load(path: Option<String>) -> Result<Health> {
+        let health_file = health_file(path);
+        if !Path::new(&health_file).exists() {
+            info!("No feed health found at {health_file}");
+            return Ok(Health::default());
+        }
+        let toml_str = read_to_string(health_file)?;
+        Ok(toml::from_str(&toml_str)?)
+    }
+
+    pub fn save(&self, path: Option<String>) -> Result<()> {
+        let health_file = health_file(path);
+        info!("Saving feed health to {health_file}");
+        write_store(&health_file, &toml::to_string(self)?)
+    }
+
+    ///Change the feed health on file, holding a lock so nobody else writes in between.
+    ///Returns the feed health as it is now
+    pub fn update<F: FnOnce(&mut Health)>(path: Option<String>, change: F) -> Result<Health> {
+        let health_file = health_file(path);
+        with_store_lock(&health_file, || {
+            let mut health = Health::load(Some(health_file.clone()))?;
+            change(&mut health);
+            health.save(Some(health_file.clone()))?;
+            health.changed.clear();
+            Ok(health)
+        })
+    }
+
+    ///Write what we changed over what is on file, and pick up whatever others changed meanwhile
+    pub fn save_changes(&mut self, path: Option<String>) -> Result<()> {
+        if self.changed.is_empty() {
+            return Ok(());
+        }
+        let updated = Health::update(path, |on_file| {
+            for url in self.changed.iter() {
+                match self.feeds.get(url) {
+                    Some(health) => {
+                        on_file.feeds.insert(url.clone(), health.clone());
+                    }
+                    None => {
+                        on_file.feeds.remove(url);
+                    }
+                }
+            }
+        })?;
+        *self = updated;
+        Ok(())
+    }
+
+    ///The health of the feed to change, which `save_changes` will then write
+    fn health_mut(&mut self, url: &str) -> &mut FeedHealth {
+        self.changed.insert(url.to_string());
+        self.feeds.entry(url.to_string()).or_default()
+    }
+
+    pub fn get(&self, url: &str) -> Option<&FeedHealth> {
+        self.feeds.get(url)
+    }
+
+    pub fn record_success(&mut self, url: &str, now: DateTime<FixedOffset>) {
+        let health = self.health_mut(url);
+        health.consecutive_failures = 0;
+        health.last_success = Some(now);
+        health.failing_since = None;
+        health.last_error = None;
+        health.disabled = false;
+    }
+
+    pub fn record_failure(&mut self, url: &str, error: &anyhow::Error, now: DateTime<FixedOffset>) {
+        let health = self.health_mut(url);
+        health.consecutive_failures += 1;
+        health.failing_since.get_or_insert(now);
+        health.last_error = Some(error.to_string());
+        if is_gone(error) {
+            info!("{url} is gone, disabling it");
+            health.disabled = true;
+        }
+        health.failures.push(Failure {
+            at: now,
+            error: error.to_string(),
+        });
+        let excess = health.failures.len().saturating_sub(FAILURES_KEPT);
+        health.failures.drain(..excess);
+    }
+
+    ///Why we should leave the feed alone for now, if we should
+    pub fn skip_reason(&self, url: &str, now: DateTime<FixedOffset>) -> Option<String> {
+        let health = self.feeds.get(url)?;
+        if health.disabled {
+            return Some("Disabled, the feed is gone".to_string());
+        }
+        let next_attempt = health.next_attempt().filter(|next| *next > now)?;
+        Some(format!(
+            "Failed {} times in a row, trying again after {}",
+            health.consecutive_failures,
+            next_attempt.to_rfc3339()
+        ))
+    }
+
+    ///Forget the failures, so the feed is fetched again next time
+    pub fn reset(&mut self, url: &str) {
+        if let Some(health) = self.feeds.get_mut(url) {
+            self.changed.insert(url.to_string());
+            *health = FeedHealth {
+                last_success: health.last_success,
+                ..Default::default()
+            };
+        }
+    }
+
+    ///Keep the health of a feed that has moved to a new url
+    pub fn move_feed(&mut self, old_url: &str, new_url: &str) {
+        if let Some(health) = self.feeds.remove(old_url) {
+            self.changed.insert(old_url.to_string());
+            self.changed.insert(new_url.to_string());
+            self.feeds.entry(new_url.to_string()).or_insert(health);
+        }
+    }
+
+    ///Forget about the feed altogether
+    pub fn remove(&mut self, url: &str) {
+        self.changed.insert(url.to_string());
+        self.feeds.remove(url);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    pub fn test_backoff() {
+        let url = "https://feeds.buzzsprout.com/2042709.rss";
+        let start = DateTime::parse_from_rfc3339("2024-06-01T12:00:00+00:00").unwrap();
+        let mut health = Health::default();
+        health.record_failure(url, &anyhow!("connection refused"), start);
+        assert!(health.skip_reason(url, start).is_some());
+        assert!(health
+            .skip_reason(url, start + Duration::minutes(30))
+            .is_none());
+
+        //the wait doubles, up to a day
+        health.record_failure(url, &anyhow!("connection refused"), start);
+        assert_eq!(
+            Some(start + Duration::hours(1)),
+            health.get(url).unwrap().next_attempt()
+        );
+        for _ in 0..10 {
+            health.record_failure(url, &anyhow!("connection refused"), start);
+        }
+        let feed_health = health.get(url).unwrap();
+        assert_eq!(
+            Some(start + Duration::hours(24)),
+            feed_health.next_attempt()
+        );
+        assert_eq!(FAILURES_KEPT, feed_health.failures.len());
+        assert_eq!("failing", feed_health.status(start));
+        assert_eq!("dead", feed_health.status(start + Duration::days(14)));
+
+        health.record_success(url, start + Duration::days(1));
+        assert!(!health.get(url).unwrap().is_broken());
+        assert!(health.skip_reason(url, start).is_none());
+
+        //gone is gone, however long we wait
+        health.record_failure(url, &StatusError(StatusCode::GONE).into(), start);
+        assert!(health.get(url).unwrap().disabled);
+        assert!(health
+            .skip_reason(url, start + Duration::days(30))
+            .is_some());
+        health.reset(url);
+        assert!(health.skip_reason(url, start).is_none());
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::Result;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::read_to_string,
+    path::Path,
+};
+
+use crate::{
+    config::{with_store_lock, write_store},
+    model::{Channel, Item},
+};
+
+///We do not want the history file growing forever
+const MAX_ITEMS_PER_CHANNEL: usize = 500;
+
+///Every item we have seen, so that things which have dropped off the feed stay readable
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct History {
+    ///channel url to items map, newest items first
+    #[serde(default)]
+    pub channels: HashMap<String, Vec<Item>>,
+    ///Channel urls we changed since loading, which are all `save_changes` writes
+    #[serde(skip)]
+    changed: HashSet<String>,
+}
+
+fn history_file(path: Option<String>) -> String {
+    path.unwrap_or(".rrss_history.toml".to_string())
+}
+
+impl History {
+    ///Load the history from file, if the file exists
+    ///If no file passed, will default to checking for './.rrss_history.toml'
+    pub fn load(path: Option<String>) -> Result<History> {
+        let history_file = history_file(path);
+        if !Path::new(&history_file).exists() {
+            info!("No history found at {history_file}");
+            return Ok(History::default());
+        }
+        let toml_str = read_to_string(history_file)?;
+        let history: History = toml::from_str(&toml_str)?;
+        Ok(history)
+    }
+
+    ///Write the history over the file, so a reader never sees half of it.
+    ///The tui, daemon and command line all fetch, so most want `update` or `save_changes`
+    pub fn save(&self, path: Option<String>) -> Result<()> {
+        let history_file = history_file(path);
+        info!("Saving history to {history_file}");
+        write_store(&history_file, &toml::to_string(self)?)
+    }
+
+    ///Change the history on file, holding a lock so nobody else writes in between.
+    ///Returns the history as it is now
+    pub fn update<F: FnOnce(&mut History)>(path: Option<String>, change: F) -> Result<History> {
+        let history_file = history_file(path);
+        with_store_lock(&history_file, || {
+            let mut history = History::load(Some(history_file.clone()))?;
+            change(&mut history);
+            history.save(Some(history_file.clone()))?;
+            history.changed.clear();
+            Ok(history)
+        })
+    }
+
+    ///Merge what we changed into what is on file, and pick up whatever others fetched meanwhile
+    pub fn save_changes(&mut self, path: Option<String>) -> Result<()> {
+        if self.changed.is_empty() {
+            return Ok(());
+        }
+        let updated = History::update(path, |on_file| {
+            for url in self.changed.iter() {
+                match self.channels.get(url) {
+                    Some(items) => {
+                        on_file.merge_items(url, items);
+                    }
+                    None => {
+                        on_file.channels.remove(url);
+                    }
+                }
+            }
+        })?;
+        *self = updated;
+        Ok(())
+    }
+
+    ///Fold freshly fetched items into what we have seen before.
+    ///Returns everything we know about the channel, newest first.
+    pub fn merge(&mut self, channel: &Channel) -> Vec<Item> {
+        self.merge_items(&channel.get_link(), &channel.items)
+    }
+
+    fn merge_items(&mut self, url: &str, items: &[Item]) -> Vec<Item> {
+        let mut merged = items.to_vec();
+        //links too, items stored before the feed had guids go by their link
+        let seen: HashSet<String> = merged
+            .iter()
+            .flat_map(|item| [Some(item.id()), item.link.clone()])
+            .flatten()
+            .collect();
+        if let Some(old_items) = self.channels.get(url) {
+            let mut old_seen = HashSet::new();
+            for item in old_items {
+                let id = item.id();
+                if !seen.contains(&id) && old_seen.insert(id) {
+                    merged.push(item.clone());
+                }
+            }
+        }
+        Channel::sort_items(&mut merged);
+        merged.truncate(MAX_ITEMS_PER_CHANNEL);
+        self.changed.insert(url.to_string());
+        self.channels.insert(url.to_string(), merged.clone());
+        merged
+    }
+
+    ///Keep the items of a channel that has moved to a new url
+    pub fn move_channel(&mut self, old_url: &str, new_url: &str) {
+        if let Some(items) = self.channels.remove(old_url) {
+            self.changed.insert(old_url.to_string());
+            self.changed.insert(new_url.to_string());
+            self.channels.entry(new_url.to_string()).or_insert(items);
+        }
+    }
+
+    ///Items we have stored for the channel at this url
+    pub fn items_for(&self, url: &str) -> Vec<Item> {
+        self.channels.get(url).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::Guid;
+
+    fn item(title: &str) -> Item {
+        Item {
+            title: Some(vec![title.to_string()]),
+            link: Some(format!("https://testing.test/{title}")),
+            ..Default::default()
+        }
+    }
+
+    fn channel(items: Vec<Item>) -> Channel {
+        let mut channel = Channel {
+            title: "Test channel".to_string(),
+            items,
+            ..Default::default()
+        };
+        channel.set_link("https://testing.test/rss");
+        channel
+    }
+
+    #[test]
+    pub fn test_load_history_from_before_repeated_tags() {
+        //guid and encoded were single values once, and the history kept them that way
+        let toml_str = r#"[[channels."https://testing.test/rss"]]
+title = ["first"]
+encoded = "<p>The whole article</p>"
+duration = "1:02:03"
+explicit = "yes"
+
+[channels."https://testing.test/rss".guid]
+isPermaLink = "false"
+"$value" = "guid-1"
+
+[channels."https://testing.test/rss".chapters]
+url = "https://testing.test/chapters.json"
+type = "application/json+chapters"
+"#;
+        let history: History = toml::from_str(toml_str).unwrap();
+        let first = &history.items_for("https://testing.test/rss")[0];
+        assert_eq!("guid-1", first.id());
+        assert_eq!(
+            Some("<p>The whole article</p>".to_string()),
+            first.get_content()
+        );
+        assert_eq!(Some(3723), first.duration_secs());
+        assert!(first.is_explicit());
+        assert!(first.get_chapters().is_some());
+    }
+
+    #[test]
+    pub fn test_merge_keeps_old_items() {
+        let mut history = History::default();
+        history.merge(&channel(vec![item("second"), item("first")]));
+        let merged = history.merge(&channel(vec![item("third"), item("second")]));
+        let titles: Vec<String> = merged.iter().map(|i| i.get_title()).collect();
+        assert_eq!(vec!["third", "second", "first"], titles);
+        assert_eq!(3, history.items_for("https://testing.test/rss").len());
+    }
+
+    #[test]
+    pub fn test_merge_dedups_on_guid() {
+        let mut history = History::default();
+        history.merge(&channel(vec![item("first")]));
+        //the feed starts giving guids, and changes the title
+        let mut renamed = item("first, now with a better title");
+        renamed.link = Some("https://testing.test/first".to_string());
+        renamed.guid = Some(vec![Guid {
+            is_perma_link: Some("false".to_string()),
+            value: Some("guid-1".to_string()),
+        }]);
+        let merged = history.merge(&channel(vec![renamed.clone()]));
+        assert_eq!(1, merged.len());
+        renamed.title = Some(vec!["and another".to_string()]);
+        renamed.link = Some("https://testing.test/elsewhere".to_string());
+        let merged = history.merge(&channel(vec![renamed]));
+        assert_eq!(1, merged.len());
+        assert_eq!("and another", merged[0].get_title());
+    }
+
+    #[test]
+    pub fn test_merge_sorts_newest_first() {
+        let mut older = item("older");
+        older.pub_date = Some("Thu, 30 May 2024 14:30:13 -0400".to_string());
+        let mut newer = item("newer");
+        newer.pub_date = Some("Fri, 31 May 2024 09:00:00 GMT".to_string());
+        let undated = item("undated");
+        let mut history = History::default();
+        let merged = history.merge(&channel(vec![undated, older, newer]));
+        let titles: Vec<String> = merged.iter().map(|i| i.get_title()).collect();
+        assert_eq!(vec!["newer", "older", "undated"], titles);
+    }
+
+    #[test]
+    pub fn test_save_changes_keeps_what_others_fetched() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_path = Some(dir.path().join("history.toml").display().to_string());
+        let mut ours = History::default();
+        ours.merge(&channel(vec![item("first")]));
+        ours.save_changes(test_path.clone()).unwrap();
+        let mut theirs = History::load(test_path.clone()).unwrap();
+        theirs.merge(&channel(vec![item("second")]));
+        let mut other = channel(vec![item("elsewhere")]);
+        other.set_link("https://testing.test/other");
+        theirs.merge(&other);
+        theirs.save_changes(test_path.clone()).unwrap();
+
+        ours.merge(&channel(vec![item("third")]));
+        ours.save_changes(test_path.clone()).unwrap();
+        let loaded = History::load(test_path).unwrap();
+        assert_eq!(3, loaded.items_for("https://testing.test/rss").len());
+        assert_eq!(1, loaded.items_for("https://testing.test/other").len());
+        assert_eq!(3, ours.items_for("https://testing.test/rss").len());
+    }
+
+    #[test]
+    pub fn test_save_and_load_history() {
+        let mut history = History::default();
+        let mut first = item("first");
+        first.guid = Some(vec![Guid {
+            is_perma_link: None,
+            value: Some("guid-1".to_string()),
+        }]);
+        history.merge(&channel(vec![first]));
+        let test_path = "test_history.toml".to_string();
+        history.save(Some(test_path.clone())).unwrap();
+        let loaded = History::load(Some(test_path.clone())).unwrap();
+        std::fs::remove_file(test_path).unwrap();
+        let items = loaded.items_for("https://testing.test/rss");
+        assert_eq!(1, items.len());
+        assert_eq!("first", items[0].get_title());
+        assert_eq!("guid-1", items[0].id());
+    }
+}
@@ -1,8 +1,13 @@
 use anyhow::{Context, Result};
 use api::fetch_rss_feed;
 use clap::{ArgGroup, Parser};
+use cli::Command;
 use color_eyre::config::HookBuilder;
-use config::load_config;
+use config::{load_config, StoreFiles};
+use daemon::is_running;
+use dates::DisplayConfig;
+use health::Health;
+use history::History;
 use log::info;
 use log::{debug, LevelFilter};
 use log4rs::{
@@ -12,24 +17,56 @@ use log4rs::{
     Config,
 };
 use model::{App, Channel};
+use paths::DownloadConfig;
+use player::PlayerConfig;
+use state::ItemStates;
 use std::collections::HashMap;
 use tui::{restore_terminal, run_app, setup_terminal};
 
 mod api;
+mod atom;
+mod autodownload;
+mod cli;
 mod config;
+mod daemon;
+mod dates;
+mod digest;
+mod download;
+mod export;
+mod feed;
+mod fever;
+mod greader;
+mod health;
+mod history;
 mod model;
+mod nextcloud;
+mod output;
+mod paths;
+mod player;
+mod podcast;
+mod search;
+mod server;
+mod state;
+mod sync;
+#[cfg(test)]
+mod testing;
 mod tui;
 
+///A 'ritten-in-Rust rss reader
 #[derive(Parser, Debug)]
 #[command(group = ArgGroup::new("exclusive").args(&["urls", "file"]))]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
     ///Optional list of urls to load up for the reader
     urls: Vec<String>,
     #[arg(short, long)]
     verbose: bool,
     ///Optional file with toml of channels to use
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     file: Option<String>,
+    ///Run without the tui
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
 ///TODO add a status line
@@ -92,9 +129,19 @@ async fn main() -> Result<()> {
 
     init_logging(args.verbose)?;
 
+    if let Some(command) = args.command {
+        return cli::run(command, args.file).await;
+    }
+
     let mut term = setup_terminal().context("Failed to setup terminal")?;
+    let files = StoreFiles::for_config(args.file.clone());
 
     let mut channels = Vec::new();
+    let mut display = DisplayConfig::default();
+    let mut download_config = DownloadConfig::default();
+    let mut player = PlayerConfig::default();
+    let mut folders = HashMap::new();
+    let mut sync = None;
     let mut loading_error_map: HashMap<String, Option<String>> = HashMap::new();
     for url in args.urls.clone() {
         //this we should make async, so we can start up and it does it in the background...?
@@ -118,9 +165,14 @@ async fn main() -> Result<()> {
     if args.urls.clone().is_empty() {
         info!("No urls passed in, checking for config file");
         //see if there is config to load
-        let maybe_config = load_config(args.file)?;
+        let maybe_config = load_config(files.config.clone())?;
         if let Some(cfg) = maybe_config {
             info!("Found config file");
+            display = cfg.display;
+            download_config = cfg.downloads;
+            player = cfg.player;
+            folders = cfg.folders;
+            sync = cfg.sync;
             let app_channel_vec = cfg
                 .channels
                 .into_iter()
@@ -141,8 +193,23 @@ async fn main() -> Result<()> {
     }
 
     let mut app = App::from(channels);
+    app.display = display;
+    app.download_config = download_config;
+    app.player = player;
+    app.folders = folders;
+    app.sync = sync;
+    app.set_history(History::load(files.history.clone())?);
+    app.history.save_changes(files.history.clone())?;
+    app.item_states = ItemStates::load(files.state.clone())?;
+    app.health = Health::load(files.health.clone())?;
+    app.files = files;
     //TODO YOU ARE BUSY DISPLAYING ERRORS ON LOADING CHANNELS
     app.set_loading_errors(&loading_error_map);
+    let daemon_socket = app.files.socket_path();
+    if is_running(&daemon_socket).await {
+        info!("Attaching to the daemon at {}", daemon_socket.display());
+        app.daemon_socket = Some(daemon_socket);
+    }
 
     run_app(&mut term, &mut app).await?;
     restore_terminal().context("Failed to restore terminal")?;
@@ -1,18 +1,77 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
 
-use log::info;
+use anyhow::Result;
+
+use chrono::{DateTime, FixedOffset};
+
+use log::{error, info};
 use ratatui::widgets::ListState;
-use serde::{Deserialize, Serialize};
+use regex::Regex;
+use reqwest::Url;
+use serde::{
+    de::{self, value::MapAccessDeserializer, IntoDeserializer, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
 use tui_textarea::{CursorMove, TextArea};
 
+use crate::{
+    api::{Discovered, FeedCandidate},
+    autodownload::{auto_download_requests, prune_downloads},
+    config::{load_config, StoreFiles},
+    daemon::DaemonDownload,
+    dates::{parse_date, DisplayConfig},
+    download::{Download, DownloadManager},
+    health::{FeedHealth, Health},
+    history::History,
+    paths::DownloadConfig,
+    player::{PlaybackUpdate, PlayerConfig},
+    search::{search, SearchHit},
+    state::ItemStates,
+    sync::SyncConfig,
+};
+
+///How often the player's position goes to the state file while it plays
+const PLAYBACK_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(PartialEq, Eq, Default, Clone)]
 pub enum AppState {
     #[default]
     Running,
     AddChannel,
+    ///The page they added has several feeds, and we are asking which
+    ChooseFeed,
+    Search,
+    Downloads,
+    ///Looking at the channels that are failing or gone
+    Health,
     Stopped,
 }
 
+///How far adding a channel has got
+#[derive(Default, Clone, Debug)]
+pub enum AddChannelStep {
+    #[default]
+    Editing,
+    Fetching,
+    ///Fetched, and waiting for them to say yes
+    Preview(Channel),
+}
+
+///What the content pane is showing for the selected item
+#[derive(PartialEq, Eq, Hash, Default, Clone, Copy, Debug)]
+pub enum ContentView {
+    #[default]
+    Item,
+    Chapters,
+    Transcript,
+}
+
 #[derive(PartialEq, Default, Clone)]
 pub enum SelectedPane {
     #[default]
@@ -20,7 +79,7 @@ pub enum SelectedPane {
     Items,
 }
 
-#[derive(Default, Clone)]
+#[derive(Default)]
 pub struct App<'a> {
     pub channels: StatefulChannelList,
     pub current_items: StatefulItemList,
@@ -32,6 +91,38 @@ pub struct App<'a> {
     pub error_popup_text: Option<String>,
     error_popup_thread_running: bool,
     pub add_channel_text_area: TextArea<'a>,
+    pub add_channel_step: AddChannelStep,
+    ///Why the last url did not work, shown in the dialog
+    pub add_channel_error: Option<String>,
+    pub search_text_area: TextArea<'a>,
+    pub search_results: StatefulSearchList,
+    pub feed_choices: StatefulFeedChoices,
+    pub history: History,
+    pub item_states: ItemStates,
+    pub health: Health,
+    pub display: DisplayConfig,
+    pub content_view: ContentView,
+    ///Chapters and transcripts we have fetched, by item id
+    pub sub_pane_texts: HashMap<(String, ContentView), String>,
+    pub downloads: DownloadManager,
+    pub download_config: DownloadConfig,
+    pub player: PlayerConfig,
+    ///When we first heard from the player since last saving where it got to
+    unsaved_playback_since: Option<Instant>,
+    ///Not shown yet, but kept so saving the config does not lose them
+    pub folders: HashMap<String, Vec<String>>,
+    ///With a server, [R]efresh syncs with it rather than fetching the feed
+    pub sync: Option<SyncConfig>,
+    ///Where the config and the stores are saved
+    pub files: StoreFiles,
+    ///Set when a daemon is running, downloads then go to it so they outlive the tui
+    pub daemon_socket: Option<PathBuf>,
+    ///Index into the downloads, when we are looking at them
+    pub selected_download: Option<usize>,
+    ///Index into the broken channels, when we are looking at their health
+    pub selected_health: Option<usize>,
+    ///We have asked to quit once while downloads were running
+    pub quit_requested: bool,
 }
 
 impl<'a> App<'a> {
@@ -51,9 +142,68 @@ impl<'a> App<'a> {
             error_popup_text: None,
             error_popup_thread_running: false,
             add_channel_text_area: TextArea::default(),
+            add_channel_step: AddChannelStep::Editing,
+            add_channel_error: None,
+            search_text_area: TextArea::default(),
+            search_results: StatefulSearchList::default(),
+            feed_choices: StatefulFeedChoices::default(),
+            history: History::default(),
+            item_states: ItemStates::default(),
+            health: Health::default(),
+            display: DisplayConfig::default(),
+            content_view: ContentView::Item,
+            sub_pane_texts: HashMap::new(),
+            downloads: DownloadManager::default(),
+            download_config: DownloadConfig::default(),
+            player: PlayerConfig::default(),
+            unsaved_playback_since: None,
+            folders: HashMap::new(),
+            sync: None,
+            files: StoreFiles::default(),
+            daemon_socket: None,
+            selected_download: None,
+            selected_health: None,
+            quit_requested: false,
         }
     }
 
+    ///Hand the app the item history and show the stored items for each channel
+    pub fn set_history(&mut self, mut history: History) {
+        for channel in self.channels.channels.iter_mut() {
+            channel.items = if channel.items.is_empty() {
+                history.items_for(&channel.get_link())
+            } else {
+                //we have freshly fetched the channel
+                history.merge(channel)
+            };
+        }
+        self.history = history;
+        self.construct_items = true;
+    }
+
+    ///Pick up what the daemon fetched for us: the items, feed health, read state,
+    ///and the new url of any channel that moved
+    pub fn reload_stores(&mut self) -> Result<()> {
+        self.item_states.save_changes(self.files.state.clone())?;
+        self.item_states = ItemStates::load(self.files.state.clone())?;
+        self.health = Health::load(self.files.health.clone())?;
+        if let Some(cfg) = load_config(self.files.config.clone())? {
+            for channel in self.channels.channels.iter_mut() {
+                if let Some(url) = cfg.channels.get(&channel.title) {
+                    channel.set_link(url);
+                }
+            }
+            self.folders = cfg.folders;
+        }
+        self.history.save_changes(self.files.history.clone())?;
+        self.history = History::load(self.files.history.clone())?;
+        for channel in self.channels.channels.iter_mut() {
+            channel.items = self.history.items_for(&channel.get_link());
+        }
+        self.construct_items = true;
+        Ok(())
+    }
+
     ///Maybe we fail to load some rss channels,
     ///so we need to display an error. To do that, we need error text.
     ///If we have errors, set the app's error text.
@@ -79,8 +229,20 @@ impl<'a> App<'a> {
         None
     }
 
-    pub fn update_selected_channel(&mut self, channel: &Channel) {
-        self.channels.update_selected_channel(channel);
+    ///Put a reloaded channel in place of the one we had at the url, under the name we gave it.
+    ///Returns the channel as it now is, None if it has left the list since
+    pub fn update_channel(&mut self, url: &str, channel: &Channel) -> Option<Channel> {
+        self.channels.update_channel(url, channel)
+    }
+
+    ///Carry the history and folders of a channel over to the url it has moved to
+    pub fn move_channel(&mut self, old_url: &str, new_url: &str) {
+        self.history.move_channel(old_url, new_url);
+        for member in self.folders.values_mut().flatten() {
+            if member == old_url {
+                *member = new_url.to_string();
+            }
+        }
     }
 
     pub fn get_selected_item(&self) -> Option<&Item> {
@@ -135,6 +297,8 @@ impl<'a> App<'a> {
             items_len - 1
         };
         self.current_items.state.select(Some(select_idx));
+        self.content_view = ContentView::Item;
+        self.mark_selected_read();
     }
 
     ///Graphically upwards from the current position
@@ -182,6 +346,85 @@ impl<'a> App<'a> {
             0
         };
         self.current_items.state.select(Some(select_idx));
+        self.content_view = ContentView::Item;
+        self.mark_selected_read();
+    }
+
+    ///Switch the content pane to the view, or back to the item if it is already showing.
+    ///Returns the item id if the view needs fetching
+    pub fn toggle_content_view(&mut self, view: ContentView) -> Option<String> {
+        if self.content_view == view {
+            self.content_view = ContentView::Item;
+            return None;
+        }
+        let item = self.get_selected_item()?;
+        let available = match view {
+            ContentView::Item => true,
+            ContentView::Chapters => item.get_chapters().is_some(),
+            ContentView::Transcript => item.transcript.as_ref().is_some_and(|t| !t.is_empty()),
+        };
+        if !available {
+            return None;
+        }
+        let item_id = item.id();
+        self.content_view = view;
+        if self.sub_pane_texts.contains_key(&(item_id.clone(), view)) {
+            None
+        } else {
+            Some(item_id)
+        }
+    }
+
+    pub fn set_sub_pane_text(&mut self, item_id: String, view: ContentView, text: String) {
+        self.sub_pane_texts.insert((item_id, view), text);
+    }
+
+    ///The chapters or transcript of the selected item, if that is what we are showing
+    pub fn sub_pane_text(&self) -> Option<String> {
+        if self.content_view == ContentView::Item {
+            return None;
+        }
+        let item_id = self.get_selected_item()?.id();
+        Some(
+            self.sub_pane_texts
+                .get(&(item_id, self.content_view))
+                .cloned()
+                .unwrap_or("Loading...".to_string()),
+        )
+    }
+
+    ///Reading an item is selecting it
+    pub fn mark_selected_read(&mut self) {
+        let Some(item) = self.get_selected_item() else {
+            return;
+        };
+        if self.item_states.set_read(&item.id(), true) {
+            self.save_item_states();
+        }
+    }
+
+    ///Star the selected item, or take the star away, returning whether it is now starred
+    pub fn toggle_selected_starred(&mut self) -> Option<bool> {
+        let id = self.get_selected_item()?.id();
+        let starred = self.item_states.toggle_starred(&id);
+        self.save_item_states();
+        Some(starred)
+    }
+
+    pub fn is_starred(&self, item: &Item) -> bool {
+        self.item_states.is_starred(&item.id())
+    }
+
+    pub fn is_read(&self, item: &Item) -> bool {
+        self.item_states.is_read(&item.id())
+    }
+
+    pub fn num_unread(&self, channel: &Channel) -> usize {
+        channel
+            .items
+            .iter()
+            .filter(|item| !self.is_read(item))
+            .count()
     }
 
     pub fn change_selected_pane(&mut self) {
@@ -211,8 +454,8 @@ impl<'a> App<'a> {
         };
 
         if let Some(item) = self.current_items.items.get(idx) {
-            if let Some(description) = item.description.clone() {
-                description
+            if let Some(content) = item.get_content() {
+                content
             } else {
                 item.link
                     .clone()
@@ -225,39 +468,472 @@ impl<'a> App<'a> {
     }
 
     pub fn show_add_channel_dialog(&mut self) {
+        self.add_channel_step = AddChannelStep::Editing;
+        self.add_channel_error = None;
         self.state = AppState::AddChannel
     }
 
     pub fn unshow_add_channel_dialog(&mut self) {
         self.clear_add_channel_text_area();
+        self.add_channel_step = AddChannelStep::Editing;
+        self.add_channel_error = None;
         self.state = AppState::Running
     }
 
-    ///Add the text in dialog as a channel
-    pub fn add_channel(&mut self) -> Option<String> {
+    ///The url in the dialog, to be fetched before anything is added.
+    ///None, with the reason shown in the dialog, when it is not a url
+    pub fn add_channel_url(&mut self) -> Option<String> {
         //expect a single line
-        let hopefully_a_channel = self.add_channel_text_area.lines()[0].clone();
-        if hopefully_a_channel.is_empty() {
+        let hopefully_a_url = self.add_channel_text_area.lines()[0].trim().to_string();
+        if hopefully_a_url.is_empty() {
             return None;
         }
-        let channel_link = ChannelLink {
-            href: Some(hopefully_a_channel.clone()),
-            value: None,
-        };
-        let link = vec![channel_link.clone()];
-        let channel_to_add = Channel {
-            title: hopefully_a_channel,
-            link,
-            ..Default::default()
+        match Url::parse(&hopefully_a_url) {
+            Ok(url) if ["http", "https"].contains(&url.scheme()) => {
+                self.add_channel_error = None;
+                self.add_channel_step = AddChannelStep::Fetching;
+                Some(hopefully_a_url)
+            }
+            _ => {
+                self.add_channel_error = Some(format!("{hopefully_a_url} is not a web address"));
+                None
+            }
+        }
+    }
+
+    ///What came of fetching the url in the dialog: a feed to preview, feeds to choose from,
+    ///or an error to show under the url
+    pub fn add_channel_fetched(&mut self, url: &str, fetched: Result<Discovered>) {
+        //they gave up waiting
+        if !matches!(self.add_channel_step, AddChannelStep::Fetching) {
+            return;
+        }
+        self.add_channel_step = AddChannelStep::Editing;
+        match fetched {
+            Ok(Discovered::Feed(channel)) => {
+                self.add_channel_step = AddChannelStep::Preview(channel);
+            }
+            Ok(Discovered::Choices(choices)) => self.show_feed_choices(url, choices),
+            Err(e) => self.add_channel_error = Some(e.to_string()),
+        }
+    }
+
+    ///Add the previewed channel, and give it back so its items can be looked after
+    pub fn confirm_add_channel(&mut self) -> Option<Channel> {
+        let AddChannelStep::Preview(channel) = &self.add_channel_step else {
+            return None;
         };
-        self.channels.channels.push(channel_to_add);
-        let num_channels = self.num_channels();
-        self.clear_add_channel_text_area();
+        let link = channel.get_link();
+        if let Some(known) = self
+            .channels
+            .channels
+            .iter()
+            .find(|known| known.get_link() == link)
+        {
+            self.add_channel_error = Some(format!("Already following this as {}", known.title));
+            self.add_channel_step = AddChannelStep::Editing;
+            return None;
+        }
+        let mut channel = channel.clone();
+        channel.items = self.history.merge(&channel);
+        self.channels.channels.push(channel.clone());
         //select the just added channel
-        self.channels.state.select(Some(num_channels - 1));
-        //we no longer wish to display the textarea
-        self.state = AppState::Running;
-        channel_link.href
+        self.channels.state.select(Some(self.num_channels() - 1));
+        self.construct_items = true;
+        self.unshow_add_channel_dialog();
+        Some(channel)
+    }
+
+    ///Back to the url, to fix it or try another
+    pub fn reject_add_channel(&mut self) {
+        self.add_channel_step = AddChannelStep::Editing;
+    }
+
+    pub fn show_feed_choices(&mut self, page_url: &str, choices: Vec<FeedCandidate>) {
+        self.feed_choices = StatefulFeedChoices {
+            state: ListState::default().with_selected(Some(0)),
+            page_url: page_url.to_string(),
+            choices,
+        };
+        self.state = AppState::ChooseFeed
+    }
+
+    ///None of them, back to the url
+    pub fn unshow_feed_choices(&mut self) {
+        self.feed_choices = StatefulFeedChoices::default();
+        self.state = AppState::AddChannel
+    }
+
+    pub fn select_up_feed_choices(&mut self) {
+        let choices_len = self.feed_choices.choices.len();
+        if choices_len == 0 {
+            return;
+        }
+        let select_idx = match self.feed_choices.state.selected() {
+            Some(0) | None => choices_len - 1,
+            Some(idx) => idx - 1,
+        };
+        self.feed_choices.state.select(Some(select_idx));
+    }
+
+    pub fn select_down_feed_choices(&mut self) {
+        let choices_len = self.feed_choices.choices.len();
+        if choices_len == 0 {
+            return;
+        }
+        let select_idx = match self.feed_choices.state.selected() {
+            Some(idx) if idx < choices_len - 1 => idx + 1,
+            _ => 0,
+        };
+        self.feed_choices.state.select(Some(select_idx));
+    }
+
+    ///The feed they chose, to fetch and preview
+    pub fn choose_feed(&mut self) -> Option<String> {
+        let idx = self.feed_choices.state.selected()?;
+        let chosen = self.feed_choices.choices.get(idx)?.url.clone();
+        self.feed_choices = StatefulFeedChoices::default();
+        self.add_channel_step = AddChannelStep::Fetching;
+        self.state = AppState::AddChannel;
+        Some(chosen)
+    }
+
+    pub fn show_search_dialog(&mut self) {
+        self.state = AppState::Search
+    }
+
+    pub fn unshow_search_dialog(&mut self) {
+        self.search_text_area = TextArea::default();
+        self.search_results = StatefulSearchList::default();
+        self.state = AppState::Running
+    }
+
+    ///Rerun the search with whatever is in the search box
+    pub fn update_search(&mut self) {
+        let query = self.search_text_area.lines().join(" ");
+        let hits = search(&self.channels.channels, &query);
+        let selected = if hits.is_empty() { None } else { Some(0) };
+        self.search_results = StatefulSearchList {
+            state: ListState::default().with_selected(selected),
+            hits,
+        };
+    }
+
+    pub fn select_up_search_results(&mut self) {
+        let hits_len = self.search_results.hits.len();
+        if hits_len == 0 {
+            return;
+        }
+        let select_idx = match self.search_results.state.selected() {
+            Some(0) | None => hits_len - 1,
+            Some(idx) => idx - 1,
+        };
+        self.search_results.state.select(Some(select_idx));
+    }
+
+    pub fn select_down_search_results(&mut self) {
+        let hits_len = self.search_results.hits.len();
+        if hits_len == 0 {
+            return;
+        }
+        let select_idx = match self.search_results.state.selected() {
+            Some(idx) if idx < hits_len - 1 => idx + 1,
+            _ => 0,
+        };
+        self.search_results.state.select(Some(select_idx));
+    }
+
+    ///Select the channel and item of the chosen search result and close the search
+    pub fn jump_to_selected_search_result(&mut self) {
+        let hit = match self.search_results.state.selected() {
+            Some(idx) => self.search_results.hits.get(idx).cloned(),
+            None => None,
+        };
+        let Some(hit) = hit else {
+            return;
+        };
+        self.channels.state.select(Some(hit.channel_idx));
+        if let Some(channel) = self.get_selected_channel() {
+            self.current_items = StatefulItemList::from(channel);
+            let hit_id = hit.item.id();
+            let item_idx = self
+                .current_items
+                .items
+                .iter()
+                .position(|item| item.id() == hit_id);
+            self.current_items.state.select(item_idx);
+            self.construct_items = false;
+            self.selected_pane = SelectedPane::Items;
+            self.content_view = ContentView::Item;
+            self.mark_selected_read();
+        }
+        self.unshow_search_dialog();
+    }
+
+    pub fn show_downloads(&mut self) {
+        if self.selected_download.is_none() && !self.downloads.downloads.is_empty() {
+            self.selected_download = Some(0);
+        }
+        self.state = AppState::Downloads
+    }
+
+    pub fn unshow_downloads(&mut self) {
+        self.state = AppState::Running
+    }
+
+    pub fn select_up_downloads(&mut self) {
+        let downloads_len = self.downloads.downloads.len();
+        if downloads_len == 0 {
+            return;
+        }
+        self.selected_download = match self.selected_download {
+            Some(0) | None => Some(downloads_len - 1),
+            Some(idx) => Some(idx - 1),
+        };
+    }
+
+    pub fn select_down_downloads(&mut self) {
+        let downloads_len = self.downloads.downloads.len();
+        if downloads_len == 0 {
+            return;
+        }
+        self.selected_download = match self.selected_download {
+            Some(idx) if idx < downloads_len - 1 => Some(idx + 1),
+            _ => Some(0),
+        };
+    }
+
+    ///The channels that are failing or gone, for the health view
+    pub fn broken_channels(&self) -> Vec<(&Channel, &FeedHealth)> {
+        self.channels
+            .channels
+            .iter()
+            .filter_map(|channel| {
+                let health = self.health.get(&channel.get_link())?;
+                health.is_broken().then_some((channel, health))
+            })
+            .collect()
+    }
+
+    pub fn show_health(&mut self) {
+        if self.selected_health.is_none() && !self.broken_channels().is_empty() {
+            self.selected_health = Some(0);
+        }
+        self.state = AppState::Health
+    }
+
+    pub fn unshow_health(&mut self) {
+        self.state = AppState::Running
+    }
+
+    pub fn select_up_health(&mut self) {
+        let broken_len = self.broken_channels().len();
+        if broken_len == 0 {
+            return;
+        }
+        self.selected_health = match self.selected_health {
+            Some(0) | None => Some(broken_len - 1),
+            Some(idx) => Some(idx - 1),
+        };
+    }
+
+    pub fn select_down_health(&mut self) {
+        let broken_len = self.broken_channels().len();
+        if broken_len == 0 {
+            return;
+        }
+        self.selected_health = match self.selected_health {
+            Some(idx) if idx < broken_len - 1 => Some(idx + 1),
+            _ => Some(0),
+        };
+    }
+
+    fn get_selected_broken_channel(&self) -> Option<Channel> {
+        let broken = self.broken_channels();
+        broken
+            .get(self.selected_health?)
+            .map(|(channel, _)| (*channel).clone())
+    }
+
+    ///The list got shorter, keep the selection on it
+    fn clamp_selected_health(&mut self) {
+        let broken_len = self.broken_channels().len();
+        self.selected_health = match self.selected_health {
+            _ if broken_len == 0 => None,
+            Some(idx) if idx >= broken_len => Some(broken_len - 1),
+            selected => selected,
+        };
+    }
+
+    ///Forget the failures of the selected broken channel, so it is fetched again.
+    ///Returns its title
+    pub fn enable_selected_health(&mut self) -> Option<String> {
+        let channel = self.get_selected_broken_channel()?;
+        self.health.reset(&channel.get_link());
+        if let Err(e) = self.health.save_changes(self.files.health.clone()) {
+            error!("Unable to save feed health: {e}");
+        }
+        self.clamp_selected_health();
+        Some(channel.title)
+    }
+
+    ///Drop the selected broken channel, for pruning the dead ones. Returns its title
+    pub fn remove_selected_health(&mut self) -> Option<String> {
+        let channel = self.get_selected_broken_channel()?;
+        let url = channel.get_link();
+        self.channels.channels.retain(|kept| kept.get_link() != url);
+        let channels_len = self.channels.channels.len();
+        if let Some(idx) = self.channels.state.selected() {
+            self.channels.state.select(match channels_len {
+                0 => None,
+                _ => Some(idx.min(channels_len - 1)),
+            });
+        }
+        self.construct_items = true;
+        self.health.remove(&url);
+        if let Err(e) = self.health.save_changes(self.files.health.clone()) {
+            error!("Unable to save feed health: {e}");
+        }
+        self.clamp_selected_health();
+        Some(channel.title)
+    }
+
+    pub fn cancel_selected_download(&mut self) {
+        if let Some(download) = self.get_selected_download() {
+            let id = download.id;
+            self.downloads.cancel(id);
+        }
+    }
+
+    pub fn retry_selected_download(&mut self) {
+        if let Some(download) = self.get_selected_download() {
+            let id = download.id;
+            self.downloads.retry(id);
+        }
+    }
+
+    fn get_selected_download(&self) -> Option<&Download> {
+        self.downloads.downloads.get(self.selected_download?)
+    }
+
+    ///Queue up the episodes the channel's rules ask for, skipping those we already have.
+    ///Returns how many were queued
+    pub fn auto_download(&mut self, channel: &Channel) -> usize {
+        let requests = auto_download_requests(
+            &self.download_config,
+            channel,
+            &self.item_states,
+            &self.downloads,
+        );
+        let queued = requests.len();
+        for request in requests {
+            info!("Automatically downloading {}", request.title);
+            self.downloads.enqueue(request);
+        }
+        queued
+    }
+
+    ///Delete downloads the channels' retention rules no longer want kept
+    pub fn prune_downloads(&mut self) {
+        if prune_downloads(
+            &self.download_config,
+            &self.channels.channels,
+            &mut self.item_states,
+        ) {
+            self.save_item_states();
+        }
+    }
+
+    ///Remember where the finished downloads went
+    pub fn record_downloads(&mut self, finished: &[Download]) {
+        let mut changed = false;
+        for download in finished {
+            changed |= self.item_states.set_downloaded(
+                &download.request.item_id,
+                Some(download.request.path.clone()),
+            );
+        }
+        if changed {
+            self.save_item_states();
+        }
+    }
+
+    ///Remember where the daemon's finished downloads went, so our saves do not lose them
+    pub fn record_daemon_downloads(&mut self, downloads: &[DaemonDownload]) {
+        let mut changed = false;
+        for download in downloads.iter().filter(|d| d.status == "finished") {
+            changed |= self
+                .item_states
+                .set_downloaded(&download.item_id, Some(download.path.clone()));
+        }
+        if changed {
+            self.save_item_states();
+        }
+    }
+
+    ///Remember where the player got to.
+    ///The player tells us every second, so we only save now and then, or when it gets played
+    pub fn record_playback(&mut self, update: PlaybackUpdate) {
+        let was_played = self
+            .item_states
+            .get(&update.item_id)
+            .is_some_and(|state| state.played);
+        self.item_states.set_position(
+            &update.item_id,
+            update.position,
+            update.duration,
+            self.player.played_threshold,
+        );
+        let unsaved_since = *self.unsaved_playback_since.get_or_insert(Instant::now());
+        let now_played = self
+            .item_states
+            .get(&update.item_id)
+            .is_some_and(|state| state.played);
+        if now_played != was_played || unsaved_since.elapsed() >= PLAYBACK_SAVE_INTERVAL {
+            self.finish_playback();
+        }
+    }
+
+    ///Save where the player got to, if we have not yet
+    pub fn finish_playback(&mut self) {
+        if self.unsaved_playback_since.take().is_some() {
+            self.save_item_states();
+        }
+    }
+
+    pub fn progress_label(&self, item: &Item) -> Option<String> {
+        self.item_states.get(&item.id())?.progress_label()
+    }
+
+    pub fn downloaded_path(&self, item: &Item) -> Option<&Path> {
+        self.item_states.downloaded_path(&item.id())
+    }
+
+    ///Delete the local copy of the selected item's enclosure.
+    ///Returns the title of the item, if there was a copy to delete
+    pub fn delete_selected_download(&mut self) -> Result<Option<String>> {
+        let Some(item) = self.get_selected_item() else {
+            return Ok(None);
+        };
+        let (id, title) = (item.id(), item.get_title());
+        let Some(path) = self
+            .item_states
+            .downloaded_path(&id)
+            .map(|p| p.to_path_buf())
+        else {
+            return Ok(None);
+        };
+        info!("Deleting download {}", path.display());
+        std::fs::remove_file(&path)?;
+        self.item_states.set_downloaded(&id, None);
+        self.save_item_states();
+        Ok(Some(title))
+    }
+
+    fn save_item_states(&mut self) {
+        if let Err(e) = self.item_states.save_changes(self.files.state.clone()) {
+            error!("Unable to save item state: {e}");
+        }
     }
 
     pub fn set_add_channel_contents(&mut self, contents: &str) {
@@ -295,16 +971,22 @@ impl<'a> App<'a> {
 pub struct StatefulChannelList {
     pub state: ListState,
     pub channels: Vec<Channel>,
+    #[allow(dead_code)]
     pub last_selected: Option<usize>,
 }
 
 impl StatefulChannelList {
-    pub fn update_selected_channel(&mut self, channel: &Channel) {
-        if let Some(idx) = self.state.selected() {
-            self.channels[idx] = channel.clone();
-        } else {
-            self.channels.push(channel.clone());
-        }
+    ///The selection may have moved on while the channel was fetched, so we go by its url
+    pub fn update_channel(&mut self, url: &str, channel: &Channel) -> Option<Channel> {
+        let existing = self
+            .channels
+            .iter_mut()
+            .find(|existing| existing.get_link() == url)?;
+        *existing = Channel {
+            title: existing.title.clone(),
+            ..channel.clone()
+        };
+        Some(existing.clone())
     }
 }
 
@@ -313,6 +995,7 @@ impl StatefulChannelList {
 pub struct StatefulItemList {
     pub state: ListState,
     pub items: Vec<Item>,
+    #[allow(dead_code)]
     pub last_selected: Option<usize>,
 }
 
@@ -326,6 +1009,21 @@ impl StatefulItemList {
     }
 }
 
+///Results of a search across all the channels
+#[derive(Default, Clone)]
+pub struct StatefulSearchList {
+    pub state: ListState,
+    pub hits: Vec<SearchHit>,
+}
+
+///Feeds found on a page that is being added as a channel, to pick one from
+#[derive(Default, Clone)]
+pub struct StatefulFeedChoices {
+    pub state: ListState,
+    pub page_url: String,
+    pub choices: Vec<FeedCandidate>,
+}
+
 ///Big rss wrapping tag
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Rss {
@@ -374,6 +1072,29 @@ impl Channel {
         return_link
     }
 
+    ///The url the feed is fetched from, if we know it
+    pub fn feed_url(&self) -> Option<String> {
+        self.link
+            .iter()
+            .rev()
+            .find_map(|link| link.href.clone())
+            .filter(|href| !href.is_empty())
+    }
+
+    pub fn published(&self) -> Option<DateTime<FixedOffset>> {
+        self.pub_date.as_deref().and_then(parse_date)
+    }
+
+    ///Newest items first, items without a date we can read keep their place at the back
+    pub fn sort_items(items: &mut [Item]) {
+        items.sort_by(|a, b| match (a.published(), b.published()) {
+            (Some(a_date), Some(b_date)) => b_date.cmp(&a_date),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+    }
+
     ///Set the url of the channel
     pub fn set_link(&mut self, txt: &str) {
         //clear out old items
@@ -390,7 +1111,7 @@ impl Channel {
 }
 
 ///News items in the channel
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
 pub struct Item {
     // pub title: String,
     // pub link: String,
@@ -400,6 +1121,37 @@ pub struct Item {
     pub enclosure: Option<Enclosure>,
     #[serde(rename = "pubDate")]
     pub pub_date: Option<String>,
+    ///Both `author` and `itunes:author` land here, hence the vec
+    pub author: Option<Vec<String>>,
+    ///`dc:creator`
+    pub creator: Option<Vec<String>>,
+    pub category: Option<Vec<String>>,
+    ///`itunes:duration`, either seconds or h:mm:ss
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub duration: Option<Vec<String>>,
+    ///`content:encoded`, the full article where the description is only a summary
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub encoded: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub guid: Option<Vec<Guid>>,
+    ///`slash:comments` counts land here along with the comments url
+    pub comments: Option<Vec<String>>,
+    ///`itunes:episode` and `podcast:episode`
+    pub episode: Option<Vec<String>>,
+    ///`itunes:season` and `podcast:season`
+    pub season: Option<Vec<String>>,
+    ///`itunes:explicit`
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub explicit: Option<Vec<String>>,
+    ///`itunes:image`
+    pub image: Option<Vec<Image>>,
+    ///`podcast:chapters`
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub chapters: Option<Vec<PodcastChapters>>,
+    ///`podcast:transcript`, often in more than one format
+    pub transcript: Option<Vec<PodcastTranscript>>,
+    ///`podcast:person`
+    pub person: Option<Vec<PodcastPerson>>,
 }
 
 impl Item {
@@ -411,6 +1163,233 @@ impl Item {
         }
         String::from("None")
     }
+
+    ///The first guid, feeds should only have the one
+    pub fn guid_tag(&self) -> Option<&Guid> {
+        self.guid.as_ref()?.first()
+    }
+
+    pub fn get_guid(&self) -> Option<String> {
+        self.guid_tag()
+            .and_then(|guid| guid.value.clone())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    ///The link, or the guid when the guid is a permalink
+    pub fn get_link(&self) -> Option<String> {
+        if let Some(link) = &self.link {
+            return Some(link.clone());
+        }
+        match self.guid_tag() {
+            Some(guid) if guid.is_perma_link() => self.get_guid(),
+            _ => None,
+        }
+    }
+
+    pub fn get_comments_link(&self) -> Option<String> {
+        self.comments
+            .iter()
+            .flatten()
+            .map(|comments| comments.trim())
+            .find(|comments| comments.starts_with("http"))
+            .map(|comments| comments.to_string())
+    }
+
+    ///`content:encoded`, the first that has anything in it
+    pub fn get_encoded(&self) -> Option<String> {
+        self.encoded
+            .iter()
+            .flatten()
+            .find(|encoded| !encoded.trim().is_empty())
+            .cloned()
+    }
+
+    ///The full article if we have it, otherwise the summary
+    pub fn get_content(&self) -> Option<String> {
+        self.get_encoded().or(self.description.clone())
+    }
+
+    ///When the item was published, if the feed tells us in a way we understand
+    pub fn published(&self) -> Option<DateTime<FixedOffset>> {
+        self.pub_date.as_deref().and_then(parse_date)
+    }
+
+    ///The `dc:creator`, or failing that the author.
+    ///`itunes:author` is usually the show rather than a person, and it shares the author slot
+    pub fn get_author(&self) -> Option<String> {
+        [&self.creator, &self.author]
+            .into_iter()
+            .flatten()
+            .flatten()
+            .find(|a| !a.trim().is_empty())
+            .cloned()
+    }
+
+    pub fn get_categories(&self) -> Vec<String> {
+        self.category
+            .iter()
+            .flatten()
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+            .collect()
+    }
+
+    ///S2E5, or just E5 if the feed has no seasons
+    pub fn episode_label(&self) -> Option<String> {
+        let first_number = |numbers: &Option<Vec<String>>| {
+            numbers
+                .iter()
+                .flatten()
+                .map(|number| number.trim().to_string())
+                .find(|number| !number.is_empty())
+        };
+        let episode = first_number(&self.episode)?;
+        match first_number(&self.season) {
+            Some(season) => Some(format!("S{season}E{episode}")),
+            None => Some(format!("E{episode}")),
+        }
+    }
+
+    ///As the first of `itunes:explicit` and `googleplay:explicit` has it
+    pub fn is_explicit(&self) -> bool {
+        self.explicit
+            .as_ref()
+            .and_then(|explicit| explicit.first())
+            .is_some_and(|explicit| {
+                let explicit = explicit.trim().to_lowercase();
+                explicit == "yes" || explicit == "true" || explicit == "explicit"
+            })
+    }
+
+    pub fn get_image(&self) -> Option<String> {
+        self.image
+            .iter()
+            .flatten()
+            .find_map(|image| image.href.clone().or(image.url.clone()))
+    }
+
+    pub fn get_people(&self) -> Vec<String> {
+        self.person
+            .iter()
+            .flatten()
+            .filter_map(|person| {
+                let name = person.name.clone()?.trim().to_string();
+                match &person.role {
+                    Some(role) => Some(format!("{name} ({})", role.to_lowercase())),
+                    None => Some(name),
+                }
+            })
+            .collect()
+    }
+
+    ///`itunes:duration` as the feed has it, the first that has anything in it
+    pub fn get_duration(&self) -> Option<String> {
+        self.duration
+            .iter()
+            .flatten()
+            .map(|duration| duration.trim())
+            .find(|duration| !duration.is_empty())
+            .map(|duration| duration.to_string())
+    }
+
+    ///Length of the episode in seconds
+    pub fn duration_secs(&self) -> Option<u64> {
+        //seconds, m:ss or h:mm:ss
+        self.get_duration()?.split(':').try_fold(0, |total, part| {
+            part.trim().parse::<u64>().ok().map(|num| total * 60 + num)
+        })
+    }
+
+    pub fn get_chapters(&self) -> Option<&PodcastChapters> {
+        self.chapters.as_ref()?.first()
+    }
+
+    ///Something to tell this item apart from the others in its channel.
+    ///The guid if the feed gives us one, otherwise the best we can do
+    pub fn id(&self) -> String {
+        if let Some(guid) = self.get_guid() {
+            return guid;
+        }
+        if let Some(link) = &self.link {
+            return link.clone();
+        }
+        if let Some(enclosure) = &self.enclosure {
+            return enclosure.url.clone();
+        }
+        format!(
+            "{}|{}",
+            self.get_title(),
+            self.pub_date.clone().unwrap_or_default()
+        )
+    }
+}
+
+///Throw away the tags and then decode the html entities, leaving text we can display.
+///Escaped markup is text the feed meant us to show, so it has to outlive the tags
+pub fn strip_html(html_text: &str) -> String {
+    static TAG_REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = TAG_REGEX.get_or_init(|| {
+        Regex::new("<\\/?[a-z][a-z0-9]*[^<>]*>|<!--.*?-->").expect("Tag regex should compile")
+    });
+    let text = regex.replace_all(html_text, "");
+    html_escape::decode_html_entities(&text).to_string()
+}
+
+///Elements a feed may repeat, which serde would otherwise turn down as a duplicate field
+///and lose us the whole feed. From xml they come as a sequence, from history saved before
+///they were lists as the one value
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct OneOrMany<T>(std::marker::PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for OneOrMany<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("one or more values")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<T>, A::Error> {
+            let mut values = Vec::new();
+            while let Some(value) = seq.next_element()? {
+                values.push(value);
+            }
+            Ok(values)
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Vec<T>, A::Error> {
+            Ok(vec![T::deserialize(MapAccessDeserializer::new(map))?])
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Vec<T>, E> {
+            Ok(vec![T::deserialize(value.into_deserializer())?])
+        }
+    }
+
+    let values = deserializer.deserialize_seq(OneOrMany(std::marker::PhantomData))?;
+    Ok(Some(values).filter(|values| !values.is_empty()))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Guid {
+    #[serde(rename = "isPermaLink")]
+    pub is_perma_link: Option<String>,
+    #[serde(rename = "$value")]
+    pub value: Option<String>,
+}
+
+impl Guid {
+    ///Guids are permalinks unless the feed says otherwise
+    pub fn is_perma_link(&self) -> bool {
+        !self
+            .is_perma_link
+            .as_ref()
+            .is_some_and(|perma| perma.trim().eq_ignore_ascii_case("false"))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -421,6 +1400,68 @@ pub struct Enclosure {
     pub enclosure_type: String,
 }
 
+impl Enclosure {
+    ///Size in bytes, feeds tend to put 0 when they do not know
+    pub fn size_bytes(&self) -> Option<u64> {
+        self.length.trim().parse().ok().filter(|size| *size > 0)
+    }
+}
+
+///Bytes as something a person can read, e.g. 52.3 MB
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next_unit in UNITS {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1000.0;
+        unit = next_unit;
+    }
+    format!("{size:.1} {unit}")
+}
+
+///Seconds as h:mm:ss, or m:ss when short enough
+pub fn format_duration(secs: u64) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+///Where to find the chapters of an episode, usually json
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PodcastChapters {
+    pub url: String,
+    #[serde(rename = "type")]
+    pub chapters_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PodcastTranscript {
+    pub url: String,
+    #[serde(rename = "type")]
+    pub transcript_type: Option<String>,
+    pub language: Option<String>,
+    pub rel: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PodcastPerson {
+    pub role: Option<String>,
+    pub group: Option<String>,
+    pub img: Option<String>,
+    pub href: Option<String>,
+    #[serde(rename = "$value")]
+    pub name: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Image {
     pub url: Option<String>,
@@ -429,6 +1470,7 @@ pub struct Image {
     pub title: Option<String>,
 }
 
+#[cfg(test)]
 mod test {
     /* reference xml for our test
     <atom:link href="https://feeds.buzzsprout.com/2042709.rss" rel="self" type="application/rss+xml" />
@@ -439,8 +1481,143 @@ mod test {
     <language>en-us</language>
     */
 
+    use super::*;
+
     #[test]
     pub fn test_get_link() {
-        // let mut links = ""
+        let guid = |is_perma_link: Option<&str>| {
+            Some(vec![Guid {
+                is_perma_link: is_perma_link.map(str::to_string),
+                value: Some("https://testing.test/guid".to_string()),
+            }])
+        };
+        let linked = Item {
+            link: Some("https://testing.test/link".to_string()),
+            guid: guid(None),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some("https://testing.test/link".to_string()),
+            linked.get_link()
+        );
+        let perma = Item {
+            guid: guid(None),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some("https://testing.test/guid".to_string()),
+            perma.get_link()
+        );
+        let not_perma = Item {
+            guid: guid(Some("false")),
+            ..Default::default()
+        };
+        assert_eq!(None, not_perma.get_link());
+    }
+
+    #[test]
+    pub fn test_duration_secs() {
+        let with_duration = |duration: &str| Item {
+            duration: Some(vec![duration.to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(Some(3723), with_duration("1:02:03").duration_secs());
+        assert_eq!(Some(3723), with_duration("62:03").duration_secs());
+        assert_eq!(Some(3723), with_duration("3723").duration_secs());
+        assert_eq!(None, with_duration("an hour").duration_secs());
+        assert_eq!(None, Item::default().duration_secs());
+    }
+
+    #[test]
+    pub fn test_format_size_and_duration() {
+        assert_eq!("512 B", format_size(512));
+        assert_eq!("52.3 MB", format_size(52_300_000));
+        assert_eq!("1.2 GB", format_size(1_200_000_000));
+        assert_eq!("1:02:03", format_duration(3723));
+        assert_eq!("4:05", format_duration(245));
+    }
+
+    #[test]
+    pub fn test_strip_html() {
+        assert_eq!(
+            "Fish & chips",
+            strip_html("<p>Fish &amp; <b>chips</b></p><!-- menu -->")
+        );
+        assert_eq!(
+            "Use <b> for bold",
+            strip_html("<p>Use &lt;b&gt; for bold</p>")
+        );
+    }
+
+    #[test]
+    pub fn test_add_channel_preview() {
+        let mut app = App::from(Vec::new());
+        app.show_add_channel_dialog();
+        app.set_add_channel_contents("feeds.buzzsprout.com/2042709.rss");
+        assert_eq!(None, app.add_channel_url());
+        assert!(app.add_channel_error.is_some());
+
+        app.clear_add_channel_text_area();
+        app.set_add_channel_contents("https://feeds.buzzsprout.com/2042709.rss");
+        let url = app.add_channel_url().unwrap();
+        app.add_channel_fetched(&url, Err(anyhow::anyhow!("404 Not Found")));
+        assert_eq!(Some("404 Not Found".to_string()), app.add_channel_error);
+        assert_eq!(0, app.num_channels());
+
+        let mut channel = Channel {
+            title: "Between Two Cairns".to_string(),
+            items: vec![Item::default()],
+            ..Default::default()
+        };
+        channel.set_link(&url);
+        app.add_channel_url().unwrap();
+        app.add_channel_fetched(&url, Ok(Discovered::Feed(channel.clone())));
+        assert!(matches!(app.add_channel_step, AddChannelStep::Preview(_)));
+        let added = app.confirm_add_channel().unwrap();
+        assert_eq!("Between Two Cairns", added.title);
+        assert_eq!(1, app.num_channels());
+        assert!(app.state == AppState::Running);
+
+        //the same feed again is caught before it is added twice
+        app.show_add_channel_dialog();
+        app.set_add_channel_contents(&url);
+        app.add_channel_url().unwrap();
+        app.add_channel_fetched(&url, Ok(Discovered::Feed(channel)));
+        assert_eq!(None, app.confirm_add_channel().map(|channel| channel.title));
+        assert_eq!(1, app.num_channels());
+    }
+
+    #[test]
+    pub fn test_update_channel_by_url() {
+        let channel = |title: &str, url: &str| {
+            let mut channel = Channel {
+                title: title.to_string(),
+                ..Default::default()
+            };
+            channel.set_link(url);
+            channel
+        };
+        let mut app = App::from(vec![
+            channel("Cairns", "https://testing.test/cairns.rss"),
+            channel("Other", "https://testing.test/other.rss"),
+        ]);
+        //the cursor moved on while the first was reloading, and it has moved house
+        app.channels.state.select(Some(1));
+        let mut reloaded = channel("Between Two Cairns", "https://testing.test/moved.rss");
+        reloaded.items = vec![Item::default()];
+        let updated = app
+            .update_channel("https://testing.test/cairns.rss", &reloaded)
+            .unwrap();
+        assert_eq!("Cairns", updated.title);
+        assert_eq!("https://testing.test/moved.rss", updated.get_link());
+        assert_eq!(1, app.channels.channels[0].items.len());
+        assert_eq!("Other", app.channels.channels[1].title);
+        assert_eq!(
+            "https://testing.test/other.rss",
+            app.channels.channels[1].get_link()
+        );
+        assert!(app
+            .update_channel("https://testing.test/gone.rss", &reloaded)
+            .is_none());
     }
 }
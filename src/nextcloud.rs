@@ -0,0 +1,296 @@
+use anyhow::{anyhow, Result};
+use reqwest::{Client, RequestBuilder, Response};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+use crate::{
+    model::{Enclosure, Guid, Item},
+    sync::{unix_date, Backend, Flag, RemoteFeed, RemoteItem, SyncConfig},
+};
+
+#[derive(Deserialize)]
+struct Folders {
+    folders: Vec<Folder>,
+}
+
+#[derive(Deserialize)]
+struct Folder {
+    id: i64,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Feeds {
+    feeds: Vec<Feed>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Feed {
+    id: i64,
+    url: String,
+    title: Option<String>,
+    folder_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct Items {
+    items: Vec<NextcloudItem>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NextcloudItem {
+    id: i64,
+    guid: Option<String>,
+    url: Option<String>,
+    title: Option<String>,
+    author: Option<String>,
+    pub_date: Option<i64>,
+    body: Option<String>,
+    enclosure_mime: Option<String>,
+    enclosure_link: Option<String>,
+    feed_id: i64,
+    unread: bool,
+    starred: bool,
+}
+
+///Talks to the Nextcloud News app, v1.3 of its api
+pub struct NextcloudClient {
+    client: Client,
+    ///The api root, e.g. https://cloud.example.com/index.php/apps/news/api/v1-3
+    url: String,
+    username: String,
+    password: String,
+    ///Feed urls by the server's feed id, the items only have the id
+    feed_urls: HashMap<i64, String>,
+}
+
+impl NextcloudClient {
+    pub fn new(config: &SyncConfig) -> Self {
+        Self {
+            client: Client::new(),
+            url: config.url.trim_end_matches('/').to_string(),
+            username: config.username.clone(),
+            password: config.password.clone(),
+            feed_urls: HashMap::new(),
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder, what: &str) -> Result<Response> {
+        let response = request
+            .basic_auth(&self.username, Some(&self.password))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("{what} failed: {}", response.status()));
+        }
+        Ok(response)
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> Result<String> {
+        let request = self.client.get(format!("{}{path}", self.url)).query(query);
+        Ok(self
+            .send(request, &format!("GET {path}"))
+            .await?
+            .text()
+            .await?)
+    }
+
+    async fn put(&self, path: &str, body: serde_json::Value) -> Result<()> {
+        let request = self
+            .client
+            .put(format!("{}{path}", self.url))
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        self.send(request, &format!("PUT {path}")).await?;
+        Ok(())
+    }
+}
+
+impl Backend for NextcloudClient {
+    async fn feeds(&mut self) -> Result<Vec<RemoteFeed>> {
+        let folders: Folders = serde_json::from_str(&self.get("/folders", &[]).await?)?;
+        let folders: HashMap<i64, String> = folders
+            .folders
+            .into_iter()
+            .map(|folder| (folder.id, folder.name))
+            .collect();
+        let feeds: Feeds = serde_json::from_str(&self.get("/feeds", &[]).await?)?;
+        self.feed_urls = feeds
+            .feeds
+            .iter()
+            .map(|feed| (feed.id, feed.url.clone()))
+            .collect();
+        Ok(feeds
+            .feeds
+            .into_iter()
+            .map(|feed| RemoteFeed {
+                title: feed.title.unwrap_or(feed.url.clone()),
+                folder: feed.folder_id.and_then(|id| folders.get(&id).cloned()),
+                url: feed.url,
+            })
+            .collect())
+    }
+
+    async fn subscribe(&mut self, url: &str) -> Result<()> {
+        let request = self
+            .client
+            .post(format!("{}/feeds", self.url))
+            .header("Content-Type", "application/json")
+            .body(json!({ "url": url, "folderId": null }).to_string());
+        self.send(request, "POST /feeds").await?;
+        Ok(())
+    }
+
+    async fn items(&mut self, limit: usize) -> Result<Vec<RemoteItem>> {
+        //type 3 is every item, whatever feed or folder it is in
+        let query = [
+            ("batchSize", limit.to_string()),
+            ("type", "3".to_string()),
+            ("id", "0".to_string()),
+            ("getRead", "true".to_string()),
+        ];
+        let items: Items = serde_json::from_str(&self.get("/items", &query).await?)?;
+        Ok(items
+            .items
+            .into_iter()
+            .map(|item| RemoteItem {
+                id: item.id.to_string(),
+                feed_url: self.feed_urls.get(&item.feed_id).cloned(),
+                read: !item.unread,
+                starred: item.starred,
+                item: Item {
+                    title: item.title.map(|title| vec![title]),
+                    link: item.url,
+                    description: item.body,
+                    pub_date: item.pub_date.and_then(unix_date),
+                    author: item.author.map(|author| vec![author]),
+                    guid: item.guid.map(|guid| {
+                        vec![Guid {
+                            is_perma_link: Some("false".to_string()),
+                            value: Some(guid),
+                        }]
+                    }),
+                    enclosure: item.enclosure_link.map(|url| Enclosure {
+                        url,
+                        length: "0".to_string(),
+                        enclosure_type: item.enclosure_mime.unwrap_or_default(),
+                    }),
+                    ..Default::default()
+                },
+            })
+            .collect())
+    }
+
+    async fn set_flag(&mut self, ids: &[String], flag: Flag, on: bool) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let action = match (flag, on) {
+            (Flag::Read, true) => "read",
+            (Flag::Read, false) => "unread",
+            (Flag::Starred, true) => "star",
+            (Flag::Starred, false) => "unstar",
+        };
+        let item_ids: Vec<i64> = ids.iter().filter_map(|id| id.parse().ok()).collect();
+        self.put(
+            &format!("/items/{action}/multiple"),
+            json!({ "itemIds": item_ids }),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::RssConfig,
+        history::History,
+        state::ItemStates,
+        sync::{sync, SyncRecord, SyncState},
+        testing::mock_server,
+    };
+
+    fn respond(request: &str) -> String {
+        let target = request.split_whitespace().nth(1).unwrap_or_default();
+        let body = if target.ends_with("/folders") {
+            r#"{"folders": [{"id": 4, "name": "Podcasts"}]}"#
+        } else if target.ends_with("/feeds") && request.starts_with("GET") {
+            r#"{"feeds": [{"id": 39, "url": "http://feeds.libsyn.com/103241/rss",
+                "title": "Fear of a Black Dragon", "folderId": 4}], "starredCount": 1}"#
+        } else if target.contains("/items?") {
+            r#"{"items": [{"id": 3443, "guid": "lair-of-the-lich", "guidHash": "a3f1",
+                "url": "https://fearofablackdragon.libsyn.com/lair", "title": "Lair of the lich",
+                "author": "Jason", "pubDate": 1717243200, "body": "<p>A dungeon</p>",
+                "enclosureMime": "audio/mpeg", "enclosureLink": "https://traffic.libsyn.com/lair.mp3",
+                "feedId": 39, "unread": true, "starred": true}]}"#
+        } else {
+            "{}"
+        };
+        body.to_string()
+    }
+
+    #[tokio::test]
+    pub async fn test_sync() {
+        let (addr, requests) = mock_server(respond).await;
+        let mut client = NextcloudClient::new(&SyncConfig {
+            url: format!("http://{addr}/index.php/apps/news/api/v1-3"),
+            username: "alice".to_string(),
+            password: "app-password".to_string(),
+            ..Default::default()
+        });
+        let mut cfg = RssConfig::default();
+        let mut history = History::default();
+        let mut item_states = ItemStates::default();
+        //read here before we ever synced, so the server hears of it
+        item_states.set_read("lair-of-the-lich", true);
+        let mut sync_state = SyncState::default();
+
+        let record = sync(
+            &mut client,
+            &mut cfg,
+            &mut history,
+            &mut item_states,
+            &mut sync_state,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            SyncRecord {
+                channels_added: 1,
+                items_added: 1,
+                items_pulled: 1,
+                items_pushed: 1,
+                ..Default::default()
+            },
+            record
+        );
+        assert_eq!(
+            Some(&vec!["Fear of a Black Dragon".to_string()]),
+            cfg.folders.get("Podcasts")
+        );
+        let lair = &history.items_for("http://feeds.libsyn.com/103241/rss")[0];
+        assert_eq!("lair-of-the-lich", lair.id());
+        assert_eq!(
+            "audio/mpeg",
+            lair.enclosure.as_ref().unwrap().enclosure_type
+        );
+        assert!(item_states.is_starred("lair-of-the-lich"));
+
+        let requests = requests.lock().unwrap();
+        //alice:app-password
+        assert!(requests
+            .iter()
+            .all(|request| request.contains("Basic YWxpY2U6YXBwLXBhc3N3b3Jk")));
+        let mark_read = requests
+            .iter()
+            .find(|request| {
+                request.starts_with("PUT /index.php/apps/news/api/v1-3/items/read/multiple")
+            })
+            .unwrap();
+        assert!(mark_read.ends_with(r#"{"itemIds":[3443]}"#));
+    }
+}
@@ -0,0 +1,329 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::{
+    health::{Failure, FeedHealth},
+    model::{Channel, Item},
+    state::ItemStates,
+};
+
+///How the headless commands print what they have to say
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    ///Tab separated, one record to a line, no header
+    #[default]
+    Tsv,
+    ///One json array of all the records
+    Json,
+    ///One json object to a line
+    Ndjson,
+}
+
+#[derive(Args, Debug, Clone, Copy)]
+pub struct OutputArgs {
+    #[arg(long, value_enum, default_value_t)]
+    pub format: Format,
+}
+
+///A record we can also print as a line of tab separated values
+pub trait TsvRecord {
+    fn tsv_fields(&self) -> Vec<String>;
+}
+
+///A channel in the config, and how many items we have for it
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ChannelRecord {
+    pub name: String,
+    pub url: String,
+    pub items: usize,
+    pub unread: usize,
+}
+
+impl TsvRecord for ChannelRecord {
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.url.clone(),
+            self.items.to_string(),
+            self.unread.to_string(),
+        ]
+    }
+}
+
+///How fetching a channel went, error is set if it did not
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FetchRecord {
+    pub name: String,
+    pub url: String,
+    pub new_items: usize,
+    pub error: Option<String>,
+    ///The url it had before it moved for good, the config now has the new one
+    pub moved_from: Option<String>,
+}
+
+impl TsvRecord for FetchRecord {
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.url.clone(),
+            self.new_items.to_string(),
+            self.error.clone().unwrap_or_default(),
+            self.moved_from.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EnclosureRecord {
+    pub url: String,
+    #[serde(rename = "type")]
+    pub enclosure_type: String,
+    ///In bytes, if the feed knows
+    pub size: Option<u64>,
+}
+
+///An item with what we know about it on top of the feed
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ItemRecord {
+    pub id: String,
+    pub channel: String,
+    pub title: String,
+    pub link: Option<String>,
+    ///RFC 3339
+    pub published: Option<String>,
+    pub author: Option<String>,
+    pub categories: Vec<String>,
+    pub read: bool,
+    pub played: bool,
+    ///Seconds into the episode
+    pub position: Option<f64>,
+    pub downloaded: Option<PathBuf>,
+    pub enclosure: Option<EnclosureRecord>,
+}
+
+impl ItemRecord {
+    pub fn new(channel: &Channel, item: &Item, item_states: &ItemStates) -> Self {
+        let state = item_states.get(&item.id()).cloned().unwrap_or_default();
+        Self {
+            id: item.id(),
+            channel: channel.title.clone(),
+            title: item.get_title(),
+            link: item.get_link(),
+            published: item.published().map(|published| published.to_rfc3339()),
+            author: item.get_author(),
+            categories: item.get_categories(),
+            read: state.read,
+            played: state.played,
+            position: state.position,
+            downloaded: item_states
+                .downloaded_path(&item.id())
+                .map(|path| path.to_path_buf()),
+            enclosure: item.enclosure.as_ref().map(|enclosure| EnclosureRecord {
+                url: enclosure.url.clone(),
+                enclosure_type: enclosure.enclosure_type.clone(),
+                size: enclosure.size_bytes(),
+            }),
+        }
+    }
+}
+
+impl TsvRecord for ItemRecord {
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.published.clone().unwrap_or_default(),
+            self.title.clone(),
+            self.link.clone().unwrap_or_default(),
+            self.read.to_string(),
+            self.enclosure
+                .as_ref()
+                .map(|enclosure| enclosure.url.clone())
+                .unwrap_or_default(),
+            self.downloaded
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+        ]
+    }
+}
+
+///How fetching a channel has been going lately
+#[derive(Serialize, Debug, PartialEq)]
+pub struct HealthRecord {
+    pub name: String,
+    pub url: String,
+    ///ok, failing, dead or disabled
+    pub status: String,
+    pub consecutive_failures: u32,
+    ///RFC 3339
+    pub last_success: Option<String>,
+    pub last_error: Option<String>,
+    ///RFC 3339, when we try it again, if we are waiting
+    pub next_attempt: Option<String>,
+    pub failures: Vec<Failure>,
+}
+
+impl HealthRecord {
+    pub fn new(channel: &Channel, health: &FeedHealth, now: DateTime<FixedOffset>) -> Self {
+        Self {
+            name: channel.title.clone(),
+            url: channel.get_link(),
+            status: health.status(now).to_string(),
+            consecutive_failures: health.consecutive_failures,
+            last_success: health.last_success.map(|at| at.to_rfc3339()),
+            last_error: health.last_error.clone(),
+            next_attempt: health
+                .next_attempt()
+                .filter(|next| !health.disabled && *next > now)
+                .map(|next| next.to_rfc3339()),
+            failures: health.failures.clone(),
+        }
+    }
+}
+
+impl TsvRecord for HealthRecord {
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.url.clone(),
+            self.status.clone(),
+            self.consecutive_failures.to_string(),
+            self.last_success.clone().unwrap_or_default(),
+            self.last_error.clone().unwrap_or_default(),
+            self.next_attempt.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+///A finished download
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DownloadRecord {
+    pub item_id: String,
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+impl TsvRecord for DownloadRecord {
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.item_id.clone(),
+            self.path.display().to_string(),
+            self.bytes.to_string(),
+        ]
+    }
+}
+
+///What went wrong, for when a command fails outright
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ErrorRecord {
+    pub error: String,
+}
+
+impl TsvRecord for ErrorRecord {
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![self.error.clone()]
+    }
+}
+
+///Turn the records into text in the format asked for
+pub fn format_records<T: Serialize + TsvRecord>(format: Format, records: &[T]) -> Result<String> {
+    let text = match format {
+        Format::Json => serde_json::to_string_pretty(records)?,
+        Format::Ndjson => records
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<String>, _>>()?
+            .join("\n"),
+        Format::Tsv => records
+            .iter()
+            .map(|record| {
+                record
+                    .tsv_fields()
+                    .iter()
+                    .map(|field| tsv_escape(field))
+                    .collect::<Vec<String>>()
+                    .join("\t")
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+    };
+    Ok(text)
+}
+
+pub fn print_records<T: Serialize + TsvRecord>(format: Format, records: &[T]) -> Result<()> {
+    let text = format_records(format, records)?;
+    if !text.is_empty() {
+        println!("{text}");
+    }
+    Ok(())
+}
+
+///Tabs and newlines would break up the record
+fn tsv_escape(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_format_records() {
+        let records = vec![
+            FetchRecord {
+                name: "Between Two Cairns".to_string(),
+                url: "https://feeds.buzzsprout.com/2042709.rss".to_string(),
+                new_items: 2,
+                error: None,
+                moved_from: None,
+            },
+            FetchRecord {
+                name: "Broken\tfeed".to_string(),
+                url: "http://127.0.0.1:9/rss".to_string(),
+                new_items: 0,
+                error: Some("connection refused".to_string()),
+                moved_from: None,
+            },
+        ];
+        assert_eq!(
+            "Between Two Cairns\thttps://feeds.buzzsprout.com/2042709.rss\t2\t\t\nBroken feed\thttp://127.0.0.1:9/rss\t0\tconnection refused\t",
+            format_records(Format::Tsv, &records).unwrap()
+        );
+        assert_eq!(
+            r#"{"name":"Between Two Cairns","url":"https://feeds.buzzsprout.com/2042709.rss","new_items":2,"error":null,"moved_from":null}"#,
+            format_records(Format::Ndjson, &records)
+                .unwrap()
+                .lines()
+                .next()
+                .unwrap()
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&format_records(Format::Json, &records).unwrap()).unwrap();
+        assert_eq!("connection refused", json[1]["error"]);
+    }
+
+    #[test]
+    pub fn test_item_record() {
+        let item = Item {
+            title: Some(vec!["Goblin markets".to_string()]),
+            link: Some("https://example.com/goblins".to_string()),
+            pub_date: Some("Thu, 30 May 2024 14:30:13 GMT".to_string()),
+            ..Default::default()
+        };
+        let channel = Channel {
+            title: "Pod".to_string(),
+            ..Default::default()
+        };
+        let mut item_states = ItemStates::default();
+        item_states.set_read(&item.id(), true);
+        let record = ItemRecord::new(&channel, &item, &item_states);
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!("https://example.com/goblins", json["id"]);
+        assert_eq!("2024-05-30T14:30:13+00:00", json["published"]);
+        assert_eq!(true, json["read"]);
+        assert!(json["enclosure"].is_null());
+    }
+}
@@ -0,0 +1,367 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    download::DownloadRequest,
+    model::{Channel, Enclosure, Item},
+};
+
+///Characters no filesystem we care about will take in a name
+const ILLEGAL_CHARS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+///Long titles make for unwieldy file names
+const MAX_NAME_CHARS: usize = 120;
+
+///Enclosure types we know the extension of
+const MIME_EXTENSIONS: [(&str, &str); 14] = [
+    ("audio/mpeg", "mp3"),
+    ("audio/mp3", "mp3"),
+    ("audio/x-mp3", "mp3"),
+    ("audio/mp4", "m4a"),
+    ("audio/x-m4a", "m4a"),
+    ("audio/aac", "aac"),
+    ("audio/ogg", "ogg"),
+    ("audio/opus", "opus"),
+    ("audio/flac", "flac"),
+    ("audio/wav", "wav"),
+    ("audio/x-wav", "wav"),
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+    ("application/pdf", "pdf"),
+];
+
+///Where downloads go and what they are called
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DownloadConfig {
+    ///Directory everything is downloaded under
+    pub root: String,
+    ///File name template, '/' makes directories.
+    ///Knows {channel}, {title}, {date}, {ext} and {filename}, the last being the name in the url
+    pub template: String,
+    ///Settings for particular channels, keyed on the channel name or url
+    pub channels: HashMap<String, ChannelDownloadConfig>,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            root: ".".to_string(),
+            template: "{channel}/{date}-{title}.{ext}".to_string(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ChannelDownloadConfig {
+    ///Download this channel here instead of the root, relative paths are under the root
+    pub dir: Option<String>,
+    ///After a refresh, download the newest this many episodes
+    pub auto_latest: Option<usize>,
+    ///After a refresh, download episodes published after this date
+    pub auto_since: Option<String>,
+    ///After a refresh, download episodes whose title matches this regex
+    pub auto_title_regex: Option<String>,
+    ///Delete downloaded episodes beyond the newest this many
+    pub keep_last: Option<usize>,
+}
+
+impl ChannelDownloadConfig {
+    ///Any of the auto rules turns automatic downloads on, together they narrow down what gets downloaded
+    pub fn auto_downloads(&self) -> bool {
+        self.auto_latest.is_some() || self.auto_since.is_some() || self.auto_title_regex.is_some()
+    }
+}
+
+impl DownloadConfig {
+    pub fn channel_config(&self, channel: &Channel) -> Option<&ChannelDownloadConfig> {
+        self.channels
+            .get(&channel.title)
+            .or(self.channels.get(&channel.get_link()))
+    }
+
+    ///The directory the channel's downloads go under
+    pub fn dir_for(&self, channel: &Channel) -> PathBuf {
+        let root = PathBuf::from(&self.root);
+        match self
+            .channel_config(channel)
+            .and_then(|cfg| cfg.dir.as_ref())
+        {
+            Some(dir) => root.join(dir),
+            None => root,
+        }
+    }
+
+    ///What to ask the download manager for, if the item has something to download
+    pub fn download_request(&self, channel: &Channel, item: &Item) -> Option<DownloadRequest> {
+        let enclosure = item.enclosure.as_ref()?;
+        Some(DownloadRequest {
+            item_id: item.id(),
+            title: item.get_title(),
+            path: self.path_for(channel, item),
+            expected_size: enclosure.size_bytes(),
+            url: enclosure.url.clone(),
+        })
+    }
+
+    ///Where the item's enclosure should be downloaded to, before checking for collisions
+    pub fn path_for(&self, channel: &Channel, item: &Item) -> PathBuf {
+        let url_file_name = item
+            .enclosure
+            .as_ref()
+            .map(|enclosure| url_file_name(&enclosure.url))
+            .unwrap_or_default();
+        let ext = item
+            .enclosure
+            .as_ref()
+            .and_then(extension_for)
+            .unwrap_or_default();
+        let date = item
+            .published()
+            .map(|dt| dt.format("%Y-%m-%d").to_string())
+            .unwrap_or("undated".to_string());
+        let mut path = self.dir_for(channel);
+        //each part is filled in and cleaned up on its own, so a '/' in a title cannot make a directory
+        for part in self.template.split('/').filter(|part| !part.is_empty()) {
+            let filled = part
+                .replace("{channel}", &channel.title)
+                .replace("{title}", &item.get_title())
+                .replace("{date}", &date)
+                .replace("{ext}", &ext)
+                .replace("{filename}", &url_file_name);
+            path.push(sanitize(&filled));
+        }
+        path
+    }
+}
+
+///Make the name safe to use as a single file or directory name
+pub fn sanitize(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() {
+                ' '
+            } else if ILLEGAL_CHARS.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let cleaned = cleaned.split_whitespace().collect::<Vec<&str>>().join(" ");
+    //windows will not have trailing dots or spaces, and leading dots hide files
+    let cleaned = cleaned.trim_matches(|c| c == '.' || c == ' ');
+    let cleaned: String = match cleaned.rsplit_once('.') {
+        //keep the extension when shortening
+        Some((stem, ext)) if cleaned.chars().count() > MAX_NAME_CHARS && ext.len() <= 5 => {
+            let stem: String = stem.chars().take(MAX_NAME_CHARS - ext.len() - 1).collect();
+            format!("{}.{ext}", stem.trim_end())
+        }
+        _ => cleaned.chars().take(MAX_NAME_CHARS).collect(),
+    };
+    if cleaned.is_empty() {
+        "untitled".to_string()
+    } else {
+        cleaned
+    }
+}
+
+///The extension, going by the enclosure type first and then the url
+pub fn extension_for(enclosure: &Enclosure) -> Option<String> {
+    let mime = enclosure
+        .enclosure_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    MIME_EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == mime)
+        .map(|(_, ext)| ext.to_string())
+        .or(file_extension(&url_file_name(&enclosure.url)))
+}
+
+///The extension of a file name, if it looks like one
+pub fn file_extension(file_name: &str) -> Option<String> {
+    let (_, ext) = file_name.rsplit_once('.')?;
+    if ext.is_empty() || ext.len() > 5 || !ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(ext.to_lowercase())
+}
+
+///The last segment of the url, without the query string
+pub fn url_file_name(url: &str) -> String {
+    let file_name = url.split('/').next_back().unwrap_or_default();
+    truncate_query_params(file_name).to_string()
+}
+
+///get rid of everything after the last 3, so if we have 'some_pod_cast.mp3?weird-stuff-here',
+///we should get back 'some_pod_cast.mp3'
+fn truncate_query_params(pod_title: &str) -> &str {
+    //this will mess up with unicode, probably, but, eh, cannot be bothered rn
+    //also should probably check if it is an mp3 and etc and blah blah...actually, i can strip
+    //everything after and including the ? and not bother with mp3! Okay let us do it
+    if let Some(ridx) = pod_title.rfind('?') {
+        &pod_title[0..ridx]
+    } else {
+        pod_title
+    }
+}
+
+///The file name out of a Content-Disposition header,
+///e.g. `attachment; filename="episode 12.mp3"`
+pub fn content_disposition_filename(header: &str) -> Option<String> {
+    header.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+        match key.as_str() {
+            "filename" => Some(value.trim_matches('"').to_string()),
+            //filename*=UTF-8''episode%2012.mp3, we only need the extension so skip decoding
+            "filename*" => value.rsplit("''").next().map(|name| name.to_string()),
+            _ => None,
+        }
+        .filter(|name| !name.is_empty())
+    })
+}
+
+///The path, or the path with " (1)", " (2)" etc. added if that one is taken
+pub fn unique_path(path: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    if !taken(path) {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{stem} ({n}){ext}")))
+        .find(|candidate| !taken(candidate))
+        .expect("Should run out of disk before running out of numbers")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_item() -> Item {
+        Item {
+            title: Some(vec!["Trouble at Grog's: part 1/2".to_string()]),
+            pub_date: Some("Thu, 30 May 2024 14:30:13 GMT".to_string()),
+            enclosure: Some(Enclosure {
+                url: "https://example.com/FoaBD-106.mp3?dest-id=549775".to_string(),
+                length: "0".to_string(),
+                enclosure_type: "audio/mpeg".to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_truncate_query_string() {
+        let file_name = "FoaBD-106-Trouble-at-Grogs.mp3?dest-id=549775";
+        let truncated_file_name = truncate_query_params(file_name);
+        assert_eq!("FoaBD-106-Trouble-at-Grogs.mp3", truncated_file_name);
+
+        let file_name_without_suffix = "i_am_mp3_2.mp3";
+        let truncated_file_name_2 = truncate_query_params(file_name_without_suffix);
+        assert_eq!(file_name_without_suffix, truncated_file_name_2);
+    }
+
+    #[test]
+    pub fn test_path_for() {
+        let channel = Channel {
+            title: "Fear of a Black Dragon".to_string(),
+            ..Default::default()
+        };
+        let mut cfg = DownloadConfig {
+            root: "pods".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            PathBuf::from("pods/Fear of a Black Dragon/2024-05-30-Trouble at Grog's_ part 1_2.mp3"),
+            cfg.path_for(&channel, &test_item())
+        );
+
+        cfg.template = "{filename}".to_string();
+        cfg.channels.insert(
+            "Fear of a Black Dragon".to_string(),
+            ChannelDownloadConfig {
+                dir: Some("dragons".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            PathBuf::from("pods/dragons/FoaBD-106.mp3"),
+            cfg.path_for(&channel, &test_item())
+        );
+    }
+
+    #[test]
+    pub fn test_sanitize() {
+        assert_eq!("a_b_c", sanitize("a/b\\c"));
+        assert_eq!("untitled", sanitize(".."));
+        assert_eq!("Episode 1", sanitize("  Episode\t1. "));
+        let long = format!("{}.mp3", "x".repeat(200));
+        let shortened = sanitize(&long);
+        assert_eq!(MAX_NAME_CHARS, shortened.chars().count());
+        assert!(shortened.ends_with("x.mp3"));
+    }
+
+    #[test]
+    pub fn test_extension_for() {
+        let enclosure = |url: &str, enclosure_type: &str| Enclosure {
+            url: url.to_string(),
+            length: "0".to_string(),
+            enclosure_type: enclosure_type.to_string(),
+        };
+        assert_eq!(
+            Some("m4a".to_string()),
+            extension_for(&enclosure("https://example.com/ep", "audio/x-m4a"))
+        );
+        assert_eq!(
+            Some("ogg".to_string()),
+            extension_for(&enclosure("https://example.com/ep.OGG?x=1", ""))
+        );
+        assert_eq!(
+            None,
+            extension_for(&enclosure("https://example.com/download", ""))
+        );
+        assert_eq!(
+            Some("episode 12.mp3".to_string()),
+            content_disposition_filename(r#"attachment; filename="episode 12.mp3""#)
+        );
+        assert_eq!(None, content_disposition_filename("inline"));
+    }
+
+    #[test]
+    pub fn test_unique_path() {
+        let taken = [
+            PathBuf::from("pods/ep.mp3"),
+            PathBuf::from("pods/ep (1).mp3"),
+        ];
+        assert_eq!(
+            PathBuf::from("pods/ep (2).mp3"),
+            unique_path(Path::new("pods/ep.mp3"), |path| taken
+                .iter()
+                .any(|t| t == path))
+        );
+        assert_eq!(
+            PathBuf::from("pods/other.mp3"),
+            unique_path(Path::new("pods/other.mp3"), |path| taken
+                .iter()
+                .any(|t| t == path))
+        );
+    }
+}
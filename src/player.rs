@@ -0,0 +1,408 @@
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+use crate::{model::Enclosure, paths::file_extension};
+
+///Extensions we hand to the player when the enclosure type does not tell us
+const MEDIA_EXTENSIONS: [&str; 11] = [
+    "mp3", "m4a", "aac", "ogg", "opus", "flac", "wav", "mp4", "m4v", "webm", "mkv",
+];
+
+///How often we ask the player where it is
+const POSITION_INTERVAL: Duration = Duration::from_secs(1);
+///How long the player gets to open its ipc socket
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+///Whether the player gets the terminal while it runs, or runs off on its own
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerMode {
+    Detached,
+    #[default]
+    Suspend,
+}
+
+///How we play enclosures
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PlayerConfig {
+    ///Command template, knows {file_or_url}, {pos} in seconds, {title}
+    ///and {ipc_socket}, which turns on position tracking through mpv's json ipc
+    pub command: String,
+    pub mode: PlayerMode,
+    ///Fraction of the episode after which we count it as played
+    pub played_threshold: f64,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        Self {
+            command: "mpv --start={pos} --input-ipc-server={ipc_socket} {file_or_url}".to_string(),
+            mode: PlayerMode::Suspend,
+            played_threshold: 0.9,
+        }
+    }
+}
+
+///The player command with the template filled in
+#[derive(Debug, PartialEq)]
+pub struct PlayerCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl PlayerConfig {
+    ///Fill in the template. The template is split into arguments first,
+    ///so paths and titles with spaces in them stay one argument
+    pub fn command_for(
+        &self,
+        file_or_url: &str,
+        pos: u64,
+        title: &str,
+        ipc_socket: &str,
+    ) -> Result<PlayerCommand> {
+        let mut parts = split_command(&self.command)?.into_iter().map(|part| {
+            part.replace("{file_or_url}", file_or_url)
+                .replace("{pos}", &pos.to_string())
+                .replace("{title}", title)
+                .replace("{ipc_socket}", ipc_socket)
+        });
+        let program = parts
+            .next()
+            .ok_or(anyhow!("No player command configured"))?;
+        Ok(PlayerCommand {
+            program,
+            args: parts.collect(),
+        })
+    }
+
+    pub fn uses_ipc(&self) -> bool {
+        self.command.contains("{ipc_socket}")
+    }
+}
+
+///Somewhere for the player to put its ipc socket, different for every play
+pub fn ipc_socket_path() -> PathBuf {
+    static PLAYS: AtomicUsize = AtomicUsize::new(0);
+    let play = PLAYS.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("rrss-player-{}-{play}.sock", std::process::id()))
+}
+
+///Where the player is in an episode
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaybackUpdate {
+    pub item_id: String,
+    pub position: f64,
+    pub duration: Option<f64>,
+}
+
+///Ask the player over its ipc socket where it is, every second, until it goes away.
+///The channel is unbounded as nobody reads it while a suspended player has the terminal
+#[cfg(unix)]
+pub async fn track_position(
+    socket: PathBuf,
+    item_id: String,
+    tx: mpsc::UnboundedSender<PlaybackUpdate>,
+) -> Result<()> {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UnixStream,
+        time::{sleep, timeout, Instant},
+    };
+
+    let started = Instant::now();
+    let stream = loop {
+        match UnixStream::connect(&socket).await {
+            Ok(stream) => break stream,
+            Err(e) if started.elapsed() > CONNECT_TIMEOUT => {
+                return Err(anyhow!("Player never opened {}: {e}", socket.display()))
+            }
+            Err(_) => sleep(Duration::from_millis(200)).await,
+        }
+    };
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let request = concat!(
+            r#"{"command": ["get_property", "time-pos"], "request_id": 1}"#,
+            "\n",
+            r#"{"command": ["get_property", "duration"], "request_id": 2}"#,
+            "\n"
+        );
+        if writer.write_all(request.as_bytes()).await.is_err() {
+            break;
+        }
+        let (mut position, mut duration) = (None, None);
+        let mut answers = 0;
+        //the player also sends events we do not care about, so we read until both answers are in
+        while answers < 2 {
+            let line = match timeout(POSITION_INTERVAL, lines.next_line()).await {
+                Ok(Ok(Some(line))) => line,
+                Ok(_) => return Ok(()),
+                Err(_) => break,
+            };
+            let Ok(reply) = serde_json::from_str::<serde_json::Value>(&line) else {
+                continue;
+            };
+            match reply["request_id"].as_u64() {
+                Some(1) => position = reply["data"].as_f64(),
+                Some(2) => duration = reply["data"].as_f64(),
+                _ => continue,
+            }
+            answers += 1;
+        }
+        if let Some(position) = position {
+            let update = PlaybackUpdate {
+                item_id: item_id.clone(),
+                position,
+                duration,
+            };
+            if tx.send(update).is_err() {
+                break;
+            }
+        }
+        sleep(POSITION_INTERVAL).await;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn track_position(
+    socket: PathBuf,
+    _item_id: String,
+    _tx: mpsc::UnboundedSender<PlaybackUpdate>,
+) -> Result<()> {
+    Err(anyhow!(
+        "Cannot track the player through {}, only unix sockets are supported",
+        socket.display()
+    ))
+}
+
+impl PlayerCommand {
+    ///Start the player and leave it be, reaping it in the background when it exits
+    pub fn spawn_detached(&self) -> Result<()> {
+        info!("Starting player {} {:?}", self.program, self.args);
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        std::thread::spawn(move || {
+            if let Err(e) = child.wait() {
+                error!("Player did not exit cleanly: {e}");
+            }
+        });
+        Ok(())
+    }
+
+    ///Run the player in our terminal and wait for it to finish
+    pub fn run(&self) -> Result<ExitStatus> {
+        info!("Running player {} {:?}", self.program, self.args);
+        Ok(Command::new(&self.program).args(&self.args).status()?)
+    }
+}
+
+///Split a command line into arguments on whitespace, keeping quoted bits together
+pub fn split_command(command: &str) -> Result<Vec<String>> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_part = false;
+    let mut quote = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                in_part = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_part {
+                    parts.push(std::mem::take(&mut current));
+                    in_part = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_part = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(anyhow!("Unclosed quote in player command: {command}"));
+    }
+    if in_part {
+        parts.push(current);
+    }
+    Ok(parts)
+}
+
+///Audio or video, as opposed to a pdf or some such
+pub fn is_media(enclosure: &Enclosure) -> bool {
+    let enclosure_type = enclosure.enclosure_type.to_lowercase();
+    if enclosure_type.starts_with("audio/") || enclosure_type.starts_with("video/") {
+        return true;
+    }
+    let file_name = enclosure.url.split('?').next().unwrap_or_default();
+    file_extension(file_name).is_some_and(|ext| MEDIA_EXTENSIONS.contains(&ext.as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_command_for() {
+        let cfg = PlayerConfig::default();
+        assert_eq!(
+            PlayerCommand {
+                program: "mpv".to_string(),
+                args: vec!["--start=90".to_string(), "/pods/Episode 1.mp3".to_string()],
+            },
+            PlayerConfig {
+                command: "mpv --start={pos} {file_or_url}".to_string(),
+                ..cfg
+            }
+            .command_for("/pods/Episode 1.mp3", 90, "Episode 1", "")
+            .unwrap()
+        );
+        let quoted = PlayerConfig {
+            command: r#"vlc --meta-title "{title} (rrss)" '{file_or_url}'"#.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "--meta-title",
+                "Episode 1 (rrss)",
+                "https://example.com/ep.mp3"
+            ],
+            quoted
+                .command_for("https://example.com/ep.mp3", 0, "Episode 1", "")
+                .unwrap()
+                .args
+        );
+        assert!(split_command("mpv 'unclosed").is_err());
+        assert!(PlayerConfig {
+            command: " ".to_string(),
+            ..Default::default()
+        }
+        .command_for("ep.mp3", 0, "", "")
+        .is_err());
+        assert!(PlayerConfig::default().uses_ipc());
+    }
+
+    #[test]
+    pub fn test_is_media() {
+        let enclosure = |url: &str, enclosure_type: &str| Enclosure {
+            url: url.to_string(),
+            length: "0".to_string(),
+            enclosure_type: enclosure_type.to_string(),
+        };
+        assert!(is_media(&enclosure("https://example.com/ep", "audio/mpeg")));
+        assert!(is_media(&enclosure("https://example.com/ep.m4a?x=1", "")));
+        assert!(!is_media(&enclosure(
+            "https://example.com/notes.pdf",
+            "application/pdf"
+        )));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn test_stub_player() {
+        let dir = tempfile::tempdir().unwrap();
+        let played = dir.path().join("played");
+        let cfg = PlayerConfig {
+            command: format!(
+                "sh -c 'echo $0 $1 > {}' {{pos}} {{file_or_url}}",
+                played.display()
+            ),
+            mode: PlayerMode::Detached,
+            ..Default::default()
+        };
+        let command = cfg.command_for("episode.mp3", 42, "", "").unwrap();
+        assert!(command.run().unwrap().success());
+        assert_eq!(
+            "42 episode.mp3\n",
+            std::fs::read_to_string(&played).unwrap()
+        );
+
+        std::fs::remove_file(&played).unwrap();
+        command.spawn_detached().unwrap();
+        for _ in 0..100 {
+            if played.exists() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(played.exists(), "Detached player should have run");
+    }
+
+    ///Pretends to be mpv for a few polls, then quits
+    #[cfg(unix)]
+    #[tokio::test]
+    pub async fn test_track_position() {
+        use tokio::{
+            io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+            net::UnixListener,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("mpv.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut position = 10.0;
+            for _ in 0..4 {
+                let Ok(Some(line)) = lines.next_line().await else {
+                    return;
+                };
+                let reply = if line.contains("time-pos") {
+                    position += 1.0;
+                    format!(r#"{{"data":{position},"request_id":1,"error":"success"}}"#)
+                } else {
+                    r#"{"data":1800.0,"request_id":2,"error":"success"}"#.to_string()
+                };
+                let event = r#"{"event":"playback-restart"}"#;
+                let _ = writer
+                    .write_all(format!("{event}\n{reply}\n").as_bytes())
+                    .await;
+            }
+        });
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        track_position(socket, "guid-1".to_string(), tx)
+            .await
+            .unwrap();
+        let mut updates = Vec::new();
+        while let Ok(update) = rx.try_recv() {
+            updates.push(update);
+        }
+        assert_eq!(
+            vec![
+                PlaybackUpdate {
+                    item_id: "guid-1".to_string(),
+                    position: 11.0,
+                    duration: Some(1800.0),
+                },
+                PlaybackUpdate {
+                    item_id: "guid-1".to_string(),
+                    position: 12.0,
+                    duration: Some(1800.0),
+                },
+            ],
+            updates
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    api::fetch_text,
+    model::{format_duration, strip_html, PodcastTranscript},
+};
+
+///Transcript types we can make sense of, best first
+const TRANSCRIPT_TYPES: [&str; 6] = [
+    "text/vtt",
+    "application/srt",
+    "application/x-subrip",
+    "application/json",
+    "text/html",
+    "text/plain",
+];
+
+///The json chapters file of the podcast namespace
+#[derive(Deserialize, Debug)]
+struct ChaptersFile {
+    chapters: Vec<Chapter>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Chapter {
+    pub start_time: f64,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    ///Chapters can be left out of tables of contents
+    #[serde(default = "default_toc")]
+    pub toc: bool,
+}
+
+fn default_toc() -> bool {
+    true
+}
+
+///The json transcript of the podcast namespace
+#[derive(Deserialize, Debug)]
+struct JsonTranscript {
+    segments: Vec<JsonSegment>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonSegment {
+    speaker: Option<String>,
+    start_time: Option<f64>,
+    body: String,
+}
+
+pub fn parse_chapters(json: &str) -> Result<Vec<Chapter>> {
+    let chapters_file: ChaptersFile = serde_json::from_str(json)?;
+    Ok(chapters_file
+        .chapters
+        .into_iter()
+        .filter(|chapter| chapter.toc)
+        .collect())
+}
+
+///Fetch the chapters and make them displayable
+pub async fn load_chapters(url: &str) -> Result<String> {
+    let json = fetch_text(url).await?;
+    Ok(chapters_text(&parse_chapters(&json)?))
+}
+
+///Fetch the transcript and make it displayable
+pub async fn load_transcript(transcript: &PodcastTranscript) -> Result<String> {
+    let body = fetch_text(&transcript.url).await?;
+    let transcript_type = transcript.transcript_type.clone().unwrap_or_default();
+    Ok(transcript_text(&transcript_type, &body))
+}
+
+///One chapter to a line, with the time it starts
+pub fn chapters_text(chapters: &[Chapter]) -> String {
+    if chapters.is_empty() {
+        return "No chapters".to_string();
+    }
+    chapters
+        .iter()
+        .map(|chapter| {
+            let start = format_duration(chapter.start_time.max(0.0) as u64);
+            let title = chapter.title.clone().unwrap_or("Untitled".to_string());
+            match &chapter.url {
+                Some(url) => format!("{start:>8}  {title}  <{url}>"),
+                None => format!("{start:>8}  {title}"),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+///Of all the transcripts the feed offers, the one we are best at displaying
+pub fn pick_transcript(transcripts: &[PodcastTranscript]) -> Option<&PodcastTranscript> {
+    let rank = |transcript: &PodcastTranscript| {
+        let transcript_type = transcript
+            .transcript_type
+            .clone()
+            .unwrap_or_default()
+            .to_lowercase();
+        TRANSCRIPT_TYPES
+            .iter()
+            .position(|known| *known == transcript_type)
+            .unwrap_or(TRANSCRIPT_TYPES.len())
+    };
+    transcripts.iter().min_by_key(|transcript| rank(transcript))
+}
+
+///Turn a transcript into plain text, with the cue times kept at the start of the lines
+pub fn transcript_text(transcript_type: &str, body: &str) -> String {
+    let transcript_type = transcript_type.to_lowercase();
+    if transcript_type.contains("json") {
+        if let Ok(json) = serde_json::from_str::<JsonTranscript>(body) {
+            return json_transcript_text(json);
+        }
+    }
+    if transcript_type.contains("vtt") || transcript_type.contains("srt") || body.contains("-->") {
+        return subtitles_text(body);
+    }
+    if transcript_type.contains("html") {
+        return strip_html(body);
+    }
+    body.to_string()
+}
+
+fn json_transcript_text(json: JsonTranscript) -> String {
+    json.segments
+        .into_iter()
+        .map(|segment| {
+            let start = segment
+                .start_time
+                .map(|start| format!("[{}] ", format_duration(start.max(0.0) as u64)))
+                .unwrap_or_default();
+            match segment.speaker {
+                Some(speaker) => format!("{start}{speaker}: {}", segment.body.trim()),
+                None => format!("{start}{}", segment.body.trim()),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+///Srt and vtt both come as blocks of optional number, times, then text
+fn subtitles_text(body: &str) -> String {
+    let cue_time = Regex::new(r"^(?:(\d+):)?(\d{1,2}):(\d{2})[.,]\d{3}\s*-->")
+        .expect("Cue regex should compile");
+    let mut lines = Vec::new();
+    for block in body.replace("\r\n", "\n").split("\n\n") {
+        let mut start = None;
+        let mut text = Vec::new();
+        for line in block.lines().map(|line| line.trim()) {
+            if let Some(captures) = cue_time.captures(line) {
+                let number = |idx: usize| {
+                    captures
+                        .get(idx)
+                        .and_then(|number| number.as_str().parse::<u64>().ok())
+                        .unwrap_or(0)
+                };
+                start = Some(format_duration(
+                    number(1) * 3600 + number(2) * 60 + number(3),
+                ));
+            } else if start.is_some() && !line.is_empty() {
+                text.push(strip_html(line));
+            }
+        }
+        if let Some(start) = start {
+            lines.push(format!("[{start}] {}", text.join(" ")));
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_chapters() {
+        let json = r#"{"version": "1.2.0", "chapters": [
+            {"startTime": 0, "title": "Intro"},
+            {"startTime": 95.5, "title": "Hidden", "toc": false},
+            {"startTime": 3723, "title": "Goblin markets", "url": "https://example.com/goblins"}
+        ]}"#;
+        let chapters = parse_chapters(json).unwrap();
+        assert_eq!(2, chapters.len());
+        assert_eq!(
+            "    0:00  Intro\n 1:02:03  Goblin markets  <https://example.com/goblins>",
+            chapters_text(&chapters)
+        );
+    }
+
+    #[test]
+    pub fn test_pick_transcript() {
+        let transcript = |transcript_type: &str| PodcastTranscript {
+            url: format!("https://example.com/{transcript_type}"),
+            transcript_type: Some(transcript_type.to_string()),
+            language: None,
+            rel: None,
+        };
+        let transcripts = vec![
+            transcript("text/html"),
+            transcript("application/srt"),
+            transcript("application/pdf"),
+        ];
+        assert_eq!(
+            Some(&transcripts[1]),
+            pick_transcript(&transcripts),
+            "Should prefer srt"
+        );
+        assert_eq!(None, pick_transcript(&[]));
+    }
+
+    #[test]
+    pub fn test_transcript_text() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:04,000\r\nWelcome to the show\r\n\r\n2\r\n00:01:05,500 --> 00:01:08,000\r\nToday: <i>goblins</i>\r\nand markets\r\n";
+        assert_eq!(
+            "[0:01] Welcome to the show\n[1:05] Today: goblins and markets",
+            transcript_text("application/srt", srt)
+        );
+        let vtt = "WEBVTT\n\n00:00:01.000 --> 00:00:04.000\n<v Yochai>Welcome</v>\n";
+        assert_eq!("[0:01] Welcome", transcript_text("text/vtt", vtt));
+        let json = r#"{"version": "1.0.0", "segments": [{"speaker": "Yochai", "startTime": 1.5, "body": "Welcome"}]}"#;
+        assert_eq!(
+            "[0:01] Yochai: Welcome",
+            transcript_text("application/json", json)
+        );
+        assert_eq!("Plain", transcript_text("text/plain", "Plain"));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::model::{strip_html, Channel, Item};
+
+const TITLE_WEIGHT: usize = 3;
+const AUTHOR_WEIGHT: usize = 2;
+const BODY_WEIGHT: usize = 1;
+
+///An item that matched the search, and where to find it
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub channel_idx: usize,
+    pub channel_title: String,
+    pub item: Item,
+    pub score: usize,
+    ///Position of the item in its channel, channels list their newest items first
+    pub item_idx: usize,
+}
+
+///Search every item of every channel for all the words in the query.
+///Titles count more than authors, authors count more than the body text.
+///Best matches first, newer items first when the matches are as good.
+pub fn search(channels: &[Channel], query: &str) -> Vec<SearchHit> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| term.to_lowercase())
+        .collect();
+    if terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut hits = Vec::new();
+    for (channel_idx, channel) in channels.iter().enumerate() {
+        for (item_idx, item) in channel.items.iter().enumerate() {
+            if let Some(score) = score_item(item, &terms) {
+                hits.push(SearchHit {
+                    channel_idx,
+                    channel_title: channel.title.clone(),
+                    item: item.clone(),
+                    score,
+                    item_idx,
+                });
+            }
+        }
+    }
+    hits.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| b.item.published().cmp(&a.item.published()))
+            .then(a.item_idx.cmp(&b.item_idx))
+    });
+    hits
+}
+
+///None if any of the terms is missing from the item
+fn score_item(item: &Item, terms: &[String]) -> Option<usize> {
+    let title = item.get_title().to_lowercase();
+    let author = item.get_author().unwrap_or_default().to_lowercase();
+    let body = strip_html(&item.get_content().unwrap_or_default()).to_lowercase();
+
+    let mut score = 0;
+    for term in terms {
+        let term_score = title.matches(term.as_str()).count() * TITLE_WEIGHT
+            + author.matches(term.as_str()).count() * AUTHOR_WEIGHT
+            + body.matches(term.as_str()).count() * BODY_WEIGHT;
+        if term_score == 0 {
+            return None;
+        }
+        score += term_score;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(title: &str, description: &str) -> Item {
+        Item {
+            title: Some(vec![title.to_string()]),
+            description: Some(description.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_search_ranks_title_above_body() {
+        let channels = vec![
+            Channel {
+                title: "Between Two Cairns".to_string(),
+                items: vec![item("Dungeon crawl", "<p>We talk about goblins</p>")],
+                ..Default::default()
+            },
+            Channel {
+                title: "Fear of a Black Dragon".to_string(),
+                items: vec![
+                    item("Goblin market", "A module review"),
+                    item("Trouble at Grogs", "No match here"),
+                ],
+                ..Default::default()
+            },
+        ];
+        let hits = search(&channels, "goblin");
+        assert_eq!(2, hits.len());
+        assert_eq!("Goblin market", hits[0].item.get_title());
+        assert_eq!("Fear of a Black Dragon", hits[0].channel_title);
+        assert_eq!("Dungeon crawl", hits[1].item.get_title());
+    }
+
+    #[test]
+    pub fn test_search_needs_all_terms() {
+        let channels = vec![Channel {
+            items: vec![item("Goblin market", "A module review")],
+            ..Default::default()
+        }];
+        assert_eq!(1, search(&channels, "goblin review").len());
+        assert!(search(&channels, "goblin dragon").is_empty());
+        assert!(search(&channels, "   ").is_empty());
+    }
+
+    #[test]
+    pub fn test_search_prefers_newer_on_ties() {
+        let channels = vec![Channel {
+            items: vec![item("Goblins II", ""), item("Goblins I", "")],
+            ..Default::default()
+        }];
+        let hits = search(&channels, "goblins");
+        assert_eq!("Goblins II", hits[0].item.get_title());
+
+        let mut older = item("Goblins I", "");
+        older.pub_date = Some("Thu, 30 May 2024 14:30:13 -0400".to_string());
+        let mut newer = item("Goblins II", "");
+        newer.pub_date = Some("Fri, 31 May 2024 09:00:00 GMT".to_string());
+        let channels = vec![
+            Channel {
+                items: vec![older],
+                ..Default::default()
+            },
+            Channel {
+                items: vec![newer],
+                ..Default::default()
+            },
+        ];
+        let hits = search(&channels, "goblins");
+        assert_eq!("Goblins II", hits[0].item.get_title());
+    }
+}
@@ -0,0 +1,546 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{error, info};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    time::timeout,
+};
+
+use crate::{
+    cli::{channel_record, channels, find_channel, folder_channels, with_stored_items},
+    config::{load_config, StoreFiles},
+    feed::{select_entries, write_feed, FeedFormat, FeedInfo},
+    history::History,
+    output::{ChannelRecord, ErrorRecord, ItemRecord},
+    state::ItemStates,
+};
+
+///Requests bigger than this are turned away
+const MAX_BODY: usize = 1024 * 1024;
+const MAX_HEADERS: usize = 100;
+///How long a client gets to send the whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+///How many items /items gives back unless asked for more
+const DEFAULT_ITEM_LIMIT: usize = 200;
+///How many items the feeds have unless asked for more
+const DEFAULT_FEED_LIMIT: usize = 50;
+
+const ENDPOINTS: &str = "rrss
+GET  /channels                 channels with item and unread counts
+GET  /items                    items, newest first. ?channel= ?folder= ?unread=true ?starred=true ?limit=
+POST /items/read               {\"ids\": [...], \"read\": true} marks items read, or unread with false. Needs the token
+GET  /feed.xml, /feed.atom     the items as one rss or atom feed. ?folder= ?starred=true ?limit= ?title=
+";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    ///The host header, so feeds can link to themselves
+    pub host: Option<String>,
+    pub content_type: Option<String>,
+    pub authorization: Option<String>,
+    ///Path and query as sent
+    pub target: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json<T: Serialize>(value: &T) -> Result<Self> {
+        Ok(Self {
+            status: 200,
+            content_type: "application/json",
+            body: serde_json::to_string_pretty(value)?,
+        })
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(&ErrorRecord {
+                error: message.to_string(),
+            })
+            .unwrap_or_default(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            415 => "Unsupported Media Type",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+///Body of a request to mark items read
+#[derive(Deserialize, Debug)]
+struct MarkRead {
+    ids: Vec<String>,
+    ///Defaults to marking them read
+    read: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct MarkReadRecord {
+    changed: usize,
+}
+
+///Answer requests until the listener fails.
+///Requests are handled one at a time, the stores are files and we do not want two writers.
+///Without a token nothing can be changed
+pub async fn serve(listener: TcpListener, files: StoreFiles, token: Option<String>) -> Result<()> {
+    let lock = Arc::new(Mutex::new(()));
+    let token = Arc::new(token);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let files = files.clone();
+        let lock = lock.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &files, token.as_deref(), &lock).await {
+                error!("Request from {addr} failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    files: &StoreFiles,
+    token: Option<&str>,
+    lock: &Mutex<()>,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let response = match timeout(REQUEST_TIMEOUT, read_request(&mut reader)).await {
+        Ok(Ok(request)) => {
+            info!("{} {}", request.method, request.target);
+            let _guard = lock.lock().await;
+            route(&request, files, token)
+        }
+        Ok(Err(e)) => Response::error(400, &e.to_string()),
+        Err(_) => Response::error(408, "Took too long sending the request"),
+    };
+    let header = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    );
+    let stream = reader.get_mut();
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn read_request(reader: &mut BufReader<TcpStream>) -> Result<Request> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(anyhow!("Malformed request line"));
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        ..Default::default()
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    request.path = percent_decode(path);
+    request.query = parse_query(query);
+
+    let mut content_length = 0;
+    for _ in 0..MAX_HEADERS {
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        match name.trim().to_lowercase().as_str() {
+            "content-length" => content_length = value.trim().parse()?,
+            "host" => request.host = Some(value.trim().to_string()),
+            "content-type" => request.content_type = Some(value.trim().to_string()),
+            "authorization" => request.authorization = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(anyhow!("Request body too large"));
+    }
+    request.body = vec![0; content_length];
+    reader.read_exact(&mut request.body).await?;
+    Ok(request)
+}
+
+///Work out the answer to a request, anything that goes wrong is a 500
+pub fn route(request: &Request, files: &StoreFiles, token: Option<&str>) -> Response {
+    if request.method == "POST" {
+        if let Some(refused) = refuse_write(request, token) {
+            return refused;
+        }
+    }
+    match handle(request, files) {
+        Ok(response) => response,
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+///Writes need the token, and json, which a form on some other site cannot send us
+fn refuse_write(request: &Request, token: Option<&str>) -> Option<Response> {
+    let Some(token) = token else {
+        return Some(Response::error(
+            403,
+            "Read only, start rrss serve with --token to allow changes",
+        ));
+    };
+    let sent = request
+        .authorization
+        .as_deref()
+        .and_then(|authorization| authorization.strip_prefix("Bearer "));
+    if !sent.is_some_and(|sent| same_secret(sent.trim(), token)) {
+        return Some(Response::error(401, "Wrong or missing bearer token"));
+    }
+    let is_json = request.content_type.as_deref().is_some_and(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if !is_json {
+        return Some(Response::error(415, "Send application/json"));
+    }
+    None
+}
+
+///Compare without giving away how much of the secret was right
+fn same_secret(sent: &str, secret: &str) -> bool {
+    sent.len() == secret.len()
+        && sent
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |differs, (a, b)| differs | (a ^ b))
+            == 0
+}
+
+fn handle(request: &Request, files: &StoreFiles) -> Result<Response> {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => Ok(Response {
+            status: 200,
+            content_type: "text/plain; charset=utf-8",
+            body: ENDPOINTS.to_string(),
+        }),
+        ("GET", "/channels") => list_channels(files),
+        ("GET", "/items") => list_items(request, files),
+        ("POST", "/items/read") => mark_read(request, files),
+        ("GET", "/feed.xml") => feed(request, files, FeedFormat::Rss),
+        ("GET", "/feed.atom") => feed(request, files, FeedFormat::Atom),
+        (_, "/" | "/channels" | "/items" | "/items/read" | "/feed.xml" | "/feed.atom") => Ok(
+            Response::error(405, &format!("{} not allowed here", request.method)),
+        ),
+        (_, path) => Ok(Response::error(404, &format!("Nothing at {path}"))),
+    }
+}
+
+fn list_channels(files: &StoreFiles) -> Result<Response> {
+    let cfg = load_config(files.config.clone())?.unwrap_or_default();
+    let history = History::load(files.history.clone())?;
+    let item_states = ItemStates::load(files.state.clone())?;
+    let records: Vec<ChannelRecord> = channels(&cfg)
+        .iter()
+        .map(|channel| channel_record(channel, &history, &item_states))
+        .collect();
+    Response::json(&records)
+}
+
+fn query_flag(request: &Request, name: &str) -> bool {
+    request
+        .query
+        .get(name)
+        .is_some_and(|value| value == "true" || value == "1")
+}
+
+fn query_limit(request: &Request, default: usize) -> Result<usize> {
+    match request.query.get("limit") {
+        Some(limit) => limit
+            .parse()
+            .map_err(|_| anyhow!("limit should be a number, not {limit}")),
+        None => Ok(default),
+    }
+}
+
+fn list_items(request: &Request, files: &StoreFiles) -> Result<Response> {
+    let cfg = load_config(files.config.clone())?.unwrap_or_default();
+    let selected = match (request.query.get("channel"), request.query.get("folder")) {
+        (Some(channel), _) => find_channel(&cfg, channel).map(|channel| vec![channel]),
+        (None, Some(folder)) => folder_channels(&cfg, folder),
+        (None, None) => Ok(channels(&cfg)),
+    };
+    let selected = match selected {
+        Ok(selected) => selected,
+        Err(e) => return Ok(Response::error(404, &e.to_string())),
+    };
+    let limit = match query_limit(request, DEFAULT_ITEM_LIMIT) {
+        Ok(limit) => limit,
+        Err(e) => return Ok(Response::error(400, &e.to_string())),
+    };
+    let with_items = with_stored_items(selected, &History::load(files.history.clone())?);
+    let item_states = ItemStates::load(files.state.clone())?;
+    let unread_only = query_flag(request, "unread");
+    let records: Vec<ItemRecord> = select_entries(
+        &with_items,
+        &item_states,
+        query_flag(request, "starred"),
+        usize::MAX,
+    )
+    .into_iter()
+    .filter(|(_, item)| !unread_only || !item_states.is_read(&item.id()))
+    .take(limit)
+    .map(|(channel, item)| ItemRecord::new(channel, item, &item_states))
+    .collect();
+    Response::json(&records)
+}
+
+fn mark_read(request: &Request, files: &StoreFiles) -> Result<Response> {
+    let mark_read: MarkRead = match serde_json::from_slice(&request.body) {
+        Ok(mark_read) => mark_read,
+        Err(e) => return Ok(Response::error(400, &format!("Bad body: {e}"))),
+    };
+    let read = mark_read.read.unwrap_or(true);
+    let mut changed = 0;
+    ItemStates::update(files.state.clone(), |item_states| {
+        changed = mark_read
+            .ids
+            .iter()
+            .filter(|id| item_states.set_read(id, read))
+            .count();
+    })?;
+    Response::json(&MarkReadRecord { changed })
+}
+
+fn feed(request: &Request, files: &StoreFiles, format: FeedFormat) -> Result<Response> {
+    let cfg = load_config(files.config.clone())?.unwrap_or_default();
+    let folder = request.query.get("folder");
+    let selected = match folder {
+        Some(folder) => match folder_channels(&cfg, folder) {
+            Ok(selected) => selected,
+            Err(e) => return Ok(Response::error(404, &e.to_string())),
+        },
+        None => channels(&cfg),
+    };
+    let limit = match query_limit(request, DEFAULT_FEED_LIMIT) {
+        Ok(limit) => limit,
+        Err(e) => return Ok(Response::error(400, &e.to_string())),
+    };
+    let with_items = with_stored_items(selected, &History::load(files.history.clone())?);
+    let item_states = ItemStates::load(files.state.clone())?;
+    let entries = select_entries(
+        &with_items,
+        &item_states,
+        query_flag(request, "starred"),
+        limit,
+    );
+    let title = request
+        .query
+        .get("title")
+        .or(folder)
+        .cloned()
+        .unwrap_or("rrss".to_string());
+    let info = FeedInfo {
+        description: format!("{title}, from rrss"),
+        title,
+        link: request
+            .host
+            .as_ref()
+            .map(|host| format!("http://{host}{}", request.target)),
+    };
+    Ok(Response {
+        status: 200,
+        content_type: match format {
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+        },
+        body: write_feed(format, &info, &entries, Utc::now()),
+    })
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect()
+}
+
+///Undo the %xx escapes, and + for space, in a url
+fn percent_decode(text: &str) -> String {
+    percent_decode_str(&text.replace('+', " "))
+        .decode_utf8_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{api::parse_rss, model::Channel, model::Item};
+
+    #[test]
+    pub fn test_parse_query() {
+        let query =
+            parse_query("channel=Between+Two%20Cairns&unread=true&url=https%3A%2F%2Fx.com%2F");
+        assert_eq!("Between Two Cairns", query["channel"]);
+        assert_eq!("true", query["unread"]);
+        assert_eq!("https://x.com/", query["url"]);
+        assert_eq!("100%", percent_decode("100%"));
+        assert_eq!("1+1", percent_decode("1%2B1"));
+    }
+
+    #[tokio::test]
+    pub async fn test_serve() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| Some(dir.path().join(name).display().to_string());
+        let files = StoreFiles {
+            config: path("rrss.toml"),
+            history: path("history.toml"),
+            state: path("state.toml"),
+            sync: path("sync.toml"),
+            health: path("health.toml"),
+            digest: path("digest.toml"),
+            socket: path("rrss.sock"),
+        };
+        std::fs::write(
+            files.config.clone().unwrap(),
+            "[channels]\n'Between Two Cairns'='https://feeds.buzzsprout.com/2042709.rss'\n",
+        )
+        .unwrap();
+        let mut channel = Channel {
+            title: "Between Two Cairns".to_string(),
+            items: [("41", "2024-05-23"), ("42", "2024-05-30")]
+                .iter()
+                .map(|(episode, date)| Item {
+                    title: Some(vec![format!("Episode {episode}")]),
+                    link: Some(format!("https://www.buzzsprout.com/2042709/{episode}")),
+                    pub_date: Some(format!("{date}T10:00:00Z")),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        channel.set_link("https://feeds.buzzsprout.com/2042709.rss");
+        let mut history = History::default();
+        history.merge(&channel);
+        history.save(files.history.clone()).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, files.clone(), Some("sesame".to_string())));
+        let client = reqwest::Client::new();
+
+        let channels: serde_json::Value = client
+            .get(format!("http://{addr}/channels"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .map(|text| serde_json::from_str(&text).unwrap())
+            .unwrap();
+        assert_eq!(2, channels[0]["unread"]);
+
+        let mark_read = r#"{"ids": ["https://www.buzzsprout.com/2042709/42"]}"#;
+        let post = |token: &str, content_type: &str| {
+            client
+                .post(format!("http://{addr}/items/read"))
+                .bearer_auth(token)
+                .header("Content-Type", content_type)
+                .body(mark_read)
+                .send()
+        };
+        //a form posted from some web page gets nowhere
+        let response = post("sesame", "text/plain").await.unwrap();
+        assert_eq!(415, response.status().as_u16());
+        let response = post("open up", "application/json").await.unwrap();
+        assert_eq!(401, response.status().as_u16());
+        assert!(!ItemStates::load(files.state.clone())
+            .unwrap()
+            .is_read("https://www.buzzsprout.com/2042709/42"));
+        let response = post("sesame", "application/json").await.unwrap();
+        assert_eq!(200, response.status().as_u16());
+        assert!(ItemStates::load(files.state.clone())
+            .unwrap()
+            .is_read("https://www.buzzsprout.com/2042709/42"));
+
+        let items: serde_json::Value = client
+            .get(format!(
+                "http://{addr}/items?channel=between+two+cairns&unread=true"
+            ))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .map(|text| serde_json::from_str(&text).unwrap())
+            .unwrap();
+        assert_eq!(1, items.as_array().unwrap().len());
+        assert_eq!("Episode 41", items[0]["title"]);
+
+        let feed = client
+            .get(format!("http://{addr}/feed.xml"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let feed = parse_rss(&feed).unwrap().channel;
+        assert_eq!("Episode 42", feed.items[0].get_title());
+
+        let missing = client
+            .get(format!("http://{addr}/items?channel=nope"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(404, missing.status().as_u16());
+
+        //without a token we only answer questions
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let read_only = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, files.clone(), None));
+        let response = client
+            .post(format!("http://{read_only}/items/read"))
+            .header("Content-Type", "application/json")
+            .body(mark_read)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(403, response.status().as_u16());
+    }
+}
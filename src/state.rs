@@ -0,0 +1,350 @@
+use anyhow::Result;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{with_store_lock, write_store},
+    model::format_duration,
+};
+
+///What we know about an item beyond what the feed tells us
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ItemState {
+    pub read: bool,
+    ///Where we downloaded the enclosure to
+    pub downloaded: Option<PathBuf>,
+    ///How far into the episode we got, in seconds
+    pub position: Option<f64>,
+    ///How long the player says the episode is, in seconds
+    pub duration: Option<f64>,
+    ///We listened past the played threshold
+    pub played: bool,
+    ///Picked out to keep, or to share in a feed
+    pub starred: bool,
+    ///The retention rules deleted the download, so the auto rules leave it be
+    pub pruned: bool,
+}
+
+impl ItemState {
+    ///Take the fields that changed from `base` to `ours`, leaving the rest as they are
+    fn merge_changes(&mut self, base: &ItemState, ours: &ItemState) {
+        if ours.read != base.read {
+            self.read = ours.read;
+        }
+        if ours.downloaded != base.downloaded {
+            self.downloaded = ours.downloaded.clone();
+        }
+        if ours.position != base.position {
+            self.position = ours.position;
+        }
+        if ours.duration != base.duration {
+            self.duration = ours.duration;
+        }
+        if ours.played != base.played {
+            self.played = ours.played;
+        }
+        if ours.starred != base.starred {
+            self.starred = ours.starred;
+        }
+        if ours.pruned != base.pruned {
+            self.pruned = ours.pruned;
+        }
+    }
+
+    ///How far along we are, for the item list
+    pub fn progress_label(&self) -> Option<String> {
+        if self.played {
+            return Some("played".to_string());
+        }
+        let position = self.position.filter(|position| *position >= 1.0)?;
+        match self.duration.filter(|duration| *duration > 0.0) {
+            Some(duration) => Some(format!("{:.0}%", position / duration * 100.0)),
+            None => Some(format!("at {}", format_duration(position as u64))),
+        }
+    }
+}
+
+///Per item state, keyed on the item id.
+///Kept apart from the history because it changes far more often.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct ItemStates {
+    #[serde(default)]
+    pub items: HashMap<String, ItemState>,
+    ///The items we changed since loading, as they were before,
+    ///so `save_changes` writes only the fields we changed
+    #[serde(skip)]
+    changed: HashMap<String, ItemState>,
+}
+
+fn state_file(path: Option<String>) -> String {
+    path.unwrap_or(".rrss_state.toml".to_string())
+}
+
+impl ItemStates {
+    ///Load the item states from file, if the file exists
+    ///If no file passed, will default to checking for './.rrss_state.toml'
+    pub fn load(path: Option<String>) -> Result<ItemStates> {
+        let state_file = state_file(path);
+        if !Path::new(&state_file).exists() {
+            info!("No item state found at {state_file}");
+            return Ok(ItemStates::default());
+        }
+        let toml_str = read_to_string(state_file)?;
+        let states: ItemStates = toml::from_str(&toml_str)?;
+        Ok(states)
+    }
+
+    ///Write the item states over the file, so a reader never sees half of them.
+    ///The tui, daemon, server and command line all write here, so most want `update` or `save_changes`
+    pub fn save(&self, path: Option<String>) -> Result<()> {
+        let state_file = state_file(path);
+        info!("Saving item state to {state_file}");
+        write_store(&state_file, &toml::to_string(self)?)
+    }
+
+    ///Change the item states on file, holding a lock so nobody else writes in between.
+    ///Returns the item states as they are now
+    pub fn update<F: FnOnce(&mut ItemStates)>(
+        path: Option<String>,
+        change: F,
+    ) -> Result<ItemStates> {
+        let state_file = state_file(path);
+        with_store_lock(&state_file, || {
+            let mut states = ItemStates::load(Some(state_file.clone()))?;
+            change(&mut states);
+            states.save(Some(state_file.clone()))?;
+            states.changed.clear();
+            Ok(states)
+        })
+    }
+
+    ///Write the fields we changed over what is on file, and pick up whatever others changed meanwhile
+    pub fn save_changes(&mut self, path: Option<String>) -> Result<()> {
+        let changes: Vec<(&String, &ItemState, &ItemState)> = self
+            .changed
+            .iter()
+            .filter_map(|(id, base)| Some((id, base, self.items.get(id)?)))
+            .filter(|(_, base, ours)| base != ours)
+            .collect();
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let updated = ItemStates::update(path, |on_file| {
+            for (id, base, ours) in changes {
+                on_file
+                    .items
+                    .entry(id.clone())
+                    .or_default()
+                    .merge_changes(base, ours);
+            }
+        })?;
+        *self = updated;
+        Ok(())
+    }
+
+    ///The state of the item to change, `save_changes` will then write the fields that changed
+    pub fn state_mut(&mut self, id: &str) -> &mut ItemState {
+        if !self.changed.contains_key(id) {
+            let base = self.items.get(id).cloned().unwrap_or_default();
+            self.changed.insert(id.to_string(), base);
+        }
+        self.items.entry(id.to_string()).or_default()
+    }
+
+    pub fn is_read(&self, id: &str) -> bool {
+        self.items.get(id).is_some_and(|state| state.read)
+    }
+
+    ///Returns true if this changed anything
+    pub fn set_read(&mut self, id: &str, read: bool) -> bool {
+        if self.is_read(id) == read {
+            return false;
+        }
+        self.state_mut(id).read = read;
+        true
+    }
+
+    pub fn is_starred(&self, id: &str) -> bool {
+        self.items.get(id).is_some_and(|state| state.starred)
+    }
+
+    ///Returns whether the item is now starred
+    pub fn toggle_starred(&mut self, id: &str) -> bool {
+        let state = self.state_mut(id);
+        state.starred = !state.starred;
+        state.starred
+    }
+
+    ///Where the enclosure was downloaded to, as long as it is still there
+    pub fn downloaded_path(&self, id: &str) -> Option<&Path> {
+        self.items
+            .get(id)
+            .and_then(|state| state.downloaded.as_deref())
+            .filter(|path| path.exists())
+    }
+
+    ///Record where the player got to, marking the item played and read once past the threshold
+    pub fn set_position(&mut self, id: &str, position: f64, duration: Option<f64>, threshold: f64) {
+        let state = self.state_mut(id);
+        state.position = Some(position);
+        if duration.is_some() {
+            state.duration = duration;
+        }
+        if state
+            .duration
+            .is_some_and(|duration| duration > 0.0 && position / duration >= threshold)
+        {
+            state.played = true;
+            state.read = true;
+        }
+    }
+
+    ///Where to start playing from, the start again for played episodes
+    pub fn resume_position(&self, id: &str) -> u64 {
+        match self.items.get(id) {
+            Some(state) if !state.played => state.position.unwrap_or(0.0) as u64,
+            _ => 0,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ItemState> {
+        self.items.get(id)
+    }
+
+    ///Returns true if this changed anything
+    pub fn set_downloaded(&mut self, id: &str, path: Option<PathBuf>) -> bool {
+        let current = self.items.get(id);
+        let unchanged = current.and_then(|state| state.downloaded.as_ref()) == path.as_ref();
+        let unpruned = path.is_some() && current.is_some_and(|state| state.pruned);
+        if unchanged && !unpruned {
+            return false;
+        }
+        let state = self.state_mut(id);
+        let changed = state.downloaded != path;
+        //asking for it again means they want it after all
+        state.pruned &= path.is_none();
+        state.downloaded = path;
+        changed
+    }
+
+    pub fn is_pruned(&self, id: &str) -> bool {
+        self.items.get(id).is_some_and(|state| state.pruned)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_set_read() {
+        let mut states = ItemStates::default();
+        assert!(!states.is_read("guid-1"));
+        assert!(states.set_read("guid-1", true));
+        assert!(!states.set_read("guid-1", true));
+        assert!(states.is_read("guid-1"));
+
+        let test_path = "test_state.toml".to_string();
+        states.save(Some(test_path.clone())).unwrap();
+        let loaded = ItemStates::load(Some(test_path.clone())).unwrap();
+        std::fs::remove_file(test_path).unwrap();
+        assert!(loaded.is_read("guid-1"));
+        assert!(!loaded.is_read("guid-2"));
+    }
+
+    #[test]
+    pub fn test_save_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Some(dir.path().join("state.toml").display().to_string());
+        let mut ours = ItemStates::load(path.clone()).unwrap();
+        ours.set_read("guid-1", true);
+
+        //someone else marks another item read meanwhile
+        ItemStates::update(path.clone(), |theirs| {
+            theirs.set_read("guid-2", true);
+        })
+        .unwrap();
+
+        ours.save_changes(path.clone()).unwrap();
+        assert!(ours.is_read("guid-2"), "We pick up their change");
+        let on_file = ItemStates::load(path).unwrap();
+        assert!(on_file.is_read("guid-1"));
+        assert!(on_file.is_read("guid-2"), "Our save keeps their change");
+    }
+
+    #[test]
+    pub fn test_save_changes_keeps_their_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Some(dir.path().join("state.toml").display().to_string());
+        ItemStates::update(path.clone(), |states| {
+            states.set_read("guid-1", true);
+        })
+        .unwrap();
+        let mut ours = ItemStates::load(path.clone()).unwrap();
+
+        //the daemon finishes a download, and the server marks it unread, while we look on
+        let download = dir.path().join("episode.mp3");
+        ItemStates::update(path.clone(), |theirs| {
+            theirs.set_downloaded("guid-1", Some(download.clone()));
+            theirs.set_read("guid-1", false);
+        })
+        .unwrap();
+
+        //moving over an item we think is read already changes nothing
+        assert!(!ours.set_read("guid-1", true));
+        assert!(!ours.set_read("guid-3", false));
+        ours.set_position("guid-1", 60.0, Some(600.0), 0.9);
+        ours.save_changes(path.clone()).unwrap();
+        assert!(ours.get("guid-3").is_none());
+
+        let on_file = ItemStates::load(path).unwrap();
+        let state = on_file.get("guid-1").unwrap();
+        assert!(!state.read, "Their read flag stays");
+        assert_eq!(Some(download), state.downloaded, "Their download stays");
+        assert_eq!(Some(60.0), state.position, "Our position is saved");
+        assert!(on_file.get("guid-3").is_none());
+    }
+
+    #[test]
+    pub fn test_downloaded_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("episode.mp3");
+        let mut states = ItemStates::default();
+        assert!(states.set_downloaded("guid-1", Some(path.clone())));
+        assert!(!states.set_downloaded("guid-1", Some(path.clone())));
+        assert_eq!(
+            None,
+            states.downloaded_path("guid-1"),
+            "File is not there yet"
+        );
+        std::fs::write(&path, b"episode").unwrap();
+        assert_eq!(Some(path.as_path()), states.downloaded_path("guid-1"));
+        assert!(states.set_downloaded("guid-1", None));
+        assert_eq!(None, states.downloaded_path("guid-1"));
+    }
+
+    #[test]
+    pub fn test_set_position() {
+        let mut states = ItemStates::default();
+        states.set_position("guid-1", 600.0, Some(2400.0), 0.9);
+        assert_eq!(600, states.resume_position("guid-1"));
+        assert_eq!(
+            Some("25%".to_string()),
+            states.get("guid-1").unwrap().progress_label()
+        );
+        assert!(!states.is_read("guid-1"));
+
+        //the duration sticks around when the player has not told us it again
+        states.set_position("guid-1", 2200.0, None, 0.9);
+        assert!(states.get("guid-1").unwrap().played);
+        assert!(states.is_read("guid-1"));
+        assert_eq!(0, states.resume_position("guid-1"), "Played starts over");
+        assert_eq!(0, states.resume_position("guid-2"));
+    }
+}
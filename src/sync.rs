@@ -0,0 +1,389 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::read_to_string,
+    path::Path,
+};
+
+use crate::{
+    cli::{channels, Fetched},
+    config::{load_config, save_config, RssConfig, StoreFiles},
+    fever::FeverClient,
+    greader::GReaderClient,
+    history::History,
+    model::{Channel, Item},
+    nextcloud::NextcloudClient,
+    output::TsvRecord,
+    state::ItemStates,
+};
+
+///How many of the newest items on the server we look at each sync
+pub const SYNC_ITEMS: usize = 1000;
+
+///Which api the server talks
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncApi {
+    ///Google Reader, as FreshRSS, Miniflux and Inoreader have
+    #[default]
+    GReader,
+    ///Nextcloud News v1.3
+    Nextcloud,
+    Fever,
+}
+
+///Where to sync to. Without it rrss keeps everything local
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SyncConfig {
+    #[serde(default)]
+    pub api: SyncApi,
+    ///The api root, e.g. https://freshrss.example.com/api/greader.php
+    pub url: String,
+    pub username: String,
+    ///FreshRSS wants the api password here, not the login one
+    pub password: String,
+}
+
+///A feed the server has us subscribed to
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteFeed {
+    pub title: String,
+    pub url: String,
+    pub folder: Option<String>,
+}
+
+///An item as the server has it: what is in it, and its flags
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteItem {
+    ///The server's id, only good for talking to the server
+    pub id: String,
+    ///Url of the feed it came from, when the server says
+    pub feed_url: Option<String>,
+    pub item: Item,
+    pub read: bool,
+    pub starred: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Flag {
+    Read,
+    Starred,
+}
+
+///A server that fetches the feeds for us, and keeps the read and starred flags
+pub trait Backend {
+    async fn feeds(&mut self) -> Result<Vec<RemoteFeed>>;
+    async fn subscribe(&mut self, url: &str) -> Result<()>;
+    ///The newest items across all the feeds
+    async fn items(&mut self, limit: usize) -> Result<Vec<RemoteItem>>;
+    ///Set or clear the flag on the items, by server id
+    async fn set_flag(&mut self, ids: &[String], flag: Flag, on: bool) -> Result<()>;
+}
+
+///The rfc2822 dates feeds use, from the unix seconds the apis use
+pub fn unix_date(seconds: i64) -> Option<String> {
+    DateTime::from_timestamp(seconds, 0).map(|date| date.to_rfc2822())
+}
+
+///Read and starred as they were on both sides after the last sync, by server item id.
+///Tells us which side changed since, so the change wins
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct SyncState {
+    pub last_sync: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub items: HashMap<String, SyncedItem>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq)]
+pub struct SyncedItem {
+    pub read: bool,
+    pub starred: bool,
+}
+
+fn sync_file(path: Option<String>) -> String {
+    path.unwrap_or(".rrss_sync.toml".to_string())
+}
+
+impl SyncState {
+    ///Load the sync state from file, if the file exists
+    ///If no file passed, will default to checking for './.rrss_sync.toml'
+    pub fn load(path: Option<String>) -> Result<SyncState> {
+        let sync_file = sync_file(path);
+        if !Path::new(&sync_file).exists() {
+            info!("No sync state found at {sync_file}");
+            return Ok(SyncState::default());
+        }
+        let toml_str = read_to_string(sync_file)?;
+        Ok(toml::from_str(&toml_str)?)
+    }
+
+    pub fn save(&self, path: Option<String>) -> Result<()> {
+        let sync_file = sync_file(path);
+        info!("Saving sync state to {sync_file}");
+        std::fs::write(sync_file, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+///Where a flag should end up: whichever side changed it since the last sync wins,
+///and the first time round a flag set on either side sticks
+pub fn merge_flag(local: bool, remote: bool, last: Option<bool>) -> bool {
+    match last {
+        Some(last) if remote != last => remote,
+        Some(_) => local,
+        None => local || remote,
+    }
+}
+
+///How a sync went
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct SyncRecord {
+    ///Feeds on the server we did not have
+    pub channels_added: usize,
+    ///Our channels the server did not have
+    pub channels_pushed: usize,
+    ///Items the server had that we had not fetched ourselves
+    pub items_added: usize,
+    ///Items whose read or starred state we took from the server
+    pub items_pulled: usize,
+    ///Items whose read or starred state we sent to the server
+    pub items_pushed: usize,
+}
+
+impl TsvRecord for SyncRecord {
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.channels_added.to_string(),
+            self.channels_pushed.to_string(),
+            self.items_added.to_string(),
+            self.items_pulled.to_string(),
+            self.items_pushed.to_string(),
+        ]
+    }
+}
+
+///Sync the feeds and folders both ways, take in the items we do not have,
+///then sync read and starred state for the items we both have.
+///Items are matched on their link or id, as the server has its own ids.
+///Unsubscribing is left alone either way, it is too easy to lose a channel by accident
+pub async fn sync<B: Backend>(
+    backend: &mut B,
+    cfg: &mut RssConfig,
+    history: &mut History,
+    item_states: &mut ItemStates,
+    sync_state: &mut SyncState,
+) -> Result<SyncRecord> {
+    let mut record = SyncRecord::default();
+
+    let feeds = backend.feeds().await?;
+    let remote_urls: HashSet<&str> = feeds.iter().map(|feed| feed.url.as_str()).collect();
+    for channel in channels(cfg) {
+        let url = channel.get_link();
+        if !remote_urls.contains(url.as_str()) {
+            info!("Subscribing to {url} on the server");
+            //not every api can subscribe, that should not stop the rest of the sync
+            match backend.subscribe(&url).await {
+                Ok(()) => record.channels_pushed += 1,
+                Err(e) => warn!("Could not subscribe to {url} on the server: {e}"),
+            }
+        }
+    }
+    for feed in feeds.iter() {
+        let name = match cfg.channels.iter().find(|(_, url)| **url == feed.url) {
+            Some((name, _)) => name.clone(),
+            None => {
+                let name = if cfg.channels.contains_key(&feed.title) {
+                    format!("{} ({})", feed.title, feed.url)
+                } else {
+                    feed.title.clone()
+                };
+                info!("Adding {name} from the server");
+                cfg.channels.insert(name.clone(), feed.url.clone());
+                record.channels_added += 1;
+                name
+            }
+        };
+        if let Some(folder) = &feed.folder {
+            let members = cfg.folders.entry(folder.clone()).or_default();
+            if !members
+                .iter()
+                .any(|member| *member == name || *member == feed.url)
+            {
+                members.push(name);
+            }
+        }
+    }
+
+    let remote_items = backend.items(SYNC_ITEMS).await?;
+    let mut new_items: HashMap<&str, Vec<Item>> = HashMap::new();
+    for remote in remote_items.iter() {
+        let Some(feed_url) = &remote.feed_url else {
+            continue;
+        };
+        let known = history.items_for(feed_url).iter().any(|item| {
+            item.id() == remote.item.id()
+                || (item.link.is_some() && item.get_link() == remote.item.get_link())
+        });
+        if !known {
+            new_items
+                .entry(feed_url)
+                .or_default()
+                .push(remote.item.clone());
+        }
+    }
+    for (feed_url, items) in new_items {
+        record.items_added += items.len();
+        let mut channel = Channel {
+            items,
+            ..Default::default()
+        };
+        channel.set_link(feed_url);
+        history.merge(&channel);
+    }
+
+    let mut local_ids: HashMap<String, String> = HashMap::new();
+    for item in history.channels.values().flatten() {
+        if let Some(link) = item.get_link() {
+            local_ids.insert(link, item.id());
+        }
+        local_ids.insert(item.id(), item.id());
+    }
+    let mut to_flag: HashMap<(Flag, bool), Vec<String>> = HashMap::new();
+    for remote in remote_items {
+        let local_id = remote
+            .item
+            .get_link()
+            .and_then(|link| local_ids.get(&link))
+            .or(local_ids.get(&remote.item.id()));
+        let Some(local_id) = local_id else {
+            continue;
+        };
+        let last = sync_state.items.get(&remote.id);
+        let local_read = item_states.is_read(local_id);
+        let local_starred = item_states.is_starred(local_id);
+        let read = merge_flag(local_read, remote.read, last.map(|last| last.read));
+        let starred = merge_flag(local_starred, remote.starred, last.map(|last| last.starred));
+
+        if read != local_read || starred != local_starred {
+            item_states.set_read(local_id, read);
+            if starred != local_starred {
+                item_states.toggle_starred(local_id);
+            }
+            record.items_pulled += 1;
+        }
+        if read != remote.read || starred != remote.starred {
+            if read != remote.read {
+                to_flag
+                    .entry((Flag::Read, read))
+                    .or_default()
+                    .push(remote.id.clone());
+            }
+            if starred != remote.starred {
+                to_flag
+                    .entry((Flag::Starred, starred))
+                    .or_default()
+                    .push(remote.id.clone());
+            }
+            record.items_pushed += 1;
+        }
+        sync_state
+            .items
+            .insert(remote.id, SyncedItem { read, starred });
+    }
+    for ((flag, on), ids) in to_flag {
+        backend.set_flag(&ids, flag, on).await?;
+    }
+    sync_state.last_sync = Some(Local::now());
+    Ok(record)
+}
+
+///Sync using the stores in the files, saving whatever changed
+pub async fn sync_files(files: &StoreFiles) -> Result<SyncRecord> {
+    let mut cfg = load_config(files.config.clone())?.unwrap_or_default();
+    let sync_config = cfg
+        .sync
+        .clone()
+        .ok_or(anyhow!("No [sync] in the config, rrss is local only"))?;
+    let folders_before = cfg.folders.clone();
+    let mut history = History::load(files.history.clone())?;
+    let mut item_states = ItemStates::load(files.state.clone())?;
+    let mut sync_state = SyncState::load(files.sync.clone())?;
+    let (c, h, i, s) = (&mut cfg, &mut history, &mut item_states, &mut sync_state);
+    let record = match sync_config.api {
+        SyncApi::GReader => {
+            let mut client = GReaderClient::login(&sync_config).await?;
+            sync(&mut client, c, h, i, s).await?
+        }
+        SyncApi::Nextcloud => {
+            let mut client = NextcloudClient::new(&sync_config);
+            sync(&mut client, c, h, i, s).await?
+        }
+        SyncApi::Fever => {
+            let mut client = FeverClient::new(&sync_config);
+            sync(&mut client, c, h, i, s).await?
+        }
+    };
+    if record.channels_added > 0 || cfg.folders != folders_before {
+        save_config(files.config.clone(), cfg)?;
+    }
+    history.save_changes(files.history.clone())?;
+    item_states.save_changes(files.state.clone())?;
+    sync_state.save(files.sync.clone())?;
+    Ok(record)
+}
+
+///Have the server do the fetching: sync, then say what came in for each of the channels
+pub async fn fetch_from_server(
+    cfg: &RssConfig,
+    files: &StoreFiles,
+) -> Result<(Vec<Fetched>, SyncRecord)> {
+    let before = History::load(files.history.clone())?;
+    let record = sync_files(files).await?;
+    let history = History::load(files.history.clone())?;
+    let fetched = channels(cfg)
+        .into_iter()
+        .map(|channel| {
+            let url = channel.get_link();
+            let known: HashSet<String> = before
+                .items_for(&url)
+                .iter()
+                .map(|item| item.id())
+                .collect();
+            let items = history.items_for(&url);
+            let unseen = items
+                .iter()
+                .map(|item| item.id())
+                .filter(|id| !known.contains(id))
+                .collect();
+            Fetched {
+                channel,
+                items,
+                unseen,
+                error: None,
+                moved_from: None,
+                skipped: false,
+            }
+        })
+        .collect();
+    Ok((fetched, record))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_merge_flag() {
+        //first sync, either side setting it is enough
+        assert!(merge_flag(true, false, None));
+        assert!(merge_flag(false, true, None));
+        assert!(!merge_flag(false, false, None));
+        //the side that changed since wins
+        assert!(!merge_flag(true, false, Some(true)));
+        assert!(!merge_flag(false, true, Some(true)));
+        assert!(merge_flag(true, false, Some(false)));
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+///A server for the backends, fetching and downloads to talk to in tests.
+///Each request goes to the responder, and they are all kept for looking at after
+pub async fn mock_server<F, R>(respond: F) -> (SocketAddr, Arc<Mutex<Vec<String>>>)
+where
+    F: Fn(&str) -> R + Send + 'static,
+    R: Into<Vec<u8>>,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let requests_clone = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            //read until we have the headers and as much body as they promise
+            loop {
+                let read = stream.read(&mut buf).await.unwrap_or(0);
+                request.extend_from_slice(&buf[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length: ")
+                                .map(str::to_string)
+                        })
+                        .and_then(|length| length.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+                if read == 0 {
+                    break;
+                }
+            }
+            let request = String::from_utf8_lossy(&request).to_string();
+            requests_clone.lock().unwrap().push(request.clone());
+            let body: Vec<u8> = respond(&request).into();
+            //responders write the whole response themselves for anything but a 200
+            let response = if body.starts_with(b"HTTP/") {
+                body
+            } else {
+                let mut response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .into_bytes();
+                response.extend(body);
+                response
+            };
+            let _ = stream.write_all(&response).await;
+        }
+    });
+    (addr, requests)
+}
@@ -1,11 +1,10 @@
+use chrono::Utc;
 use clipboard::{ClipboardContext, ClipboardProvider};
 use log::{error, info};
-use regex::Regex;
 use std::{
     collections::HashMap,
-    fs::File,
-    io::{self, copy, stdout, Cursor, Stdout},
-    thread,
+    io::{self, Stdout},
+    path::PathBuf,
     time::Duration,
 };
 use tokio::{sync::mpsc, time::sleep};
@@ -15,7 +14,6 @@ use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-    ExecutableCommand,
 };
 
 use ratatui::{
@@ -23,15 +21,22 @@ use ratatui::{
     layout::{Constraint, Layout},
     prelude::*,
     style::palette::tailwind,
-    widgets::{Block, BorderType, Borders, Clear, List, ListItem, Paragraph, Wrap},
+    widgets::{
+        Block, BorderType, Borders, Clear, LineGauge, List, ListItem, ListState, Paragraph, Wrap,
+    },
     Frame, Terminal,
 };
 
-use crate::model::Channel;
+use crate::model::{format_duration, format_size, strip_html, Channel, Item};
 use crate::{
-    api::fetch_rss_feed,
+    api::{discover_feed, fetch_rss_feed, Discovered},
     config::{save_config, RssConfig},
-    model::{App, AppState, SelectedPane, StatefulItemList},
+    daemon::{send, DaemonCommand, DaemonReply},
+    download::{Download, DownloadStatus},
+    model::{AddChannelStep, App, AppState, ContentView, SelectedPane, StatefulItemList},
+    player::{ipc_socket_path, is_media, track_position, PlaybackUpdate, PlayerMode},
+    podcast::{load_chapters, load_transcript, pick_transcript},
+    sync::sync_files,
 };
 
 const POPUP_TIME: u64 = 1;
@@ -40,8 +45,15 @@ const NORMAL_ROW_COLOR: Color = tailwind::SLATE.c950;
 const ALT_ROW_COLOR: Color = tailwind::SLATE.c900;
 const SELECTED_STYLE_FG: Color = tailwind::BLUE.c300;
 const TEXT_COLOR: Color = tailwind::SLATE.c200;
+const DATE_COLOR: Color = tailwind::SLATE.c400;
+const EPISODE_COLOR: Color = tailwind::AMBER.c300;
+const UNREAD_MARKER: &str = "●";
+const DOWNLOADED_MARKER: &str = "↓";
+const STARRED_MARKER: &str = "★";
+///How often we ask an attached daemon about its downloads
+const DAEMON_POLL: Duration = Duration::from_secs(2);
 const HEADER_TEXT: &str = r"RRSS rss reader
-        [R]efresh channnel | [S]ave channels | [A]dd channel";
+        [R]efresh channnel | [S]ave channels | [A]dd channel | [/] Search";
 pub fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
     let mut stdout = io::stdout();
     enable_raw_mode().context("Unable to enable raw mode")?;
@@ -57,6 +69,15 @@ pub fn restore_terminal() -> Result<()> {
     Ok(())
 }
 
+///Take the terminal back after handing it to something else
+fn resume_terminal<B: Backend>(term: &mut Terminal<B>) -> Result<()> {
+    enable_raw_mode().context("Unable to enable raw mode")?;
+    execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)
+        .context("Alternate screen switch...FAILED")?;
+    term.clear()?;
+    Ok(())
+}
+
 ///Sets up the ui and returns the 4 components
 ///Top bar
 ///Main area which has left bar and main concat_idents!(
@@ -81,10 +102,12 @@ pub fn ui(frame: &mut Frame, app: &mut App) -> Result<()> {
 
     let header = match app.selected_pane {
         SelectedPane::Channels => Paragraph::new(HEADER_TEXT).block(header_block),
-        SelectedPane::Items => {
-            Paragraph::new(format!("{}{}", HEADER_TEXT, " | [O]pen | [D]ownload "))
-                .block(header_block)
-        }
+        SelectedPane::Items => Paragraph::new(format!(
+            "{}{}",
+            HEADER_TEXT,
+            " | [O]pen | [P]lay | [D]ownload | [X] delete download | Do[W]nloads | [C]hapters | [T]ranscript | [*] star "
+        ))
+        .block(header_block),
     };
     frame.render_widget(header, top);
 
@@ -92,8 +115,7 @@ pub fn ui(frame: &mut Frame, app: &mut App) -> Result<()> {
 
     display_selected_channel_items(frame, app, items_pane)?;
 
-    let item_content = app.content_pane_text();
-    display_selected_item(frame, &item_content, content_pane)?;
+    display_selected_item(frame, app, content_pane)?;
 
     //we can leverage this to show the download
     if let Some(text) = app.info_popup_text.clone() {
@@ -108,6 +130,22 @@ pub fn ui(frame: &mut Frame, app: &mut App) -> Result<()> {
         show_add_channel_dialog(frame, app)
     }
 
+    if app.state == AppState::ChooseFeed {
+        show_feed_choices_dialog(frame, app)
+    }
+
+    if app.state == AppState::Search {
+        show_search_dialog(frame, app)
+    }
+
+    if app.state == AppState::Downloads {
+        show_downloads_popup(frame, app)
+    }
+
+    if app.state == AppState::Health {
+        show_health_popup(frame, app)
+    }
+
     Ok(())
 }
 
@@ -125,7 +163,17 @@ fn display_channels(frame: &mut Frame, app: &mut App, channel_pane: Rect) -> Res
         .channels
         .channels
         .iter()
-        .map(|chnl| ListItem::new(chnl.title.clone()))
+        .map(|chnl| {
+            let channel_item = match app.num_unread(chnl) {
+                0 => ListItem::new(chnl.title.clone()),
+                unread => ListItem::new(format!("{} ({unread})", chnl.title)),
+            };
+            //failing or gone, [H] shows why
+            match app.health.get(&chnl.get_link()) {
+                Some(health) if health.is_broken() => channel_item.fg(Color::Red),
+                _ => channel_item,
+            }
+        })
         .collect();
 
     let channel_list = List::new(channel_items)
@@ -152,8 +200,15 @@ fn get_border_type(selected: bool) -> BorderType {
 ///Display the items for the selected channel in their pane
 fn display_selected_channel_items(frame: &mut Frame, app: &mut App, item_pane: Rect) -> Result<()> {
     let bt = get_border_type(app.selected_pane == SelectedPane::Items);
+    let items_title = match app.get_selected_channel().and_then(|chnl| chnl.published()) {
+        Some(published) => format!(
+            "Items (updated {})",
+            app.display.format_list_date(&published)
+        ),
+        None => "Items".to_string(),
+    };
     let items_block = Block::new()
-        .title("Items")
+        .title(items_title)
         .borders(Borders::ALL)
         .border_type(bt)
         .style(Style::default().fg(TEXT_COLOR));
@@ -165,9 +220,46 @@ fn display_selected_channel_items(frame: &mut Frame, app: &mut App, item_pane: R
         let items: Vec<ListItem> = app
             .current_items
             .items
-            .clone()
             .iter()
-            .map(|item| ListItem::new(item.get_title()))
+            .map(|item| {
+                let date = item
+                    .published()
+                    .map(|dt| app.display.format_list_date(&dt))
+                    .unwrap_or_default();
+                let (read_marker, title_style) = if app.is_read(item) {
+                    (" ", Style::default().fg(DATE_COLOR))
+                } else {
+                    (UNREAD_MARKER, Style::default().fg(TEXT_COLOR))
+                };
+                //season, episode and duration for podcasts
+                let episode_info = [
+                    item.episode_label(),
+                    item.duration_secs().map(format_duration),
+                    app.progress_label(item),
+                ]
+                .into_iter()
+                .flatten()
+                .map(|info| format!("{info} "))
+                .collect::<String>();
+                let downloaded_marker = if app.downloaded_path(item).is_some() {
+                    DOWNLOADED_MARKER
+                } else {
+                    " "
+                };
+                let starred_marker = if app.is_starred(item) {
+                    STARRED_MARKER
+                } else {
+                    " "
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(read_marker, Style::default().fg(SELECTED_STYLE_FG)),
+                    Span::styled(starred_marker, Style::default().fg(EPISODE_COLOR)),
+                    Span::styled(downloaded_marker, Style::default().fg(EPISODE_COLOR)),
+                    Span::styled(format!("{date:>4} "), Style::default().fg(DATE_COLOR)),
+                    Span::styled(episode_info, Style::default().fg(EPISODE_COLOR)),
+                    Span::styled(item.get_title(), title_style),
+                ]))
+            })
             .collect();
         List::new(items).block(items_block).highlight_style(
             Style::default()
@@ -184,35 +276,132 @@ fn display_selected_channel_items(frame: &mut Frame, app: &mut App, item_pane: R
     Ok(())
 }
 
-///Display the content for the selected item in its pane
-fn display_selected_item(frame: &mut Frame, html_text: &str, item_pane: Rect) -> Result<()> {
+///Display the content for the selected item in its pane,
+///with what we know about the item in a header above the text
+fn display_selected_item(frame: &mut Frame, app: &App, item_pane: Rect) -> Result<()> {
+    let (title, text) = match (app.content_view, app.sub_pane_text()) {
+        (ContentView::Chapters, Some(chapters)) => ("Chapters", chapters),
+        (ContentView::Transcript, Some(transcript)) => ("Transcript", transcript),
+        _ => ("Content", strip_html(&app.content_pane_text())),
+    };
     let view_block = Block::new()
-        .title("Content")
+        .title(title)
         .borders(Borders::all())
         .border_type(BorderType::Thick)
         .style(Style::default().fg(Color::Cyan));
-    //let parsed_text = Html::parse_fragment(html_text);
-    let parsed_text = html_escape::decode_html_entities(html_text);
-
-    //TODO Should this be initialized outside?
-    let regex = Regex::new("<\\/?[a-z][a-z0-9]*[^<>]*>|<!--.*?-->")?;
-    let parsed_text_str = parsed_text.to_string();
-    let stripped_text = regex.replace_all(&parsed_text_str, "");
-    //let stripped_text = parsed_text_str;
-    //let item_content = Paragraph::new(html_text)
-    let item_content = Paragraph::new(stripped_text)
+    let item_content = Paragraph::new(text)
         .wrap(Wrap { trim: true })
         .block(view_block);
-    frame.render_widget(item_content, item_pane);
+
+    let header_lines = app
+        .get_selected_item()
+        .map(|item| item_header_lines(app, item))
+        .unwrap_or_default();
+    if header_lines.is_empty() {
+        frame.render_widget(item_content, item_pane);
+        return Ok(());
+    }
+
+    //borders take up two lines, and we leave the body at least half the pane
+    let header_height = (header_lines.len() as u16 + 2).min(item_pane.height / 2);
+    let [header_pane, body_pane] =
+        Layout::vertical([Constraint::Length(header_height), Constraint::Fill(1)]).areas(item_pane);
+    let header_block = Block::new()
+        .borders(Borders::all())
+        .border_type(BorderType::Thick)
+        .style(Style::default().fg(Color::Cyan));
+    let header = Paragraph::new(header_lines)
+        .wrap(Wrap { trim: true })
+        .block(header_block);
+    frame.render_widget(header, header_pane);
+    frame.render_widget(item_content, body_pane);
     Ok(())
 }
 
+///Title, channel, author, date, categories, link and enclosure of the item,
+///leaving out whatever the feed did not give us
+fn item_header_lines<'a>(app: &App, item: &Item) -> Vec<Line<'a>> {
+    let label_style = Style::default().fg(DATE_COLOR);
+    let field = |label: &str, value: String| {
+        Line::from(vec![
+            Span::styled(format!("{label:<11}"), label_style),
+            Span::raw(value),
+        ])
+    };
+
+    let mut lines = vec![Line::styled(
+        item.get_title(),
+        Style::default().add_modifier(Modifier::BOLD),
+    )];
+    if let Some(channel) = app.get_selected_channel() {
+        lines.push(field("Channel", channel.title.clone()));
+    }
+    if let Some(author) = item.get_author() {
+        lines.push(field("Author", author));
+    }
+    let episode = [
+        item.episode_label(),
+        item.is_explicit().then_some("explicit".to_string()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<String>>();
+    if !episode.is_empty() {
+        lines.push(field("Episode", episode.join(", ")));
+    }
+    let people = item.get_people();
+    if !people.is_empty() {
+        lines.push(field("People", people.join(", ")));
+    }
+    if let Some(image) = item.get_image() {
+        lines.push(field("Image", image));
+    }
+    if let Some(published) = item.published() {
+        lines.push(field("Published", app.display.format_timestamp(&published)));
+    } else if let Some(pub_date) = &item.pub_date {
+        lines.push(field("Published", pub_date.clone()));
+    }
+    let categories = item.get_categories();
+    if !categories.is_empty() {
+        lines.push(field("Categories", categories.join(", ")));
+    }
+    if let Some(link) = item.get_link() {
+        lines.push(field("Link", link));
+    }
+    if let Some(comments) = item.get_comments_link() {
+        lines.push(field("Comments", comments));
+    }
+    if let Some(enclosure) = &item.enclosure {
+        let mut details = vec![enclosure.enclosure_type.clone()];
+        if let Some(size) = enclosure.size_bytes() {
+            details.push(format_size(size));
+        }
+        if let Some(duration) = item.duration_secs() {
+            details.push(format_duration(duration));
+        }
+        details.retain(|detail| !detail.is_empty());
+        lines.push(field("Enclosure", details.join(", ")));
+    }
+    if let Some(path) = app.downloaded_path(item) {
+        lines.push(field("Downloaded", path.display().to_string()));
+    }
+    lines
+}
+
 ///Run run run the app merrily down the bitstream
 pub async fn run_app<'a, B: Backend>(term: &mut Terminal<B>, app: &mut App<'a>) -> Result<()> {
     // let app_arc = Arc::new(Mutex::new(app));
     let (channel_reload_tx, mut channel_reload_rx) = mpsc::channel(1);
     let (popup_tx, mut popup_rx) = mpsc::channel(1);
-    let mut i = 1;
+    let (sub_pane_tx, mut sub_pane_rx) = mpsc::channel(1);
+    let (playback_tx, mut playback_rx) = mpsc::unbounded_channel();
+    let (playback_done_tx, mut playback_done_rx) = mpsc::unbounded_channel();
+    let (daemon_tx, mut daemon_rx) = mpsc::channel(16);
+    let (add_channel_tx, mut add_channel_rx) = mpsc::channel(1);
+    let (sync_tx, mut sync_rx) = mpsc::channel(1);
+    if let Some(socket) = app.daemon_socket.clone() {
+        watch_daemon(socket, daemon_tx.clone());
+    }
     loop {
         // let mut app = app_arc.lock().unwrap();
         term.draw(|f| {
@@ -222,53 +411,148 @@ pub async fn run_app<'a, B: Backend>(term: &mut Terminal<B>, app: &mut App<'a>)
         if event::poll(Duration::from_millis(100))? {
             if let Event::Key(key) = event::read()? {
                 match app.state {
-                    AppState::AddChannel => match key.code {
-                        KeyCode::Esc => {
+                    AppState::AddChannel => match (&app.add_channel_step, key.code) {
+                        (AddChannelStep::Editing, KeyCode::Esc) => {
                             app.unshow_add_channel_dialog();
                         }
-                        KeyCode::Enter => {
-                            //found text in the text area
-                            if let Some(url) = app.add_channel() {
-                                //get our thread clones
-                                let (chnl_tx_clone, popup_tx_clone) =
-                                    (channel_reload_tx.clone(), popup_tx.clone());
-
-                                app.info_popup_text = Some("Attempting load...".to_string());
+                        (AddChannelStep::Editing, KeyCode::Enter) => {
+                            //found a url in the text area, we fetch it before adding anything
+                            if let Some(url) = app.add_channel_url() {
+                                fetch_for_preview(url, add_channel_tx.clone());
+                            }
+                        }
+                        (AddChannelStep::Editing, KeyCode::Char('v') | KeyCode::Char('V'))
+                            if KeyModifiers::CONTROL == key.modifiers =>
+                        {
+                            let mut clip: ClipboardContext = ClipboardProvider::new().unwrap();
+                            let contents = clip.get_contents().unwrap();
+                            app.set_add_channel_contents(&contents);
+                        }
+                        (AddChannelStep::Editing, _) => {
+                            app.add_channel_text_area.input(key);
+                        }
+                        (AddChannelStep::Fetching, KeyCode::Esc) => app.reject_add_channel(),
+                        (AddChannelStep::Preview(_), KeyCode::Enter | KeyCode::Char('y')) => {
+                            if let Some(channel) = app.confirm_add_channel() {
+                                if let Err(e) = app.history.save_changes(app.files.history.clone())
+                                {
+                                    error!("Unable to save history: {e}");
+                                }
+                                let queued = app.auto_download(&channel);
+                                app.info_popup_text = Some(if queued > 0 {
+                                    format!(
+                                        "Added {}, downloading {queued} episodes",
+                                        channel.title
+                                    )
+                                } else {
+                                    format!("Added {}", channel.title)
+                                });
+                                let popup_tx_clone = popup_tx.clone();
                                 tokio::spawn(async move {
                                     sleep(Duration::from_secs(POPUP_TIME)).await;
                                     popup_tx_clone.send(()).await.unwrap();
                                 });
-
-                                tokio::spawn(async move {
-                                    match load_channel(&url).await {
-                                        Ok(maybe_reloaded_channel) => {
-                                            if let Some(reloaded_channel) = maybe_reloaded_channel {
-                                                chnl_tx_clone
-                                                    .send(Ok(reloaded_channel))
-                                                    .await
-                                                    .unwrap()
-                                            }
-                                        }
-                                        Err(why) => chnl_tx_clone.send(Err(why)).await.unwrap(),
-                                    }
-                                });
                             }
                         }
-                        KeyCode::Char('v') | KeyCode::Char('V') => {
-                            if KeyModifiers::CONTROL == key.modifiers {
-                                let mut clip: ClipboardContext = ClipboardProvider::new().unwrap();
-                                let contents = clip.get_contents().unwrap();
-                                app.set_add_channel_contents(&contents);
+                        (AddChannelStep::Preview(_), KeyCode::Esc | KeyCode::Char('n')) => {
+                            app.reject_add_channel()
+                        }
+                        _ => {}
+                    },
+                    AppState::ChooseFeed => match key.code {
+                        KeyCode::Esc => app.unshow_feed_choices(),
+                        KeyCode::Char('k') | KeyCode::Up => app.select_up_feed_choices(),
+                        KeyCode::Char('j') | KeyCode::Down => app.select_down_feed_choices(),
+                        KeyCode::Enter => {
+                            if let Some(url) = app.choose_feed() {
+                                fetch_for_preview(url, add_channel_tx.clone());
                             }
                         }
+                        _ => {}
+                    },
+                    AppState::Downloads => match key.code {
+                        KeyCode::Esc
+                        | KeyCode::Char('q')
+                        | KeyCode::Char('w')
+                        | KeyCode::Char('W') => app.unshow_downloads(),
+                        KeyCode::Char('j') | KeyCode::Char('J') | KeyCode::Down => {
+                            app.select_down_downloads()
+                        }
+                        KeyCode::Char('k') | KeyCode::Char('K') | KeyCode::Up => {
+                            app.select_up_downloads()
+                        }
+                        KeyCode::Char('c') | KeyCode::Char('C') => app.cancel_selected_download(),
+                        KeyCode::Char('r') | KeyCode::Char('R') => app.retry_selected_download(),
+                        _ => {}
+                    },
+                    AppState::Health => {
+                        match key.code {
+                            KeyCode::Esc
+                            | KeyCode::Char('q')
+                            | KeyCode::Char('h')
+                            | KeyCode::Char('H') => app.unshow_health(),
+                            KeyCode::Char('j') | KeyCode::Char('J') | KeyCode::Down => {
+                                app.select_down_health()
+                            }
+                            KeyCode::Char('k') | KeyCode::Char('K') | KeyCode::Up => {
+                                app.select_up_health()
+                            }
+                            KeyCode::Char('e') | KeyCode::Char('E') => {
+                                if let Some(title) = app.enable_selected_health() {
+                                    app.info_popup_text =
+                                        Some(format!("{title} will be fetched again"));
+                                    let popup_tx_clone = popup_tx.clone();
+                                    tokio::spawn(async move {
+                                        sleep(Duration::from_secs(POPUP_TIME)).await;
+                                        popup_tx_clone.send(()).await.unwrap();
+                                    });
+                                }
+                            }
+                            KeyCode::Char('x') | KeyCode::Char('X') => {
+                                if let Some(title) = app.remove_selected_health() {
+                                    app.info_popup_text = Some(match save_into_config(app) {
+                                        Ok(()) => format!("Removed {title}"),
+                                        Err(e) => {
+                                            format!("Removed {title}, but could not save the config: {e}")
+                                        }
+                                    });
+                                    let popup_tx_clone = popup_tx.clone();
+                                    tokio::spawn(async move {
+                                        sleep(Duration::from_secs(POPUP_TIME)).await;
+                                        popup_tx_clone.send(()).await.unwrap();
+                                    });
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    AppState::Search => match key.code {
+                        KeyCode::Esc => app.unshow_search_dialog(),
+                        KeyCode::Enter => app.jump_to_selected_search_result(),
+                        KeyCode::Up => app.select_up_search_results(),
+                        KeyCode::Down => app.select_down_search_results(),
                         _ => {
-                            app.add_channel_text_area.input(key);
+                            app.search_text_area.input(key);
+                            app.update_search();
                         }
                     },
                     AppState::Running => {
+                        //any other key takes back a request to quit
+                        let quit_requested = std::mem::take(&mut app.quit_requested);
+                        if quit_requested {
+                            app.info_popup_text = None;
+                        }
                         match key.code {
                             KeyCode::Char('q') | KeyCode::Char('Q') => {
-                                app.state = AppState::Stopped;
+                                //quitting stops the downloads, so we make sure
+                                if app.downloads.is_idle() || quit_requested {
+                                    app.state = AppState::Stopped;
+                                } else {
+                                    app.quit_requested = true;
+                                    app.info_popup_text = Some(
+                                        "Downloads still running, [Q] again to quit".to_string(),
+                                    );
+                                }
                             }
                             //todo differentiate between the different selected states
                             KeyCode::Char('j') | KeyCode::Char('J') | KeyCode::Down => {
@@ -288,53 +572,119 @@ pub async fn run_app<'a, B: Backend>(term: &mut Terminal<B>, app: &mut App<'a>)
                                         sleep(Duration::from_secs(POPUP_TIME)).await;
                                         popup_tx_clone.send(()).await.unwrap();
                                     });
-                                    tokio::spawn(async move {
-                                        match load_channel(&url).await {
-                                            Ok(maybe_reloaded_channel) => {
-                                                if let Some(reloaded_channel) =
-                                                    maybe_reloaded_channel
-                                                {
-                                                    chnl_tx_clone
-                                                        .send(Ok(reloaded_channel))
-                                                        .await
-                                                        .unwrap()
-                                                }
-                                            }
-                                            Err(why) => chnl_tx_clone.send(Err(why)).await.unwrap(),
-                                        }
-                                    });
+                                    //the daemon fetches and saves for us, we just reload after
+                                    if let Some(socket) = app.daemon_socket.clone() {
+                                        let command = DaemonCommand::Refresh { channel: Some(url) };
+                                        send_to_daemon(socket, command, daemon_tx.clone());
+                                    } else if app.sync.is_some() {
+                                        //the server fetches the feeds for us
+                                        let (files, sync_tx) = (app.files.clone(), sync_tx.clone());
+                                        tokio::spawn(async move {
+                                            let _ = sync_tx.send(sync_files(&files).await).await;
+                                        });
+                                    } else {
+                                        tokio::spawn(async move {
+                                            let reloaded = match load_channel(&url).await {
+                                                Ok(Some(reloaded_channel)) => Ok(reloaded_channel),
+                                                Ok(None) => Err(anyhow!("No channel found")),
+                                                Err(why) => Err(why),
+                                            };
+                                            chnl_tx_clone.send((url, reloaded)).await.unwrap()
+                                        });
+                                    }
                                 }
                             }
                             KeyCode::Char('s') | KeyCode::Char('S') => {
                                 let popup_tx_clone = popup_tx.clone();
-                                app.info_popup_text = Some("Saving config...".to_string());
+                                app.info_popup_text = Some(match save_into_config(app) {
+                                    Ok(()) => "Saved config".to_string(),
+                                    Err(e) => format!("Unable to save the config: {e}"),
+                                });
                                 tokio::spawn(async move {
                                     sleep(Duration::from_secs(POPUP_TIME)).await;
                                     popup_tx_clone.send(()).await.unwrap();
                                 });
-                                save_into_config(app).await?;
                             }
                             KeyCode::Char('o') | KeyCode::Char('O') => {
-                                open_selected_link(app)?;
+                                if wants_player(app) {
+                                    if let Err(e) = play_selected(
+                                        term,
+                                        app,
+                                        playback_tx.clone(),
+                                        playback_done_tx.clone(),
+                                    ) {
+                                        app.error_popup_text = Some(format!("Unable to play: {e}"));
+                                    }
+                                } else {
+                                    open_selected_link(app)?;
+                                }
+                            }
+                            KeyCode::Char('p') | KeyCode::Char('P') => {
+                                match play_selected(
+                                    term,
+                                    app,
+                                    playback_tx.clone(),
+                                    playback_done_tx.clone(),
+                                ) {
+                                    Ok(true) => {}
+                                    Ok(false) => {
+                                        app.info_popup_text = Some("Nothing to play".to_string());
+                                        let popup_tx_clone = popup_tx.clone();
+                                        tokio::spawn(async move {
+                                            sleep(Duration::from_secs(POPUP_TIME)).await;
+                                            popup_tx_clone.send(()).await.unwrap();
+                                        });
+                                    }
+                                    Err(e) => {
+                                        app.error_popup_text = Some(format!("Unable to play: {e}"))
+                                    }
+                                }
                             }
                             KeyCode::Char('d') | KeyCode::Char('D') => {
-                                //let popup_tx_clone = popup_tx.clone();
-                                //we use our little count to do our dots when we download
-                                //okay we still need to work this out nicely but first we do the
-                                //error text
-                                if i == 5 {
-                                    i = 1;
-                                } else {
-                                    i += 1;
+                                if let Some(socket) = app.daemon_socket.clone() {
+                                    download_in_daemon(app, socket, daemon_tx.clone());
+                                } else if let Some(title) = download_selected(app) {
+                                    app.info_popup_text = Some(format!("Downloading '{title}'"));
+                                    let popup_tx_clone = popup_tx.clone();
+                                    tokio::spawn(async move {
+                                        sleep(Duration::from_secs(POPUP_TIME)).await;
+                                        popup_tx_clone.send(()).await.unwrap();
+                                    });
                                 }
-                                let num_dots = "i".repeat(i);
-                                info!("Downloading{}", num_dots);
-                                app.info_popup_text = Some("Downloading Pod".to_string());
-                                download_selected(app).await?;
+                            }
+                            KeyCode::Char('x') | KeyCode::Char('X') => {
+                                match app.delete_selected_download() {
+                                    Ok(Some(title)) => {
+                                        app.info_popup_text =
+                                            Some(format!("Deleted download of '{title}'"));
+                                        let popup_tx_clone = popup_tx.clone();
+                                        tokio::spawn(async move {
+                                            sleep(Duration::from_secs(POPUP_TIME)).await;
+                                            popup_tx_clone.send(()).await.unwrap();
+                                        });
+                                    }
+                                    Ok(None) => {}
+                                    Err(e) => {
+                                        app.error_popup_text =
+                                            Some(format!("Unable to delete download: {e}"))
+                                    }
+                                }
+                            }
+                            KeyCode::Char('w') | KeyCode::Char('W') => app.show_downloads(),
+                            KeyCode::Char('h') | KeyCode::Char('H') => app.show_health(),
+                            KeyCode::Char('*') => {
+                                app.toggle_selected_starred();
                             }
                             KeyCode::Char('a') | KeyCode::Char('A') => {
                                 app.show_add_channel_dialog();
                             }
+                            KeyCode::Char('/') => app.show_search_dialog(),
+                            KeyCode::Char('c') | KeyCode::Char('C') => {
+                                load_sub_pane(app, ContentView::Chapters, sub_pane_tx.clone());
+                            }
+                            KeyCode::Char('t') | KeyCode::Char('T') => {
+                                load_sub_pane(app, ContentView::Transcript, sub_pane_tx.clone());
+                            }
                             KeyCode::Tab => app.change_selected_pane(),
                             _ => {}
                         }
@@ -353,12 +703,59 @@ pub async fn run_app<'a, B: Backend>(term: &mut Terminal<B>, app: &mut App<'a>)
             }
         }
         //listen to the threads and react if we receive
-        if let Ok(maybe_received_channel) = channel_reload_rx.try_recv() {
-            match maybe_received_channel {
+        if let Ok((url, maybe_received_channel)) = channel_reload_rx.try_recv() {
+            //asked for by hand, so it counts even for a channel we were leaving alone
+            let now = Utc::now().fixed_offset();
+            match &maybe_received_channel {
                 Ok(received_channel) => {
+                    app.health.move_feed(&url, &received_channel.get_link());
+                    app.health.record_success(&received_channel.get_link(), now);
+                }
+                Err(why) => app.health.record_failure(&url, why, now),
+            }
+            if let Err(e) = app.health.save_changes(app.files.health.clone()) {
+                error!("Unable to save feed health: {e}");
+            }
+            match maybe_received_channel {
+                Ok(mut received_channel) => {
                     info!("Received reloaded channel");
-                    app.update_selected_channel(&received_channel);
-                    app.construct_items = true
+                    let new_url = received_channel.get_link();
+                    let moved = url != new_url;
+                    if moved {
+                        app.move_channel(&url, &new_url);
+                    }
+                    received_channel.items = app.history.merge(&received_channel);
+                    if let Err(e) = app.history.save_changes(app.files.history.clone()) {
+                        error!("Unable to save history: {e}");
+                    }
+                    //both get said, one popup should not hide the other
+                    let mut notices = Vec::new();
+                    if let Some(channel) = app.update_channel(&url, &received_channel) {
+                        app.construct_items = true;
+                        if moved {
+                            //the config should follow it to its new home
+                            notices.push(match save_into_config(app) {
+                                Ok(()) => format!("{} has moved to {new_url}", channel.title),
+                                Err(e) => format!(
+                                    "{} has moved to {new_url}, but the config still has the old url: {e}",
+                                    channel.title
+                                ),
+                            });
+                        }
+                        let queued = app.auto_download(&channel);
+                        if queued > 0 {
+                            notices.push(format!("Downloading {queued} new episodes"));
+                        }
+                        app.prune_downloads();
+                    }
+                    if !notices.is_empty() {
+                        app.info_popup_text = Some(notices.join(". "));
+                        let popup_tx_clone = popup_tx.clone();
+                        tokio::spawn(async move {
+                            sleep(Duration::from_secs(POPUP_TIME)).await;
+                            popup_tx_clone.send(()).await.unwrap();
+                        });
+                    }
                 }
                 Err(why) => {
                     let popup_tx_clone = popup_tx.clone();
@@ -370,6 +767,66 @@ pub async fn run_app<'a, B: Backend>(term: &mut Terminal<B>, app: &mut App<'a>)
                 }
             }
         };
+        let finished = app.downloads.poll();
+        if !finished.is_empty() {
+            app.record_downloads(&finished);
+            app.prune_downloads();
+        }
+        while let Ok((command, reply)) = daemon_rx.try_recv() {
+            match reply {
+                Ok(reply) => {
+                    app.record_daemon_downloads(&reply.downloads);
+                    if reply.ok && matches!(command, DaemonCommand::Refresh { .. }) {
+                        if let Err(e) = app.reload_stores() {
+                            error!("Unable to reload what the daemon fetched: {e}");
+                        }
+                    }
+                    if !reply.ok {
+                        app.error_popup_text = Some(reply.message);
+                    } else if command != DaemonCommand::Status {
+                        app.info_popup_text = Some(reply.message);
+                        let popup_tx_clone = popup_tx.clone();
+                        tokio::spawn(async move {
+                            sleep(Duration::from_secs(POPUP_TIME)).await;
+                            popup_tx_clone.send(()).await.unwrap();
+                        });
+                    }
+                }
+                Err(e) => {
+                    //it went away, so we download ourselves from here on
+                    app.daemon_socket = None;
+                    app.error_popup_text = Some(format!("Lost the daemon: {e}"));
+                }
+            }
+        }
+        if let Ok(synced) = sync_rx.try_recv() {
+            match synced.and_then(|record| app.reload_stores().map(|()| record)) {
+                Ok(record) => {
+                    app.info_popup_text = Some(format!(
+                        "Synced {} items with the server",
+                        record.items_pulled + record.items_pushed
+                    ));
+                    let popup_tx_clone = popup_tx.clone();
+                    tokio::spawn(async move {
+                        sleep(Duration::from_secs(POPUP_TIME)).await;
+                        popup_tx_clone.send(()).await.unwrap();
+                    });
+                }
+                Err(e) => app.error_popup_text = Some(format!("Unable to sync: {e}")),
+            }
+        }
+        if let Ok((url, fetched)) = add_channel_rx.try_recv() {
+            app.add_channel_fetched(&url, fetched);
+        }
+        while let Ok(update) = playback_rx.try_recv() {
+            app.record_playback(update);
+        }
+        if let Ok(()) = playback_done_rx.try_recv() {
+            app.finish_playback();
+        }
+        if let Ok((item_id, view, text)) = sub_pane_rx.try_recv() {
+            app.set_sub_pane_text(item_id, view, text);
+        }
         //we suppress weird little errors here
 
         if let Ok(()) = popup_rx.try_recv() {
@@ -378,18 +835,50 @@ pub async fn run_app<'a, B: Backend>(term: &mut Terminal<B>, app: &mut App<'a>)
 
         {
             if app.state == AppState::Stopped {
+                app.finish_playback();
                 return Ok(());
             }
         }
     }
 }
 
+///Show the chapters or transcript of the selected item, fetching them if we have not yet
+fn load_sub_pane(
+    app: &mut App,
+    view: ContentView,
+    tx: mpsc::Sender<(String, ContentView, String)>,
+) {
+    let Some(item_id) = app.toggle_content_view(view) else {
+        return;
+    };
+    let Some(item) = app.get_selected_item().cloned() else {
+        return;
+    };
+    tokio::spawn(async move {
+        let loaded = match view {
+            ContentView::Chapters => match item.get_chapters() {
+                Some(chapters) => load_chapters(&chapters.url).await,
+                None => Ok("No chapters".to_string()),
+            },
+            _ => match item.transcript.as_deref().and_then(pick_transcript) {
+                Some(transcript) => load_transcript(transcript).await,
+                None => Ok("No transcript".to_string()),
+            },
+        };
+        let text = loaded.unwrap_or_else(|e| format!("ERROR! {e}"));
+        tx.send((item_id, view, text)).await.unwrap();
+    });
+}
+
 ///Open the selected rss feed item in the browser
 pub fn open_selected_link(app: &App) -> Result<()> {
     info!("Called open link...");
     if let Some(item) = app.get_selected_item() {
         info!("Found selected item");
-        if let Some(path) = item.link.clone() {
+        if let Some(path) = app.downloaded_path(item) {
+            info!("Found local copy to be opened {}", path.display());
+            open::that(path)?
+        } else if let Some(path) = item.get_link() {
             info!("Found link to be opened {path}");
             open::that(path)?
         } else if let Some(enclosure) = item.enclosure.clone() {
@@ -403,49 +892,127 @@ pub fn open_selected_link(app: &App) -> Result<()> {
     Ok(())
 }
 
-///Download the selected item to a folder locally
-pub async fn download_selected<'a>(app: &mut App<'a>) -> Result<()> {
-    //get the url
-    //let (popup_tx, popup_rx) = mpsc::channel(1);
-    if let Some(item) = app.get_selected_item() {
-        if let Some(enclosure) = &item.enclosure {
-            let url = &enclosure.url;
-            let pod = reqwest::get(url).await?;
-            //check the status
-            let status = pod.status();
-            if status.is_success() {
-                //get the file name from the path
-                let pod_title = url.split('/').last().unwrap_or("unknown_title.mp3");
-                //now we get rid of everything after and including a ?
-                let truncated_title = truncate_query_params(pod_title);
-                let mut dload_file = File::create(truncated_title)?;
-                let mut bytes = Cursor::new(pod.bytes().await?);
-                copy(&mut bytes, &mut dload_file)?
-            } else {
-                let text = pod.text().await?;
-                let err_msg = format!(
-                    "Unable to download pod? status={}, message={}",
-                    status, text
-                );
-                error!("{}", err_msg);
-                return Err(anyhow!(err_msg));
-            };
-        }
-    } //conclude
-    Ok(())
+///Audio and video we would rather hand to the player than to whatever opens links,
+///which is when we have it downloaded or there is no link to open instead
+fn wants_player(app: &App) -> bool {
+    app.get_selected_item().is_some_and(|item| {
+        item.enclosure.as_ref().is_some_and(is_media)
+            && (app.downloaded_path(item).is_some() || item.get_link().is_none())
+    })
 }
 
-///get rid of everything after the last 3, so if we have 'some_pod_cast.mp3?weird-stuff-here',
-///we should get back 'some_pod_cast.mp3'
-fn truncate_query_params(pod_title: &str) -> &str {
-    //this will mess up with unicode, probably, but, eh, cannot be bothered rn
-    //also should probably check if it is an mp3 and etc and blah blah...actually, i can strip
-    //everything after and including the ? and not bother with mp3! Okay let us do it
-    if let Some(ridx) = pod_title.rfind(|x| x == '?') {
-        &pod_title[0..ridx]
-    } else {
-        pod_title
+///Play the selected item's enclosure, the local copy if we have one.
+///Returns false if there was nothing to play
+///If the player has an ipc socket, we follow along with where it gets to,
+///and say so on `playback_done_tx` when it goes away
+fn play_selected<B: Backend>(
+    term: &mut Terminal<B>,
+    app: &App,
+    playback_tx: mpsc::UnboundedSender<PlaybackUpdate>,
+    playback_done_tx: mpsc::UnboundedSender<()>,
+) -> Result<bool> {
+    let Some(item) = app.get_selected_item() else {
+        return Ok(false);
+    };
+    let Some(enclosure) = item
+        .enclosure
+        .as_ref()
+        .filter(|enclosure| is_media(enclosure))
+    else {
+        return Ok(false);
+    };
+    let file_or_url = match app.downloaded_path(item) {
+        Some(path) => path.display().to_string(),
+        None => enclosure.url.clone(),
+    };
+    let id = item.id();
+    let pos = app.item_states.resume_position(&id);
+    let socket = ipc_socket_path();
+    let command = app.player.command_for(
+        &file_or_url,
+        pos,
+        &item.get_title(),
+        &socket.display().to_string(),
+    )?;
+    if app.player.uses_ipc() {
+        tokio::spawn(async move {
+            if let Err(e) = track_position(socket.clone(), id, playback_tx).await {
+                error!("Lost track of the player: {e}");
+            }
+            let _ = tokio::fs::remove_file(socket).await;
+            let _ = playback_done_tx.send(());
+        });
+    }
+    match app.player.mode {
+        PlayerMode::Detached => command.spawn_detached()?,
+        PlayerMode::Suspend => {
+            restore_terminal()?;
+            let status = command.run();
+            resume_terminal(term)?;
+            let status = status?;
+            if !status.success() {
+                info!("Player exited with {status}");
+            }
+        }
     }
+    Ok(true)
+}
+
+///Queue the enclosure of the selected item for download to a folder locally.
+///Returns the title of the item, if there was anything to download
+pub fn download_selected(app: &mut App) -> Option<String> {
+    let channel = app.get_selected_channel()?;
+    let item = app.get_selected_item()?;
+    let request = app.download_config.download_request(channel, item)?;
+    let title = request.title.clone();
+    app.downloads.enqueue(request);
+    Some(title)
+}
+
+///Hand the selected item's download to the daemon, its reply comes back on the channel
+fn download_in_daemon(
+    app: &App,
+    socket: PathBuf,
+    tx: mpsc::Sender<(DaemonCommand, Result<DaemonReply>)>,
+) {
+    let Some(item) = app.get_selected_item() else {
+        return;
+    };
+    send_to_daemon(socket, DaemonCommand::Download { item: item.id() }, tx);
+}
+
+///Send the command to the daemon without waiting, its reply comes back on the channel
+fn send_to_daemon(
+    socket: PathBuf,
+    command: DaemonCommand,
+    tx: mpsc::Sender<(DaemonCommand, Result<DaemonReply>)>,
+) {
+    tokio::spawn(async move {
+        let reply = send(&socket, &command).await;
+        let _ = tx.send((command, reply)).await;
+    });
+}
+
+///Ask the daemon how its downloads are going every so often, until it or we go away
+fn watch_daemon(socket: PathBuf, tx: mpsc::Sender<(DaemonCommand, Result<DaemonReply>)>) {
+    tokio::spawn(async move {
+        loop {
+            sleep(DAEMON_POLL).await;
+            let reply = send(&socket, &DaemonCommand::Status).await;
+            let lost = reply.is_err();
+            if tx.send((DaemonCommand::Status, reply)).await.is_err() || lost {
+                return;
+            }
+        }
+    });
+}
+
+///Fetch what they want to add, which may turn out to be a web page with feeds on it
+fn fetch_for_preview(url: String, tx: mpsc::Sender<(String, Result<Discovered>)>) {
+    tokio::spawn(async move {
+        let fetched = discover_feed(&url).await;
+        tx.send((url, fetched)).await.unwrap();
+    });
 }
 
 pub async fn load_channel(url: &str) -> Result<Option<Channel>> {
@@ -456,31 +1023,182 @@ pub async fn load_channel(url: &str) -> Result<Option<Channel>> {
     }
 }
 
-pub async fn save_into_config<'a>(app: &mut App<'a>) -> Result<()> {
-    app.info_popup_text = Some("Saving config...".to_string());
+///Write the channels and settings to the config, saying why not if we cannot
+pub fn save_into_config(app: &App) -> Result<()> {
     let mut channels = HashMap::new();
     for channel in app.channels.channels.clone() {
         channels.insert(channel.title.clone(), channel.get_link().clone());
     }
-    let cfg = RssConfig { channels };
-    //TODO make this take in a file path
-    thread::spawn(|| save_config(None, cfg));
-    Ok(())
+    let cfg = RssConfig {
+        channels,
+        display: app.display.clone(),
+        downloads: app.download_config.clone(),
+        player: app.player.clone(),
+        folders: app.folders.clone(),
+        sync: app.sync.clone(),
+    };
+    save_config(app.files.config.clone(), cfg)
 }
 
 fn show_add_channel_dialog(f: &mut Frame, app: &mut App) {
+    let dialog_color = Color::Rgb(147, 204, 234);
+    let title = match app.add_channel_step {
+        AddChannelStep::Editing => "Add Channel",
+        AddChannelStep::Fetching => "Add Channel - fetching, esc to stop waiting",
+        AddChannelStep::Preview(_) => "Add Channel - enter to add, esc to go back",
+    };
     let add_channel_block = Block::new()
-        .style(Style::default().fg(Color::Rgb(147, 204, 234)))
-        .title("Add Channel")
+        .style(Style::default().fg(dialog_color))
+        .title(title)
         .borders(Borders::all())
         .border_type(BorderType::Rounded)
-        .border_style(Style::default().fg(Color::Rgb(147, 204, 234)));
+        .border_style(Style::default().fg(dialog_color));
+
+    if let AddChannelStep::Preview(channel) = &app.add_channel_step {
+        let rectangle = centered_rect(80, 40, f.size());
+        let preview = Paragraph::new(channel_preview_lines(app, channel))
+            .block(add_channel_block)
+            .wrap(Wrap { trim: true });
+        f.render_widget(Clear, rectangle);
+        f.render_widget(preview, rectangle);
+        return;
+    }
 
-    let rectangle = centered_rect(80, 10, f.size());
+    let rectangle = centered_rect(80, 20, f.size());
+    let [url_pane, error_pane] =
+        Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(rectangle);
     let mut add_channel_txt_field = app.add_channel_text_area.clone();
     add_channel_txt_field.set_block(add_channel_block);
     f.render_widget(Clear, rectangle);
-    f.render_widget(add_channel_txt_field.widget(), rectangle);
+    f.render_widget(add_channel_txt_field.widget(), url_pane);
+    if let Some(error) = &app.add_channel_error {
+        let error_paragraph = Paragraph::new(error.as_str())
+            .style(Style::default().fg(Color::Red))
+            .wrap(Wrap { trim: true });
+        f.render_widget(error_paragraph, error_pane);
+    }
+}
+
+///What we know of a feed before it is added: title, description, items and when it last had one
+fn channel_preview_lines<'a>(app: &App, channel: &Channel) -> Vec<Line<'a>> {
+    let label_style = Style::default().fg(DATE_COLOR);
+    let field = |label: &str, value: String| {
+        Line::from(vec![
+            Span::styled(format!("{label:<13}"), label_style),
+            Span::raw(value),
+        ])
+    };
+    let last_updated = channel
+        .items
+        .iter()
+        .filter_map(|item| item.published())
+        .max()
+        .map(|published| app.display.format_timestamp(&published))
+        .or(channel.pub_date.clone())
+        .unwrap_or("unknown".to_string());
+    let mut lines = vec![
+        Line::styled(
+            channel.title.clone(),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        field("Feed", channel.get_link()),
+        field("Items", channel.items.len().to_string()),
+        field("Last updated", last_updated),
+    ];
+    let description = strip_html(&channel.description);
+    if !description.trim().is_empty() {
+        lines.push(Line::raw(""));
+        lines.push(Line::raw(description.trim().to_string()));
+    }
+    lines
+}
+
+///Search box on top, everything it matched below
+fn show_search_dialog(f: &mut Frame, app: &mut App) {
+    let search_color = Color::Rgb(147, 204, 234);
+    let rectangle = centered_rect(80, 60, f.size());
+    let [search_pane, results_pane] =
+        Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(rectangle);
+
+    let search_block = Block::new()
+        .style(Style::default().fg(search_color))
+        .title("Search all channels")
+        .borders(Borders::all())
+        .border_type(BorderType::Rounded);
+    let mut search_txt_field = app.search_text_area.clone();
+    search_txt_field.set_block(search_block);
+
+    let results_block = Block::new()
+        .style(Style::default().fg(TEXT_COLOR))
+        .title(format!("Results ({})", app.search_results.hits.len()))
+        .borders(Borders::all())
+        .border_type(BorderType::Rounded);
+    let results: Vec<ListItem> = app
+        .search_results
+        .hits
+        .iter()
+        .map(|hit| {
+            ListItem::new(Line::from(vec![
+                Span::raw(hit.item.get_title()),
+                Span::styled(
+                    format!("  {}", hit.channel_title),
+                    Style::default().fg(search_color),
+                ),
+                Span::styled(
+                    format!(
+                        "  {}",
+                        hit.item
+                            .published()
+                            .map(|dt| app.display.format_list_date(&dt))
+                            .unwrap_or_default()
+                    ),
+                    Style::default().fg(DATE_COLOR),
+                ),
+            ]))
+        })
+        .collect();
+    let results_list = List::new(results)
+        .block(results_block)
+        .highlight_symbol(">")
+        .highlight_style(
+            Style::default()
+                .bg(ALT_ROW_COLOR)
+                .fg(SELECTED_STYLE_FG)
+                .add_modifier(Modifier::BOLD),
+        );
+
+    f.render_widget(Clear, rectangle);
+    f.render_widget(search_txt_field.widget(), search_pane);
+    f.render_stateful_widget(results_list, results_pane, &mut app.search_results.state);
+}
+
+fn show_feed_choices_dialog(f: &mut Frame, app: &mut App) {
+    let rectangle = centered_rect(80, 40, f.size());
+    let choices_block = Block::new()
+        .style(Style::default().fg(TEXT_COLOR))
+        .title(format!(
+            "Feeds found at {} - enter to add, esc to cancel",
+            app.feed_choices.page_url
+        ))
+        .borders(Borders::all())
+        .border_type(BorderType::Rounded);
+    let choices: Vec<ListItem> = app
+        .feed_choices
+        .choices
+        .iter()
+        .map(|choice| ListItem::new(choice.label()))
+        .collect();
+    let choices_list = List::new(choices)
+        .block(choices_block)
+        .highlight_symbol(">")
+        .highlight_style(
+            Style::default()
+                .bg(ALT_ROW_COLOR)
+                .fg(SELECTED_STYLE_FG)
+                .add_modifier(Modifier::BOLD),
+        );
+    f.render_widget(Clear, rectangle);
+    f.render_stateful_widget(choices_list, rectangle, &mut app.feed_choices.state);
 }
 
 ///Display an info popup with the given text
@@ -512,20 +1230,172 @@ pub fn show_error_popup(txt: &str, f: &mut Frame) {
     f.render_widget(popup_paragraph, centered_pane);
 }
 
-///When we are downloading a podcast we want to
-pub fn show_downloading_pod_popup(pod_title: &str, f: &mut Frame) {
-    let msg_str = format!("Downloading '{}'", pod_title);
+///When we are downloading podcasts we want to see how they are getting on,
+///so each download gets its title and a progress bar
+pub fn show_downloads_popup(f: &mut Frame, app: &App) {
     let popup_block = Block::new()
-        .style(Style::default().fg(tailwind::AMBER.c900))
+        .title("Downloads  [C]ancel | [R]etry | [Esc] close")
+        .style(Style::default().fg(tailwind::AMBER.c300))
         .borders(Borders::all())
         .border_type(BorderType::Double)
         .border_style(Style::default().fg(tailwind::AMBER.c900));
-    let popup_paragraph = Paragraph::new(msg_str)
-        .block(popup_block)
-        .wrap(Wrap { trim: true });
-    let centered_pane = centered_rect(80, 20, f.size());
+    let centered_pane = centered_rect(80, 60, f.size());
+    let inner = popup_block.inner(centered_pane);
     f.render_widget(Clear, centered_pane);
-    f.render_widget(popup_paragraph, centered_pane);
+    f.render_widget(popup_block, centered_pane);
+
+    let downloads = &app.downloads.downloads;
+    if downloads.is_empty() {
+        f.render_widget(Paragraph::new("Nothing downloading"), inner);
+        return;
+    }
+    //two rows to a download, scrolled so we can see the selected one
+    let rows_that_fit = (inner.height / 2).max(1) as usize;
+    let selected = app.selected_download.unwrap_or(0);
+    let first = (selected + 1).saturating_sub(rows_that_fit);
+    for (row, (idx, download)) in downloads
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(rows_that_fit)
+        .enumerate()
+    {
+        let y = inner.y + row as u16 * 2;
+        if y + 1 >= inner.y + inner.height {
+            break;
+        }
+        let title_area = Rect::new(inner.x, y, inner.width, 1);
+        let gauge_area = Rect::new(inner.x, y + 1, inner.width, 1);
+
+        let title_style = if idx == selected {
+            Style::default()
+                .bg(ALT_ROW_COLOR)
+                .fg(SELECTED_STYLE_FG)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        let status = match &download.status {
+            DownloadStatus::Queued => "Queued".to_string(),
+            DownloadStatus::Running => "Downloading".to_string(),
+            DownloadStatus::Finished => "Done".to_string(),
+            DownloadStatus::Failed(reason) => format!("Failed: {reason}"),
+            DownloadStatus::Cancelled => "Cancelled".to_string(),
+        };
+        let title = Line::from(vec![
+            Span::styled(download.request.title.clone(), title_style),
+            Span::styled(format!("  {status}"), Style::default().fg(DATE_COLOR)),
+        ]);
+        f.render_widget(Paragraph::new(title), title_area);
+
+        let gauge = LineGauge::default()
+            .ratio(download.ratio().unwrap_or(0.0))
+            .label(download_progress_label(download))
+            .filled_style(Style::default().fg(tailwind::AMBER.c500))
+            .unfilled_style(Style::default().fg(tailwind::SLATE.c700));
+        f.render_widget(gauge, gauge_area);
+    }
+}
+
+///The channels that are failing or gone, and the latest failures of the selected one,
+///so we can tell which to give another go and which to prune
+pub fn show_health_popup(f: &mut Frame, app: &App) {
+    let popup_block = Block::new()
+        .title("Feed health  [E]nable | [X] remove | [Esc] close")
+        .style(Style::default().fg(Color::Red))
+        .borders(Borders::all())
+        .border_type(BorderType::Double)
+        .border_style(Style::default().fg(Color::Red).bold());
+    let centered_pane = centered_rect(80, 60, f.size());
+    let inner = popup_block.inner(centered_pane);
+    f.render_widget(Clear, centered_pane);
+    f.render_widget(popup_block, centered_pane);
+
+    let broken = app.broken_channels();
+    if broken.is_empty() {
+        f.render_widget(Paragraph::new("Every channel is fetching fine"), inner);
+        return;
+    }
+    let now = Utc::now().fixed_offset();
+    let [list_area, history_area] =
+        Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(inner);
+    let broken_items: Vec<ListItem> = broken
+        .iter()
+        .map(|(channel, health)| {
+            let last_success = health
+                .last_success
+                .map(|at| app.display.format_list_date(&at))
+                .unwrap_or("never".to_string());
+            ListItem::new(format!(
+                "{}  {}, {} failures, last worked {last_success}",
+                channel.title,
+                health.status(now),
+                health.consecutive_failures
+            ))
+        })
+        .collect();
+    let mut list_state = ListState::default().with_selected(app.selected_health);
+    let broken_list = List::new(broken_items)
+        .highlight_symbol(">")
+        .highlight_style(
+            Style::default()
+                .bg(ALT_ROW_COLOR)
+                .fg(SELECTED_STYLE_FG)
+                .add_modifier(Modifier::BOLD),
+        );
+    f.render_stateful_widget(broken_list, list_area, &mut list_state);
+
+    let Some((channel, health)) = app.selected_health.and_then(|idx| broken.get(idx)) else {
+        return;
+    };
+    let mut lines = vec![Line::from(channel.get_link()).fg(DATE_COLOR)];
+    if let Some(next_attempt) = health
+        .next_attempt()
+        .filter(|next| !health.disabled && *next > now)
+    {
+        lines.push(Line::from(format!(
+            "Trying again {}",
+            app.display.format_timestamp(&next_attempt)
+        )));
+    }
+    //newest first
+    for failure in health.failures.iter().rev() {
+        lines.push(Line::from(vec![
+            Span::styled(
+                app.display.format_timestamp(&failure.at),
+                Style::default().fg(DATE_COLOR),
+            ),
+            Span::raw(format!("  {}", failure.error)),
+        ]));
+    }
+    let history = Paragraph::new(lines)
+        .block(Block::new().borders(Borders::TOP))
+        .wrap(Wrap { trim: true });
+    f.render_widget(history, history_area);
+}
+
+///e.g. 45% 23.5 MB of 52.3 MB, 1.2 MB/s, 0:24 left
+fn download_progress_label(download: &Download) -> String {
+    let mut parts = Vec::new();
+    let done = match (download.ratio(), download.total) {
+        (Some(ratio), Some(total)) => format!(
+            "{:>3.0}% {} of {}",
+            ratio * 100.0,
+            format_size(download.downloaded),
+            format_size(total)
+        ),
+        _ => format_size(download.downloaded),
+    };
+    parts.push(done);
+    if download.status == DownloadStatus::Running {
+        if let Some(speed) = download.speed() {
+            parts.push(format!("{}/s", format_size(speed as u64)));
+        }
+        if let Some(eta) = download.eta() {
+            parts.push(format!("{} left", format_duration(eta.as_secs())));
+        }
+    }
+    parts.join(", ")
 }
 
 ///Get an area that is centered'ish - with horizontal and vertical bias
@@ -554,19 +1424,3 @@ fn centered_rect(h: u16, v: u16, rect: Rect) -> Rect {
     )
     .split(layout[1])[1]
 }
-
-#[cfg(test)]
-mod test {
-    use super::truncate_query_params;
-
-    #[test]
-    pub fn test_truncate_query_string() {
-        let file_name = "FoaBD-106-Trouble-at-Grogs.mp3?dest-id=549775";
-        let truncated_file_name = truncate_query_params(file_name);
-        assert_eq!("FoaBD-106-Trouble-at-Grogs.mp3", truncated_file_name);
-
-        let file_name_without_suffix = "i_am_mp3_2.mp3";
-        let truncated_file_name_2 = truncate_query_params(file_name_without_suffix);
-        assert_eq!(file_name_without_suffix, truncated_file_name_2);
-    }
-}